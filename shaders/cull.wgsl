@@ -0,0 +1,74 @@
+// Frustum culling and level of detail selection per chunk, mirrors culling.rs.
+// Writes one DrawIndexedIndirect per chunk and level, with 0 instances for levels that aren't drawn,
+// and counts the chunks that are drawn
+
+struct Cull {
+    planes: array<vec4<f32>, 6>,
+    eye: vec4<f32>,
+    lod_distances: vec4<f32>,
+    chunk_count: u32,
+    index_count: u32
+}
+
+struct ChunkInfo {
+    min: vec4<f32>,
+    max: vec4<f32>,
+    counts: vec4<u32> // instances per level of detail
+}
+
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32
+}
+
+const LOD_LEVELS: u32 = 3u;
+
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+@group(0) @binding(1)
+var<storage, read> chunks: array<ChunkInfo>;
+@group(0) @binding(2)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+@group(0) @binding(3)
+var<storage, read_write> drawn: atomic<u32>;
+
+fn intersects(min: vec3<f32>, max: vec3<f32>) -> bool {
+    for (var i = 0; i < 6; i++) {
+        let plane = cull.planes[i];
+        let corner = select(min, max, plane.xyz >= vec3<f32>(0.0));
+        if dot(plane.xyz, corner) + plane.w < 0.0 {
+            return false;
+        }
+    }
+    return true;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= cull.chunk_count {
+        return;
+    }
+
+    let chunk = chunks[index];
+    let visible = intersects(chunk.min.xyz, chunk.max.xyz);
+
+    let distance = length(cull.eye.xyz - clamp(cull.eye.xyz, chunk.min.xyz, chunk.max.xyz));
+    let lod = u32(distance >= cull.lod_distances.x) + u32(distance >= cull.lod_distances.y);
+    if visible && chunk.counts[lod] > 0u {
+        atomicAdd(&drawn, 1u);
+    }
+
+    for (var level = 0u; level < LOD_LEVELS; level++) {
+        var draw: DrawIndexedIndirect;
+        draw.index_count = cull.index_count;
+        draw.instance_count = select(0u, chunk.counts[level], visible && level == lod);
+        draw.first_index = 0u;
+        draw.base_vertex = 0;
+        draw.first_instance = 0u;
+        draws[index * LOD_LEVELS + level] = draw;
+    }
+}
@@ -0,0 +1,32 @@
+// Flat coloured quads in window pixels, used for the text and panels of the heads up display
+
+struct Screen {
+    size: vec2<f32>
+}
+
+@group(0) @binding(0)
+var<uniform> screen: Screen;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>, // pixels from the top left corner
+    @location(1) color: vec4<f32>
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    let ndc = in.position / screen.size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
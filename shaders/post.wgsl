@@ -0,0 +1,154 @@
+// Post-processing after the scene passes: ambient occlusion, ink outlines, transparency and tone mapping.
+// Both passes draw a single triangle covering the screen and read the scene targets with textureLoad
+
+struct Post {
+    proj: mat4x4<f32>, // camera projection, to go between view space and the screen
+    size: vec2<f32>, // target size in pixels
+    ssao_radius: f32, // world units
+    exposure: f32,
+    outline_color: vec4<f32>,
+    flags: u32 // SSAO | OUTLINE | TONEMAP
+}
+
+const SSAO: u32 = 1u;
+const OUTLINE: u32 = 2u;
+const TONEMAP: u32 = 4u;
+const SSAO_SAMPLES: u32 = 16u;
+
+@group(0) @binding(0)
+var<uniform> post: Post;
+@group(0) @binding(1)
+var t_color: texture_2d<f32>;
+@group(0) @binding(2)
+var t_normal_depth: texture_2d<f32>; // view space normal, linear depth in w (0 where nothing was drawn)
+@group(0) @binding(3)
+var t_ao: texture_2d<f32>;
+@group(0) @binding(4)
+var t_accum: texture_2d<f32>; // weighted premultiplied colours of the transparent voxels, and their weights in w
+@group(0) @binding(5)
+var t_revealage: texture_2d<f32>; // how much of the opaque scene still shows through them
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>
+}
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn load_normal_depth(pixel: vec2<i32>) -> vec4<f32> {
+    let clamped = clamp(pixel, vec2<i32>(0), vec2<i32>(post.size) - vec2<i32>(1));
+    return textureLoad(t_normal_depth, clamped, 0);
+}
+
+fn view_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    return vec3<f32>(ndc.x * depth / post.proj[0][0], ndc.y * depth / post.proj[1][1], -depth);
+}
+
+// Cheap integer hash, good enough to rotate the sample kernel per pixel
+fn hash(seed: u32) -> u32 {
+    var x = seed;
+    x ^= x >> 16u;
+    x *= 0x7feb352du;
+    x ^= x >> 15u;
+    x *= 0x846ca68bu;
+    x ^= x >> 16u;
+    return x;
+}
+
+fn random(seed: u32) -> f32 {
+    return f32(hash(seed) & 0xffffu) / 65535.0;
+}
+
+@fragment
+fn fs_ssao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let center = load_normal_depth(pixel);
+    if center.w <= 0.0 {
+        return vec4<f32>(1.0);
+    }
+
+    let normal = normalize(center.xyz);
+    let position = view_position(in.uv, center.w);
+    let seed = u32(pixel.x) * 1973u + u32(pixel.y) * 9277u;
+
+    var occlusion = 0.0;
+    for (var i = 0u; i < SSAO_SAMPLES; i++) {
+        // random point in the hemisphere around the normal, denser close to the surface
+        let s = seed + i * 3u;
+        var direction = normalize(vec3<f32>(random(s), random(s + 1u), random(s + 2u)) * 2.0 - vec3<f32>(1.0));
+        direction *= sign(dot(direction, normal) + 1e-4);
+        let scale = mix(0.1, 1.0, pow(f32(i + 1u) / f32(SSAO_SAMPLES), 2.0));
+        let point = position + direction * post.ssao_radius * scale;
+
+        let clip = post.proj * vec4<f32>(point, 1.0);
+        let uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5);
+        let scene_depth = load_normal_depth(vec2<i32>(uv * post.size)).w;
+
+        // the sample is hidden when the scene is in front of it, ignoring geometry far in front
+        let range = smoothstep(0.0, 1.0, post.ssao_radius / max(abs(center.w - scene_depth), 1e-4));
+        occlusion += select(0.0, range, scene_depth > 0.0 && scene_depth < -point.z - 0.02);
+    }
+    return vec4<f32>(1.0 - occlusion / f32(SSAO_SAMPLES));
+}
+
+// 3x3 box blur hiding the per pixel noise of the kernel rotation
+fn blurred_ao(pixel: vec2<i32>) -> f32 {
+    var sum = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let p = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), vec2<i32>(post.size) - vec2<i32>(1));
+            sum += textureLoad(t_ao, p, 0).r;
+        }
+    }
+    return sum / 9.0;
+}
+
+// 1 on depth jumps and creases between faces, 0 elsewhere
+fn edge(pixel: vec2<i32>) -> f32 {
+    let center = load_normal_depth(pixel);
+    var offsets = array<vec2<i32>, 4>(vec2<i32>(1, 0), vec2<i32>(-1, 0), vec2<i32>(0, 1), vec2<i32>(0, -1));
+    var result = 0.0;
+    for (var i = 0; i < 4; i++) {
+        let neighbour = load_normal_depth(pixel + offsets[i]);
+        // only the nearer side draws the line, so silhouettes stay one pixel wide
+        let depth_edge = center.w > 0.0 && (neighbour.w <= 0.0 || neighbour.w - center.w > 0.02 * center.w + 0.5);
+        let normal_edge = center.w > 0.0 && neighbour.w > 0.0 && dot(center.xyz, neighbour.xyz) < 0.5 && center.w <= neighbour.w;
+        result = max(result, f32(depth_edge || normal_edge));
+    }
+    return result;
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    var color = textureLoad(t_color, pixel, 0).rgb;
+
+    if (post.flags & SSAO) != 0u {
+        color *= blurred_ao(pixel);
+    }
+    if (post.flags & OUTLINE) != 0u {
+        color = mix(color, post.outline_color.rgb, edge(pixel) * post.outline_color.a);
+    }
+
+    // resolve the transparent layers over the opaque scene
+    let accum = textureLoad(t_accum, pixel, 0);
+    let revealage = textureLoad(t_revealage, pixel, 0).r;
+    color = mix(accum.rgb / max(accum.a, 1e-5), color, revealage);
+    if (post.flags & TONEMAP) != 0u {
+        color = aces(color * post.exposure);
+    }
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,280 @@
+// x (10 bits) | y (10 bits) | z (10 bits) | lod (2 bits), palette index (8 bits) | visible faces (6 bits)
+struct Instance {
+    @location(2) data: vec2<u32>
+};
+
+
+struct Camera {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>
+}
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct Light {
+    view_proj: mat4x4<f32>,
+    direction: vec4<f32>, // towards the light
+    ambient: f32,
+    shadows: u32,
+    normal_offset: f32
+}
+
+@group(1) @binding(1)
+var<uniform> light: Light;
+
+struct Atlas {
+    tile_scale: vec2<f32>,
+    tile_stride: vec2<f32>,
+    padding: vec2<f32>,
+    columns: u32
+}
+
+@group(0) @binding(2)
+var<uniform> atlas: Atlas;
+
+const PI: f32 = 3.14159265;
+
+// marks palette entries that do not sample the atlas
+const NO_TILE: u32 = 0xffffffffu;
+
+struct PaletteEntry {
+    color: vec4<f32>, // alpha is the opacity, entries below 1 are drawn in the transparent pass
+    tile: u32,
+    emission: f32,
+    roughness: f32,
+    metalness: f32
+}
+
+@group(0) @binding(3)
+var<uniform> palette: array<PaletteEntry, 256>;
+
+struct Chunk {
+    model: mat4x4<f32>, // placement of the object the chunk belongs to
+    origin: vec4<f32> // in the object, w is 0 for the model, 1 and 2 for onion skins of the previous and the next frame
+}
+
+@group(2) @binding(0)
+var<uniform> chunk: Chunk;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) @interpolate(flat) textured: u32,
+    @location(3) face_uv: vec2<f32>,
+    @location(4) world_position: vec3<f32>,
+    @location(5) normal: vec3<f32>,
+    @location(6) view_normal: vec3<f32>,
+    @location(7) view_depth: f32,
+    @location(8) @interpolate(flat) material: vec3<f32> // emission, roughness, metalness
+};
+
+// The scene colour, and the view space normal and linear depth read by the post-processing passes
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) normal_depth: vec4<f32>
+}
+
+// Outward normals of the cube faces, in the order of the vertices in voxel.rs, four vertices per face
+var<private> FACE_NORMALS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>(0.0, 0.0, 1.0),
+    vec3<f32>(0.0, 0.0, -1.0),
+    vec3<f32>(1.0, 0.0, 0.0),
+    vec3<f32>(-1.0, 0.0, 0.0),
+    vec3<f32>(0.0, 1.0, 0.0),
+    vec3<f32>(0.0, -1.0, 0.0)
+);
+
+// Unpack the instance, a cube of 2^lod voxels centered on its cells
+fn world_position(model: VertexInput, instance: Instance) -> vec3<f32> {
+    let local = vec3<u32>(instance.data.x, instance.data.x >> 10u, instance.data.x >> 20u) & vec3<u32>(1023u);
+    let scale = f32(1u << (instance.data.x >> 30u));
+    let center = vec3<f32>(local) + vec3<f32>((scale - 1.0) / 2.0);
+    // corners are exact in chunk space, so neighbouring cubes share them bit for bit and leave no cracks
+    return (chunk.model * vec4<f32>(chunk.origin.xyz + (model.position * scale + center), 1.0)).xyz;
+}
+
+// Which voxels a pass draws, opaque and see-through ones go to different passes
+const DRAW_OPAQUE: u32 = 1u;
+const DRAW_TRANSPARENT: u32 = 2u;
+
+// Faces covered by a neighbouring voxel, and voxels of the other pass, collapse onto a point outside the
+// clip volume so they rasterise nothing. Cheaper than discarding their fragments
+fn keep(clip_position: vec4<f32>, visible: bool) -> vec4<f32> {
+    return select(vec4<f32>(0.0, 0.0, -2.0, 1.0), clip_position, visible);
+}
+
+fn face_visible(instance: Instance, vertex_index: u32) -> bool {
+    return ((instance.data.y >> (8u + vertex_index / 4u)) & 1u) == 1u;
+}
+
+// Onion skins are faint and tinted, red for the previous frame and blue for the next, so they never hide the model
+const ONION_OPACITY: f32 = 0.25;
+
+fn onion_skin(color: vec4<f32>) -> vec4<f32> {
+    let tint = select(vec3<f32>(0.3, 0.5, 1.0), vec3<f32>(1.0, 0.3, 0.3), chunk.origin.w < 1.5);
+    return vec4<f32>(mix(color.rgb, tint, 0.5), ONION_OPACITY);
+}
+
+fn vertex(model: VertexInput, instance: Instance, vertex_index: u32, draw: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let entry = palette[instance.data.y & 255u];
+    let world = world_position(model, instance);
+    let color = select(entry.color, onion_skin(entry.color), chunk.origin.w > 0.0);
+    let kind = select(DRAW_TRANSPARENT, DRAW_OPAQUE, color.a >= 1.0);
+
+    out.color = color;
+    out.material = vec3<f32>(entry.emission, entry.roughness, entry.metalness);
+    out.clip_position = keep(camera.view_proj * vec4<f32>(world, 1.0), face_visible(instance, vertex_index) && (draw & kind) != 0u);
+    out.world_position = world;
+    out.normal = normalize((chunk.model * vec4<f32>(FACE_NORMALS[vertex_index / 4u], 0.0)).xyz);
+    out.view_normal = (camera.view * vec4<f32>(out.normal, 0.0)).xyz;
+    out.view_depth = -(camera.view * vec4<f32>(world, 1.0)).z;
+
+    // map the face uv into the tile of this instance
+    out.textured = u32(entry.tile != NO_TILE);
+    let tile = vec2<f32>(f32(entry.tile % atlas.columns), f32(entry.tile / atlas.columns));
+    out.uv = tile * atlas.tile_stride + atlas.padding + model.uv * atlas.tile_scale;
+    out.face_uv = model.uv;
+    return out;
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: Instance, @builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    return vertex(model, instance, vertex_index, DRAW_OPAQUE);
+}
+
+@vertex
+fn vs_transparent(model: VertexInput, instance: Instance, @builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    return vertex(model, instance, vertex_index, DRAW_TRANSPARENT);
+}
+
+// Wireframes show every voxel
+@vertex
+fn vs_all(model: VertexInput, instance: Instance, @builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    return vertex(model, instance, vertex_index, DRAW_OPAQUE | DRAW_TRANSPARENT);
+}
+
+
+// Depth only pass from the light's point of view
+@vertex
+fn vs_shadow(model: VertexInput, instance: Instance, @builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    return keep(light.view_proj * vec4<f32>(world_position(model, instance), 1.0), face_visible(instance, vertex_index));
+}
+
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+@group(1) @binding(2)
+var t_shadow: texture_depth_2d;
+@group(1) @binding(3)
+var s_shadow: sampler_comparison;
+
+// Fraction of light reaching a surface, 3x3 pcf on top of the sampler's own bilinear comparison
+fn shadow(world: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if light.shadows == 0u {
+        return 1.0;
+    }
+    let clip = light.view_proj * vec4<f32>(world + normal * light.normal_offset, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5);
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+fn output(in: VertexOutput, color: vec4<f32>) -> FragmentOutput {
+    return FragmentOutput(color, vec4<f32>(normalize(in.view_normal), in.view_depth));
+}
+
+fn camera_position() -> vec3<f32> {
+    let rotation = mat3x3<f32>(camera.view[0].xyz, camera.view[1].xyz, camera.view[2].xyz);
+    return -(transpose(rotation) * camera.view[3].xyz);
+}
+
+// Lambert diffuse and a normalised Blinn-Phong highlight from the light, plus ambient and emission. Metals have
+// no diffuse part and tint the highlight with their colour
+fn shade(in: VertexOutput, albedo: vec4<f32>) -> vec4<f32> {
+    let emission = in.material.x;
+    let roughness = in.material.y;
+    let metalness = in.material.z;
+
+    let to_light = light.direction.xyz;
+    let lit = max(dot(in.normal, to_light), 0.0) * shadow(in.world_position, in.normal);
+    // ambient light also stands in for what metals would reflect of their surroundings
+    let diffuse = albedo.rgb * (light.ambient + (1.0 - light.ambient) * lit * (1.0 - metalness));
+
+    let half_vector = normalize(to_light + normalize(camera_position() - in.world_position));
+    let shininess = exp2(10.0 * (1.0 - roughness) + 1.0);
+    let highlight = pow(max(dot(in.normal, half_vector), 0.0), shininess) * (shininess + 8.0) / (8.0 * PI);
+    let specular = mix(vec3<f32>(0.04), albedo.rgb, metalness) * highlight * lit;
+
+    return vec4<f32>(diffuse + specular + albedo.rgb * emission, albedo.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    // sampling has to happen in uniform control flow, so always sample and pick afterwards
+    let texel = textureSample(t_diffuse, s_diffuse, in.uv);
+    let albedo = in.color * select(vec4<f32>(1.0), texel, in.textured == 1u);
+    return output(in, vec4<f32>(shade(in, albedo).rgb, 1.0));
+}
+
+struct TransparentOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: vec4<f32>
+}
+
+// See-through entries, weighted by their depth so nearer layers dominate whatever order they arrive in
+@fragment
+fn fs_transparent(in: VertexOutput) -> TransparentOutput {
+    let texel = textureSample(t_diffuse, s_diffuse, in.uv);
+    let albedo = in.color * select(vec4<f32>(1.0), texel, in.textured == 1u);
+    let color = shade(in, albedo);
+
+    // the view depth variant of the weight function, window depth is too compressed by the far plane to tell layers apart
+    let z = in.view_depth;
+    let weight = color.a * clamp(10.0 / (1e-5 + pow(z / 5.0, 2.0) + pow(z / 200.0, 6.0)), 1e-2, 3e3);
+    return TransparentOutput(vec4<f32>(color.rgb * color.a, color.a) * weight, vec4<f32>(color.a));
+}
+
+// Distance to the nearest face edge in pixels, derived from the face uv. Works both for
+// line rasterisation and for filled triangles, and hides the diagonal of every face.
+// fwidth is passed in because derivatives only exist in fragment entry points
+fn edge_distance(face_uv: vec2<f32>, width: vec2<f32>) -> f32 {
+    let distance = min(face_uv, vec2<f32>(1.0) - face_uv) / max(width, vec2<f32>(1e-6));
+    return min(distance.x, distance.y);
+}
+
+@fragment
+fn fs_wireframe(in: VertexOutput) -> FragmentOutput {
+    if edge_distance(in.face_uv, fwidth(in.face_uv)) > 1.0 {
+        discard;
+    }
+    return output(in, in.color);
+}
+
+@fragment
+fn fs_overlay(in: VertexOutput) -> FragmentOutput {
+    if edge_distance(in.face_uv, fwidth(in.face_uv)) > 1.0 {
+        discard;
+    }
+    return output(in, vec4<f32>(0.05, 0.05, 0.05, 1.0));
+}
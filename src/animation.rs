@@ -0,0 +1,238 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
+use cgmath::{Point3, Vector3};
+use image::RgbaImage;
+use crate::camera::Camera;
+
+// Where the camera is and what it looks at in one frame of an animation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraPose {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub fov: f32, // vertical, in degrees
+}
+
+impl CameraPose {
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye;
+        camera.target = self.target;
+        camera.fov = self.fov;
+    }
+}
+
+impl From<&Camera> for CameraPose {
+    fn from(camera: &Camera) -> Self {
+        Self {eye: camera.eye, target: camera.target, fov: camera.fov}
+    }
+}
+
+// `frames` poses circling the camera once around its target, the last one a step short of the first so the loop is seamless
+pub fn turntable(camera: &Camera, frames: u32) -> Vec<CameraPose> {
+    let mut camera: Camera = Camera {controller: None, ..*camera};
+    (0..frames).map(|_| {
+        let pose: CameraPose = CameraPose::from(&camera);
+        camera.turn(360.0 / frames as f32);
+        pose
+    }).collect()
+}
+
+// A camera pose at a point in time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraKey {
+    pub time: f32, // seconds from the start
+    pub pose: CameraPose,
+}
+
+// Camera poses at increasing times, smoothly interpolated between with Catmull-Rom splines
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraPath {
+    pub keys: Vec<CameraKey>,
+}
+
+// Tangent at key `i` of a spline through `values` at `times`, one-sided at the ends
+fn tangent<T>(times: &[f32], values: &[T], i: usize) -> T where T: Copy + std::ops::Sub<Output = T> + std::ops::Div<f32, Output = T> {
+    let (before, after): (usize, usize) = (i.saturating_sub(1), (i + 1).min(values.len() - 1));
+    (values[after] - values[before]) / (times[after] - times[before]).max(f32::EPSILON)
+}
+
+// Cubic hermite interpolation from `a` to `b` over an interval of length `dt`, at fraction `s`
+fn hermite<T>(a: T, b: T, ta: T, tb: T, dt: f32, s: f32) -> T where T: Copy + std::ops::Add<Output = T> + std::ops::Mul<f32, Output = T> {
+    let (s2, s3): (f32, f32) = (s * s, s * s * s);
+    a * (2.0 * s3 - 3.0 * s2 + 1.0) + ta * ((s3 - 2.0 * s2 + s) * dt) + b * (-2.0 * s3 + 3.0 * s2) + tb * ((s3 - s2) * dt)
+}
+
+impl CameraPath {
+    // One key per line as `seconds eye_x,eye_y,eye_z target_x,target_y,target_z [fov]`, `;` starts a comment.
+    // Keys without a field of view keep the previous key's, 45 degrees for the first
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut keys: Vec<CameraKey> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line: &str = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let key = || -> Result<CameraKey> {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let (time, eye, target, fov) = match fields[..] {
+                    [time, eye, target] => (time, eye, target, None),
+                    [time, eye, target, fov] => (time, eye, target, Some(fov)),
+                    _ => bail!("expected `seconds eye target [fov]`")
+                };
+                let point = |text: &str| -> Result<Point3<f32>> {
+                    let coordinates: Vec<f32> = text.split(',').map(|c| c.parse::<f32>()).collect::<Result<_, _>>().map_err(|_| anyhow!("invalid position {:?}", text))?;
+                    match coordinates[..] {
+                        [x, y, z] => Ok(Point3::new(x, y, z)),
+                        _ => bail!("invalid position {:?}, expected x,y,z", text)
+                    }
+                };
+                let fov: f32 = match fov {
+                    Some(fov) => fov.parse().ok().filter(|fov| (1.0..180.0).contains(fov)).ok_or_else(|| anyhow!("invalid field of view {:?}", fov))?,
+                    None => keys.last().map_or(45.0, |key| key.pose.fov)
+                };
+                Ok(CameraKey {time: time.parse().map_err(|_| anyhow!("invalid time {:?}", time))?, pose: CameraPose {eye: point(eye)?, target: point(target)?, fov}})
+            };
+            let key: CameraKey = key().with_context(|| format!("line {}", number + 1))?;
+            if keys.last().is_some_and(|last| key.time <= last.time) {
+                bail!("line {}: keys have to be in order of time", number + 1);
+            }
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            bail!("the camera path has no keys");
+        }
+        Ok(Self {keys})
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text: String = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    // Time of the last key
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0.0, |key| key.time)
+    }
+
+    // The pose at `time`, held at the first and last key outside of the path
+    pub fn sample(&self, time: f32) -> CameraPose {
+        let Some(next) = self.keys.iter().position(|key| key.time > time) else {return self.keys[self.keys.len() - 1].pose};
+        if next == 0 {
+            return self.keys[0].pose;
+        }
+        let i: usize = next - 1;
+        let times: Vec<f32> = self.keys.iter().map(|key| key.time).collect();
+        let eyes: Vec<Vector3<f32>> = self.keys.iter().map(|key| key.pose.eye - Point3::new(0.0, 0.0, 0.0)).collect();
+        let targets: Vec<Vector3<f32>> = self.keys.iter().map(|key| key.pose.target - Point3::new(0.0, 0.0, 0.0)).collect();
+        let fovs: Vec<f32> = self.keys.iter().map(|key| key.pose.fov).collect();
+
+        let dt: f32 = times[next] - times[i];
+        let s: f32 = (time - times[i]) / dt;
+        let spline = |values: &[Vector3<f32>]| Point3::new(0.0, 0.0, 0.0) + hermite(values[i], values[next], tangent(&times, values, i), tangent(&times, values, next), dt, s);
+        CameraPose {eye: spline(&eyes), target: spline(&targets), fov: hermite(fovs[i], fovs[next], tangent(&times, &fovs, i), tangent(&times, &fovs, next), dt, s)}
+    }
+
+    // A pose per frame from time 0 to the last key at `fps` frames per second
+    pub fn frames(&self, fps: f32) -> Vec<CameraPose> {
+        let count: u32 = (self.duration() * fps).floor() as u32 + 1;
+        (0..count).map(|frame| self.sample(frame as f32 / fps)).collect()
+    }
+}
+
+// Writes rendered frames as numbered png files next to each other, or as one animated png
+pub enum FrameWriter {
+    Sequence {directory: PathBuf, stem: String, extension: String},
+    Animated(png::Writer<BufWriter<File>>),
+}
+
+impl FrameWriter {
+    // An .apng path gets an animated png looping forever, any other image path a file per frame with
+    // the frame number added to its name: out.png becomes out_0000.png, out_0001.png and so on
+    pub fn create(path: &Path, frames: u32, fps: f32, width: u32, height: u32) -> Result<Self> {
+        let extension: String = path.extension().and_then(|e| e.to_str()).unwrap_or("png").to_lowercase();
+        if extension != "apng" {
+            let stem: String = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("frame").to_string();
+            let directory: PathBuf = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            return Ok(FrameWriter::Sequence {directory, stem, extension});
+        }
+
+        let file: File = File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut encoder: png::Encoder<BufWriter<File>> = png::Encoder::new(BufWriter::new(file), width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames, 0)?;
+        // frames last numerator / denominator seconds, hundredths keep fractional frame rates
+        encoder.set_frame_delay(100, (fps * 100.0).round().clamp(1.0, u16::MAX as f32) as u16)?;
+        Ok(FrameWriter::Animated(encoder.write_header().with_context(|| format!("failed to write {}", path.display()))?))
+    }
+
+    // Frames have to be written in order
+    pub fn write(&mut self, frame: u32, image: &RgbaImage) -> Result<()> {
+        match self {
+            FrameWriter::Sequence {directory, stem, extension} => {
+                let path: PathBuf = directory.join(format!("{}_{:04}.{}", stem, frame, extension));
+                image.save(&path).with_context(|| format!("failed to write {}", path.display()))
+            }
+            FrameWriter::Animated(writer) => writer.write_image_data(image.as_raw()).context("failed to write an animation frame")
+        }
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self {
+            FrameWriter::Sequence {..} => Ok(()),
+            FrameWriter::Animated(writer) => writer.finish().context("failed to finish the animation")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{MetricSpace, Point3, Vector3};
+    use image::{Rgba, RgbaImage};
+    use crate::animation::{turntable, CameraPath, CameraPose, FrameWriter};
+    use crate::camera::{Camera, CameraUniform};
+
+    #[test]
+    fn test_camera_path_goes_through_its_keys() {
+        let path: CameraPath = CameraPath::parse("; time eye target fov\n0 10,0,0 0,0,0 40\n1 0,0,10 0,0,0\n3 -10,0,0 0,1,0 60\n").unwrap();
+        assert_eq!(path.duration(), 3.0);
+        assert_eq!(path.keys[1].pose.fov, 40.0);
+        assert_eq!(path.sample(-1.0), path.keys[0].pose);
+        assert_eq!(path.sample(1.0), path.keys[1].pose);
+        assert_eq!(path.sample(5.0), path.keys[2].pose);
+
+        // smooth through the middle key, not a straight line between the keys
+        let (before, after): (CameraPose, CameraPose) = (path.sample(0.99), path.sample(1.01));
+        assert!(before.eye.distance(after.eye) < 0.5);
+        assert!(path.sample(0.5).eye.distance(Point3::new(5.0, 0.0, 5.0)) > 0.1);
+        assert!(path.sample(2.0).fov > 40.0 && path.sample(2.0).fov < 60.0);
+        assert_eq!(path.frames(2.0).len(), 7);
+
+        assert!(CameraPath::parse("").is_err());
+        assert!(CameraPath::parse("1 0,0,0 0,0,0\n0 0,0,0 0,0,0").is_err());
+        assert!(CameraPath::parse("0 0,0 0,0,0").is_err());
+    }
+
+    #[test]
+    fn test_turntable_and_animated_png() {
+        let camera: Camera = Camera {eye: Point3::new(10.0, 5.0, 0.0), target: Point3::new(0.0, 0.0, 0.0), up: Vector3::unit_y(), aspect: 1.0, fov: 45.0, near: 0.1, far: 100.0, uniform: CameraUniform::new(), controller: None};
+        let poses: Vec<CameraPose> = turntable(&camera, 4);
+        assert_eq!(poses.len(), 4);
+        assert_eq!(poses[0].eye, camera.eye);
+        assert!(poses[2].eye.distance(Point3::new(-10.0, 5.0, 0.0)) < 1e-4);
+
+        let path = std::env::temp_dir().join(format!("voxelart-test-{}.apng", std::process::id()));
+        let mut writer: FrameWriter = FrameWriter::create(&path, 2, 12.0, 4, 3).unwrap();
+        for frame in 0..2 {
+            writer.write(frame, &RgbaImage::from_pixel(4, 3, Rgba([frame as u8 * 100, 0, 0, 255]))).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!((animation.num_frames, animation.num_plays), (2, 0));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
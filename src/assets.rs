@@ -0,0 +1,144 @@
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use image::RgbaImage;
+use crate::atlas::TextureAtlas;
+use crate::palette::Palette;
+
+// Environment variable that overrides the asset directory
+pub const ASSET_DIR_VAR: &str = "VOXELART_ASSETS";
+
+// Copies compiled into the binary, used whenever a file is missing from the asset directory
+const EMBEDDED: &[(&str, &[u8])] = &[
+    ("textures/img.png", include_bytes!("../textures/img.png")),
+    ("shaders/shader.wgsl", include_bytes!("../shaders/shader.wgsl")),
+    ("shaders/cull.wgsl", include_bytes!("../shaders/cull.wgsl")),
+    ("shaders/hud.wgsl", include_bytes!("../shaders/hud.wgsl")),
+    ("shaders/post.wgsl", include_bytes!("../shaders/post.wgsl")),
+];
+
+// Resolves textures, palettes and shaders from an asset directory at runtime
+#[derive(Clone, Debug)]
+pub struct Assets {
+    pub root: PathBuf,
+}
+
+impl Assets {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {root: root.into()}
+    }
+
+    // Use $VOXELART_ASSETS if set, the working directory otherwise
+    pub fn from_env() -> Self {
+        Self::new(env::var_os(ASSET_DIR_VAR).map(PathBuf::from).unwrap_or_else(|| PathBuf::from(".")))
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    // Read an asset from disk, falling back to the embedded copy when the file does not exist
+    pub fn load_bytes(&self, name: &str) -> Result<Cow<'static, [u8]>> {
+        let path: PathBuf = self.path(name);
+        match fs::read(&path) {
+            Ok(bytes) => Ok(Cow::Owned(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => match embedded(name) {
+                Some(bytes) => {
+                    log::info!("{} not found, using embedded copy", path.display());
+                    Ok(Cow::Borrowed(bytes))
+                }
+                None => Err(anyhow!("asset {} not found and no embedded copy exists", path.display()))
+            },
+            Err(e) => Err(e).with_context(|| format!("failed to read asset {}", path.display()))
+        }
+    }
+
+    pub fn load_texture(&self, name: &str) -> Result<RgbaImage> {
+        let path: String = format!("textures/{}", name);
+        let bytes = self.load_bytes(&path)?;
+        Ok(image::load_from_memory(&bytes).with_context(|| format!("failed to decode texture {}", path))?.to_rgba8())
+    }
+
+    // A texture laid out as an atlas. The tile size is read from a file next to it with the extension `.tile`,
+    // e.g. `textures/img.tile` containing `16x16`. Without one the whole texture is a single tile
+    pub fn load_atlas(&self, name: &str, padding: u32) -> Result<(TextureAtlas, RgbaImage)> {
+        let image: RgbaImage = self.load_texture(name)?;
+        let path: PathBuf = self.path(&format!("textures/{}", name)).with_extension("tile");
+        let tile_size: (u32, u32) = match fs::read_to_string(&path) {
+            Ok(text) => TextureAtlas::parse_tile_size(&text).with_context(|| format!("failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => image.dimensions(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display()))
+        };
+        TextureAtlas::build(&image, tile_size, padding).with_context(|| format!("failed to build an atlas from texture {}", name))
+    }
+
+    pub fn load_shader(&self, name: &str) -> Result<String> {
+        let path: String = format!("shaders/{}", name);
+        let bytes = self.load_bytes(&path)?;
+        String::from_utf8(bytes.into_owned()).with_context(|| format!("shader {} is not valid utf-8", path))
+    }
+
+    // Palettes are either images (one entry per pixel) or text files, see `Palette::parse`.
+    // Without a palette file the built in default palette is used
+    pub fn load_palette(&self, name: &str) -> Result<Palette> {
+        let path: PathBuf = self.path(&format!("palettes/{}", name));
+        if !path.exists() {
+            log::info!("{} not found, using default palette", path.display());
+            return Ok(Palette::default());
+        }
+
+        let bytes: Vec<u8> = fs::read(&path).with_context(|| format!("failed to read palette {}", path.display()))?;
+        match Path::new(name).extension().and_then(|e| e.to_str()) {
+            Some("png") | Some("jpg") | Some("jpeg") => Ok(Palette::from_image(&image::load_from_memory(&bytes).with_context(|| format!("failed to decode palette {}", path.display()))?.to_rgba8())),
+            _ => Palette::parse(&String::from_utf8_lossy(&bytes)).with_context(|| format!("failed to parse palette {}", path.display()))
+        }
+    }
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+fn embedded(name: &str) -> Option<&'static [u8]> {
+    EMBEDDED.iter().find(|(n, _)| *n == name).map(|(_, bytes)| *bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::assets::Assets;
+    use crate::palette::Palette;
+
+    #[test]
+    fn test_embedded_fallback() {
+        let assets: Assets = Assets::new(std::env::temp_dir().join("voxelart-missing-assets"));
+
+        assert!(assets.load_shader("shader.wgsl").unwrap().contains("fn vs_main"));
+        assert_eq!(assets.load_texture("img.png").unwrap().dimensions(), (1024, 576));
+        assert_eq!(assets.load_atlas("img.png", 4).unwrap().0.tile_count(), 1);
+        assert_eq!(assets.load_palette("default.txt").unwrap(), Palette::default());
+        assert!(assets.load_shader("missing.wgsl").is_err());
+    }
+
+    #[test]
+    fn test_load_from_disk() {
+        let root = std::env::temp_dir().join(format!("voxelart-assets-{}", std::process::id()));
+        fs::create_dir_all(root.join("palettes")).unwrap();
+        fs::create_dir_all(root.join("textures")).unwrap();
+        fs::write(root.join("palettes/test.txt"), "ff0000\n00ff00 1\n").unwrap();
+        fs::write(root.join("textures/broken.png"), "not a png").unwrap();
+        image::RgbaImage::new(32, 16).save(root.join("textures/tiles.png")).unwrap();
+        fs::write(root.join("textures/tiles.tile"), "8x8\n").unwrap();
+
+        let assets: Assets = Assets::new(&root);
+        assert_eq!(assets.load_palette("test.txt").unwrap().len(), 2);
+        assert!(assets.load_texture("broken.png").is_err());
+        assert_eq!(assets.load_atlas("tiles.png", 1).unwrap().0.tile_count(), 8);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
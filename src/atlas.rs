@@ -0,0 +1,144 @@
+use anyhow::{bail, Context, Result};
+use image::{GenericImageView, RgbaImage};
+
+// Layout of a texture atlas: a grid of equally sized tiles, each surrounded by a gutter of
+// `padding` pixels that repeats the tile's edge texels so neighbouring tiles never bleed in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureAtlas {
+    pub tile_size: (u32, u32),
+    pub padding: u32,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AtlasUniform {
+    pub tile_scale: [f32; 2], // size of a single tile in uv space
+    pub tile_stride: [f32; 2], // distance between two tile origins in uv space (tile + gutters)
+    pub padding: [f32; 2], // size of one gutter in uv space
+    pub columns: u32,
+    pub _pad: u32,
+} // wrapper for atlas information used by the shader
+
+impl TextureAtlas {
+    // Split `source` into a grid of `tile_size` tiles and lay them out again with gutters
+    pub fn build(source: &RgbaImage, tile_size: (u32, u32), padding: u32) -> Result<(Self, RgbaImage)> {
+        if source.width() == 0 || source.height() == 0 {
+            bail!("texture is empty");
+        }
+        if tile_size.0 == 0 || tile_size.1 == 0 {
+            bail!("tile size {}x{} is empty", tile_size.0, tile_size.1);
+        }
+        let columns: u32 = (source.width() / tile_size.0).max(1);
+        let rows: u32 = (source.height() / tile_size.1).max(1);
+        let atlas: Self = Self {tile_size, padding, columns, rows};
+
+        let (width, height) = atlas.dimensions();
+        let mut image: RgbaImage = RgbaImage::new(width, height);
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let tile = source.view(column * tile_size.0, row * tile_size.1, tile_size.0.min(source.width()), tile_size.1.min(source.height()));
+                let origin: (u32, u32) = atlas.tile_origin(row * columns + column);
+
+                // copy the tile, clamping lookups so the gutter repeats the edge texels
+                for y in 0..tile_size.1 + 2 * padding {
+                    for x in 0..tile_size.0 + 2 * padding {
+                        let sx: u32 = x.saturating_sub(padding).min(tile.width() - 1);
+                        let sy: u32 = y.saturating_sub(padding).min(tile.height() - 1);
+                        image.put_pixel(origin.0 - padding + x, origin.1 - padding + y, tile.get_pixel(sx, sy));
+                    }
+                }
+            }
+        }
+
+        Ok((atlas, image))
+    }
+
+    // A tile size written as `WIDTHxHEIGHT`, e.g. `16x16`
+    pub fn parse_tile_size(text: &str) -> Result<(u32, u32)> {
+        let text: &str = text.trim();
+        let (width, height) = text.split_once('x').with_context(|| format!("invalid tile size {:?}, expected WIDTHxHEIGHT", text))?;
+        let parse = |value: &str| value.parse::<u32>().with_context(|| format!("invalid tile size {:?}, expected WIDTHxHEIGHT", text));
+        Ok((parse(width)?, parse(height)?))
+    }
+
+    // Total size of the atlas image in pixels
+    pub fn dimensions(&self) -> (u32, u32) {
+        ((self.tile_size.0 + 2 * self.padding) * self.columns, (self.tile_size.1 + 2 * self.padding) * self.rows)
+    }
+
+    pub fn tile_count(&self) -> u32 {
+        self.columns * self.rows
+    }
+
+    // Top left pixel of a tile, excluding its gutter
+    pub fn tile_origin(&self, tile: u32) -> (u32, u32) {
+        let (column, row) = (tile % self.columns, tile / self.columns);
+        ((self.tile_size.0 + 2 * self.padding) * column + self.padding, (self.tile_size.1 + 2 * self.padding) * row + self.padding)
+    }
+
+    // uv rectangle of a tile as [u, v, width, height]
+    pub fn tile_uv_rect(&self, tile: u32) -> [f32; 4] {
+        let (width, height) = self.dimensions();
+        let origin: (u32, u32) = self.tile_origin(tile);
+        [origin.0 as f32 / width as f32, origin.1 as f32 / height as f32, self.tile_size.0 as f32 / width as f32, self.tile_size.1 as f32 / height as f32]
+    }
+
+    pub fn uniform(&self) -> AtlasUniform {
+        let (width, height) = self.dimensions();
+        AtlasUniform {
+            tile_scale: [self.tile_size.0 as f32 / width as f32, self.tile_size.1 as f32 / height as f32],
+            tile_stride: [(self.tile_size.0 + 2 * self.padding) as f32 / width as f32, (self.tile_size.1 + 2 * self.padding) as f32 / height as f32],
+            padding: [self.padding as f32 / width as f32, self.padding as f32 / height as f32],
+            columns: self.columns,
+            _pad: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use crate::atlas::TextureAtlas;
+
+    #[test]
+    fn test_atlas_gutters_repeat_edges() {
+        // two 2x2 tiles side by side, red and blue
+        let source: RgbaImage = RgbaImage::from_fn(4, 2, |x, _| if x < 2 {Rgba([255, 0, 0, 255])} else {Rgba([0, 0, 255, 255])});
+        let (atlas, image) = TextureAtlas::build(&source, (2, 2), 1).unwrap();
+
+        assert_eq!(atlas.tile_count(), 2);
+        assert_eq!(image.dimensions(), (8, 4));
+
+        // the right gutter of the red tile and the left gutter of the blue tile touch
+        assert_eq!(*image.get_pixel(3, 1), Rgba([255, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(4, 1), Rgba([0, 0, 255, 255]));
+        assert_eq!(*image.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_atlas_from_tile_size() {
+        let source: RgbaImage = RgbaImage::new(64, 32);
+        let (atlas, _) = TextureAtlas::build(&source, TextureAtlas::parse_tile_size("16x16\n").unwrap(), 4).unwrap();
+        assert_eq!((atlas.columns, atlas.rows, atlas.tile_count()), (4, 2, 8));
+
+        assert!(TextureAtlas::parse_tile_size("16").is_err());
+        assert!(TextureAtlas::parse_tile_size("16xa").is_err());
+        assert!(TextureAtlas::build(&source, (0, 16), 4).is_err());
+        assert!(TextureAtlas::build(&RgbaImage::new(0, 0), (16, 16), 4).is_err());
+    }
+
+    #[test]
+    fn test_tile_uv_rect() {
+        let atlas: TextureAtlas = TextureAtlas {tile_size: (16, 16), padding: 2, columns: 4, rows: 2};
+        let rect: [f32; 4] = atlas.tile_uv_rect(5);
+
+        assert_eq!(atlas.dimensions(), (80, 40));
+        assert_eq!(rect, [22.0 / 80.0, 22.0 / 40.0, 16.0 / 80.0, 16.0 / 40.0]);
+
+        let uniform = atlas.uniform();
+        assert!((uniform.tile_stride[0] + uniform.padding[0] - rect[0]).abs() < 1e-6);
+    }
+}
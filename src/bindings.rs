@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
+use winit::event::{ElementState, Event, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent};
+
+// Everything input can make the editor do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Orbit, // held while dragging
+    Pan, // held while dragging
+    UseTool, // apply the tool selected in the toolbar
+    Place,
+    Erase,
+    Paint,
+    Pick,
+    Undo,
+    Redo,
+    ToggleWireframe,
+    ToggleStats,
+    CycleMsaa,
+    ToggleSsao,
+    ToggleOutline,
+    ToggleTonemap,
+    PathTrace,
+    ToggleGui,
+    Turntable,
+    Play, // start and stop the animation
+    NextFrame,
+    PreviousFrame,
+    ToggleOnionSkin,
+    Save,
+    TranslateGizmo, // show and hide the gizmo handles for moving
+    RotateGizmo,
+    ScaleGizmo,
+    ToggleGizmoVoxels, // switch the gizmo between the selected object and the voxels of the active layer
+}
+
+impl Action {
+    pub const ALL: [Action; 27] = [
+        Action::Orbit, Action::Pan, Action::UseTool, Action::Place, Action::Erase, Action::Paint, Action::Pick, Action::Undo, Action::Redo,
+        Action::ToggleWireframe, Action::ToggleStats, Action::CycleMsaa, Action::ToggleSsao, Action::ToggleOutline, Action::ToggleTonemap, Action::PathTrace, Action::ToggleGui, Action::Turntable,
+        Action::Play, Action::NextFrame, Action::PreviousFrame, Action::ToggleOnionSkin, Action::Save,
+        Action::TranslateGizmo, Action::RotateGizmo, Action::ScaleGizmo, Action::ToggleGizmoVoxels
+    ];
+
+    // Name used in the bindings file
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Orbit => "orbit",
+            Action::Pan => "pan",
+            Action::UseTool => "use_tool",
+            Action::Place => "place",
+            Action::Erase => "erase",
+            Action::Paint => "paint",
+            Action::Pick => "pick",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::ToggleWireframe => "toggle_wireframe",
+            Action::ToggleStats => "toggle_stats",
+            Action::CycleMsaa => "cycle_msaa",
+            Action::ToggleSsao => "toggle_ssao",
+            Action::ToggleOutline => "toggle_outline",
+            Action::ToggleTonemap => "toggle_tonemap",
+            Action::PathTrace => "path_trace",
+            Action::ToggleGui => "toggle_gui",
+            Action::Turntable => "turntable",
+            Action::Play => "play",
+            Action::NextFrame => "next_frame",
+            Action::PreviousFrame => "previous_frame",
+            Action::ToggleOnionSkin => "toggle_onion_skin",
+            Action::Save => "save",
+            Action::TranslateGizmo => "translate_gizmo",
+            Action::RotateGizmo => "rotate_gizmo",
+            Action::ScaleGizmo => "scale_gizmo",
+            Action::ToggleGizmoVoxels => "toggle_gizmo_voxels"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+// A key or mouse button that can start an action
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+// Keys by their name in the bindings file
+const KEYS: [(&str, VirtualKeyCode); 70] = [
+    ("a", VirtualKeyCode::A), ("b", VirtualKeyCode::B), ("c", VirtualKeyCode::C), ("d", VirtualKeyCode::D), ("e", VirtualKeyCode::E),
+    ("f", VirtualKeyCode::F), ("g", VirtualKeyCode::G), ("h", VirtualKeyCode::H), ("i", VirtualKeyCode::I), ("j", VirtualKeyCode::J),
+    ("k", VirtualKeyCode::K), ("l", VirtualKeyCode::L), ("m", VirtualKeyCode::M), ("n", VirtualKeyCode::N), ("o", VirtualKeyCode::O),
+    ("p", VirtualKeyCode::P), ("q", VirtualKeyCode::Q), ("r", VirtualKeyCode::R), ("s", VirtualKeyCode::S), ("t", VirtualKeyCode::T),
+    ("u", VirtualKeyCode::U), ("v", VirtualKeyCode::V), ("w", VirtualKeyCode::W), ("x", VirtualKeyCode::X), ("y", VirtualKeyCode::Y),
+    ("z", VirtualKeyCode::Z),
+    ("0", VirtualKeyCode::Key0), ("1", VirtualKeyCode::Key1), ("2", VirtualKeyCode::Key2), ("3", VirtualKeyCode::Key3), ("4", VirtualKeyCode::Key4),
+    ("5", VirtualKeyCode::Key5), ("6", VirtualKeyCode::Key6), ("7", VirtualKeyCode::Key7), ("8", VirtualKeyCode::Key8), ("9", VirtualKeyCode::Key9),
+    ("f1", VirtualKeyCode::F1), ("f2", VirtualKeyCode::F2), ("f3", VirtualKeyCode::F3), ("f4", VirtualKeyCode::F4), ("f5", VirtualKeyCode::F5),
+    ("f6", VirtualKeyCode::F6), ("f7", VirtualKeyCode::F7), ("f8", VirtualKeyCode::F8), ("f9", VirtualKeyCode::F9), ("f10", VirtualKeyCode::F10),
+    ("f11", VirtualKeyCode::F11), ("f12", VirtualKeyCode::F12),
+    ("escape", VirtualKeyCode::Escape), ("tab", VirtualKeyCode::Tab), ("space", VirtualKeyCode::Space), ("enter", VirtualKeyCode::Return),
+    ("backspace", VirtualKeyCode::Back), ("delete", VirtualKeyCode::Delete), ("insert", VirtualKeyCode::Insert), ("home", VirtualKeyCode::Home),
+    ("end", VirtualKeyCode::End), ("pageup", VirtualKeyCode::PageUp), ("pagedown", VirtualKeyCode::PageDown),
+    ("left", VirtualKeyCode::Left), ("right", VirtualKeyCode::Right), ("up", VirtualKeyCode::Up), ("down", VirtualKeyCode::Down),
+    ("minus", VirtualKeyCode::Minus), ("equals", VirtualKeyCode::Equals), ("comma", VirtualKeyCode::Comma), ("period", VirtualKeyCode::Period),
+    ("slash", VirtualKeyCode::Slash), ("lbracket", VirtualKeyCode::LBracket), ("rbracket", VirtualKeyCode::RBracket)
+];
+
+const MODIFIERS: [(&str, ModifiersState); 4] = [("ctrl", ModifiersState::CTRL), ("shift", ModifiersState::SHIFT), ("alt", ModifiersState::ALT), ("logo", ModifiersState::LOGO)];
+
+// Modifier keys only change bindings, they are never triggers on their own
+fn is_modifier(key: VirtualKeyCode) -> bool {
+    matches!(key, VirtualKeyCode::LShift | VirtualKeyCode::RShift | VirtualKeyCode::LControl | VirtualKeyCode::RControl | VirtualKeyCode::LAlt | VirtualKeyCode::RAlt | VirtualKeyCode::LWin | VirtualKeyCode::RWin)
+}
+
+// A trigger with the modifiers that have to be held, written like `ctrl+shift+z` or `mouse-right`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub trigger: Trigger,
+    pub modifiers: ModifiersState,
+}
+
+impl Binding {
+    pub fn key(key: VirtualKeyCode, modifiers: ModifiersState) -> Self {
+        Self {trigger: Trigger::Key(key), modifiers}
+    }
+
+    pub fn mouse(button: MouseButton, modifiers: ModifiersState) -> Self {
+        Self {trigger: Trigger::Mouse(button), modifiers}
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        let last: &str = parts.pop().filter(|last| !last.is_empty()).ok_or_else(|| anyhow!("empty binding {:?}", text))?;
+
+        let mut modifiers: ModifiersState = ModifiersState::empty();
+        for part in parts {
+            let (_, modifier) = MODIFIERS.iter().find(|(name, _)| part.eq_ignore_ascii_case(name)).ok_or_else(|| anyhow!("unknown modifier {:?} in {:?}", part, text))?;
+            modifiers |= *modifier;
+        }
+
+        let last: String = last.to_ascii_lowercase();
+        let trigger: Trigger = match last.strip_prefix("mouse-") {
+            Some("left") => Trigger::Mouse(MouseButton::Left),
+            Some("right") => Trigger::Mouse(MouseButton::Right),
+            Some("middle") => Trigger::Mouse(MouseButton::Middle),
+            Some(other) => Trigger::Mouse(MouseButton::Other(other.parse().with_context(|| format!("unknown mouse button {:?}", other))?)),
+            None => match KEYS.iter().find(|(name, _)| *name == last) {
+                Some((_, key)) => Trigger::Key(*key),
+                None => bail!("unknown key {:?}", last)
+            }
+        };
+        Ok(Self {trigger, modifiers})
+    }
+}
+
+impl Display for Binding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (name, modifier) in MODIFIERS {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match self.trigger {
+            Trigger::Mouse(MouseButton::Left) => write!(f, "mouse-left"),
+            Trigger::Mouse(MouseButton::Right) => write!(f, "mouse-right"),
+            Trigger::Mouse(MouseButton::Middle) => write!(f, "mouse-middle"),
+            Trigger::Mouse(MouseButton::Other(button)) => write!(f, "mouse-{}", button),
+            Trigger::Key(key) => match KEYS.iter().find(|(_, k)| *k == key) {
+                Some((name, _)) => write!(f, "{}", name),
+                None => write!(f, "{:?}", key)
+            }
+        }
+    }
+}
+
+// Which bindings start which action, several bindings can share an action but not the other way around
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bindings {
+    bindings: Vec<(Action, Binding)>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let none: ModifiersState = ModifiersState::empty();
+        Self {bindings: vec![
+            (Action::Orbit, Binding::mouse(MouseButton::Right, none)),
+            (Action::Pan, Binding::mouse(MouseButton::Middle, none)),
+            (Action::Pan, Binding::mouse(MouseButton::Right, ModifiersState::SHIFT)),
+            (Action::UseTool, Binding::mouse(MouseButton::Left, none)),
+            (Action::Erase, Binding::mouse(MouseButton::Left, ModifiersState::SHIFT)),
+            (Action::Paint, Binding::mouse(MouseButton::Left, ModifiersState::CTRL)),
+            (Action::Pick, Binding::mouse(MouseButton::Left, ModifiersState::ALT)),
+            (Action::Undo, Binding::key(VirtualKeyCode::Z, ModifiersState::CTRL)),
+            (Action::Redo, Binding::key(VirtualKeyCode::Y, ModifiersState::CTRL)),
+            (Action::Redo, Binding::key(VirtualKeyCode::Z, ModifiersState::CTRL | ModifiersState::SHIFT)),
+            (Action::ToggleWireframe, Binding::key(VirtualKeyCode::W, none)),
+            (Action::ToggleStats, Binding::key(VirtualKeyCode::F3, none)),
+            (Action::CycleMsaa, Binding::key(VirtualKeyCode::M, none)),
+            (Action::ToggleSsao, Binding::key(VirtualKeyCode::F5, none)),
+            (Action::ToggleOutline, Binding::key(VirtualKeyCode::F6, none)),
+            (Action::ToggleTonemap, Binding::key(VirtualKeyCode::F7, none)),
+            (Action::PathTrace, Binding::key(VirtualKeyCode::F9, none)),
+            (Action::ToggleGui, Binding::key(VirtualKeyCode::Tab, none)),
+            (Action::Turntable, Binding::key(VirtualKeyCode::T, none)),
+            (Action::Play, Binding::key(VirtualKeyCode::Space, none)),
+            (Action::NextFrame, Binding::key(VirtualKeyCode::Period, none)),
+            (Action::PreviousFrame, Binding::key(VirtualKeyCode::Comma, none)),
+            (Action::ToggleOnionSkin, Binding::key(VirtualKeyCode::O, none)),
+            (Action::Save, Binding::key(VirtualKeyCode::S, ModifiersState::CTRL)),
+            (Action::TranslateGizmo, Binding::key(VirtualKeyCode::G, none)),
+            (Action::RotateGizmo, Binding::key(VirtualKeyCode::R, none)),
+            (Action::ScaleGizmo, Binding::key(VirtualKeyCode::S, none)),
+            (Action::ToggleGizmoVoxels, Binding::key(VirtualKeyCode::V, none))
+        ]}
+    }
+}
+
+impl Bindings {
+    // Add a binding to an action, taking it away from the action it belonged to before
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        self.bindings.retain(|(_, b)| *b != binding);
+        self.bindings.push((action, binding));
+        // grouped by action, so equal bindings compare equal whatever order they were made in
+        self.bindings.sort_by_key(|(action, _)| *action as usize);
+    }
+
+    // Remove every binding of an action
+    pub fn clear(&mut self, action: Action) {
+        self.bindings.retain(|(a, _)| *a != action);
+    }
+
+    pub fn get(&self, action: Action) -> impl Iterator<Item = Binding> + '_ {
+        self.bindings.iter().filter(move |(a, _)| *a == action).map(|(_, binding)| *binding)
+    }
+
+    // The action started by a trigger with exactly these modifiers held
+    pub fn action(&self, binding: Binding) -> Option<Action> {
+        self.bindings.iter().find(|(_, b)| *b == binding).map(|(action, _)| *action)
+    }
+
+    // One action per line as `action = binding [binding ...]`, `;` starts a comment. Actions that aren't
+    // listed keep their default bindings, an action with nothing after the `=` is unbound
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut bindings: Bindings = Bindings::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line: &str = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (name, list) = line.split_once('=').ok_or_else(|| anyhow!("line {}: expected `action = bindings`", number + 1))?;
+            let action: Action = Action::from_name(name.trim()).ok_or_else(|| anyhow!("line {}: unknown action {:?}", number + 1, name.trim()))?;
+            bindings.clear(action);
+            for field in list.split_whitespace() {
+                bindings.bind(action, Binding::parse(field).with_context(|| format!("line {}", number + 1))?);
+            }
+        }
+        Ok(bindings)
+    }
+
+    // The text format read by `parse`, listing every action
+    pub fn to_text(&self) -> String {
+        let mut text: String = String::new();
+        for action in Action::ALL {
+            let bindings: Vec<String> = self.get(action).map(|binding| binding.to_string()).collect();
+            text += &format!("{} = {}\n", action.name(), bindings.join(" "));
+        }
+        text
+    }
+
+    // voxelart/bindings.txt in the user's config directory
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("voxelart").join("bindings.txt"))
+    }
+
+    // The user's bindings, the defaults when there is no bindings file or it can't be read
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {return Self::default()};
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).unwrap_or_else(|e| {
+                log::error!("ignoring {}: {:#}", path.display(), e);
+                Self::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                log::error!("failed to read {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        fs::write(path, self.to_text()).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionEvent {
+    Pressed(Action),
+    Released(Action),
+    Rebound(Action, Binding), // the binding the action got from `Dispatcher::rebind`
+}
+
+// Turns window events into actions, keeping track of the held modifiers and triggers
+#[derive(Default)]
+pub struct Dispatcher {
+    pub bindings: Bindings,
+    modifiers: ModifiersState,
+    held: HashMap<Trigger, Action>,
+    rebinding: Option<Action>,
+}
+
+impl Dispatcher {
+    pub fn new(bindings: Bindings) -> Self {
+        Self {bindings, ..Default::default()}
+    }
+
+    pub fn is_active(&self, action: Action) -> bool {
+        self.held.values().any(|held| *held == action)
+    }
+
+    // The next key or button pressed becomes the only binding of `action`
+    pub fn rebind(&mut self, action: Action) {
+        self.rebinding = Some(action);
+    }
+
+    // The action waiting for its new binding
+    pub fn rebinding(&self) -> Option<Action> {
+        self.rebinding
+    }
+
+    pub fn dispatch(&mut self, event: &Event<()>) -> Vec<ActionEvent> {
+        let Event::WindowEvent {event, ..} = event else {return Vec::new()};
+        let (trigger, state): (Trigger, ElementState) = match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                return Vec::new();
+            }
+            // nothing is held anymore once another window has the focus
+            WindowEvent::Focused(false) => return self.held.drain().map(|(_, action)| ActionEvent::Released(action)).collect(),
+            WindowEvent::KeyboardInput {input: KeyboardInput {state, virtual_keycode: Some(key), ..}, ..} if !is_modifier(*key) => (Trigger::Key(*key), *state),
+            WindowEvent::MouseInput {state, button, ..} => (Trigger::Mouse(*button), *state),
+            _ => return Vec::new()
+        };
+
+        match state {
+            // key repeats arrive as presses of a held key
+            ElementState::Pressed if self.held.contains_key(&trigger) => Vec::new(),
+            ElementState::Pressed => {
+                let binding: Binding = Binding {trigger, modifiers: self.modifiers};
+                // only keys with a name in the bindings file can be bound, so it can be read back
+                let named: bool = match trigger {
+                    Trigger::Key(key) => KEYS.iter().any(|(_, k)| *k == key),
+                    Trigger::Mouse(_) => true
+                };
+                if let Some(action) = self.rebinding.filter(|_| named) {
+                    self.rebinding = None;
+                    self.bindings.clear(action);
+                    self.bindings.bind(action, binding);
+                    return vec![ActionEvent::Rebound(action, binding)];
+                }
+                match self.bindings.action(binding) {
+                    Some(action) => {
+                        self.held.insert(trigger, action);
+                        vec![ActionEvent::Pressed(action)]
+                    }
+                    None => Vec::new()
+                }
+            }
+            // released whatever modifiers are held by then
+            ElementState::Released => self.held.remove(&trigger).map(ActionEvent::Released).into_iter().collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::{DeviceId, ElementState, Event, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent};
+    use winit::window::WindowId;
+    use crate::bindings::{Action, ActionEvent, Binding, Bindings, Dispatcher};
+
+    fn window_event(event: WindowEvent<'static>) -> Event<'static, ()> {
+        Event::WindowEvent {window_id: unsafe {WindowId::dummy()}, event}
+    }
+
+    #[allow(deprecated)]
+    fn key(key: VirtualKeyCode, state: ElementState) -> Event<'static, ()> {
+        window_event(WindowEvent::KeyboardInput {device_id: unsafe {DeviceId::dummy()}, input: KeyboardInput {scancode: 0, state, virtual_keycode: Some(key), modifiers: ModifiersState::empty()}, is_synthetic: false})
+    }
+
+    #[allow(deprecated)]
+    fn mouse(button: MouseButton, state: ElementState) -> Event<'static, ()> {
+        window_event(WindowEvent::MouseInput {device_id: unsafe {DeviceId::dummy()}, state, button, modifiers: ModifiersState::empty()})
+    }
+
+    #[test]
+    fn test_parse_bindings() {
+        let bindings: Bindings = Bindings::parse("; comment\nundo = ctrl+u F12\norbit = alt+mouse-left\npath_trace =\n").unwrap();
+        assert_eq!(bindings.get(Action::Undo).collect::<Vec<Binding>>(), vec![Binding::key(VirtualKeyCode::U, ModifiersState::CTRL), Binding::key(VirtualKeyCode::F12, ModifiersState::empty())]);
+        assert_eq!(bindings.action(Binding::mouse(MouseButton::Left, ModifiersState::ALT)), Some(Action::Orbit));
+        assert_eq!(bindings.get(Action::PathTrace).count(), 0);
+        assert_eq!(bindings.get(Action::ToggleWireframe).collect::<Vec<Binding>>(), vec![Binding::key(VirtualKeyCode::W, ModifiersState::empty())]);
+
+        assert_eq!(Bindings::parse(&bindings.to_text()).unwrap(), bindings);
+        assert!(Bindings::parse("jump = space").is_err());
+        assert!(Bindings::parse("undo = hyper+z").is_err());
+        assert!(Bindings::parse("undo = ctrl+").is_err());
+    }
+
+    #[test]
+    fn test_dispatch_with_modifiers() {
+        let mut dispatcher: Dispatcher = Dispatcher::default();
+        assert_eq!(dispatcher.dispatch(&key(VirtualKeyCode::Z, ElementState::Pressed)), vec![]);
+        assert_eq!(dispatcher.dispatch(&key(VirtualKeyCode::Z, ElementState::Released)), vec![]);
+
+        dispatcher.dispatch(&window_event(WindowEvent::ModifiersChanged(ModifiersState::CTRL)));
+        dispatcher.dispatch(&key(VirtualKeyCode::LControl, ElementState::Pressed));
+        assert_eq!(dispatcher.dispatch(&key(VirtualKeyCode::Z, ElementState::Pressed)), vec![ActionEvent::Pressed(Action::Undo)]);
+        assert_eq!(dispatcher.dispatch(&key(VirtualKeyCode::Z, ElementState::Pressed)), vec![]);
+        assert!(dispatcher.is_active(Action::Undo));
+        dispatcher.dispatch(&window_event(WindowEvent::ModifiersChanged(ModifiersState::empty())));
+        assert_eq!(dispatcher.dispatch(&key(VirtualKeyCode::Z, ElementState::Released)), vec![ActionEvent::Released(Action::Undo)]);
+
+        // held actions end when the window loses the focus
+        assert_eq!(dispatcher.dispatch(&mouse(MouseButton::Right, ElementState::Pressed)), vec![ActionEvent::Pressed(Action::Orbit)]);
+        assert!(dispatcher.is_active(Action::Orbit));
+        assert_eq!(dispatcher.dispatch(&window_event(WindowEvent::Focused(false))), vec![ActionEvent::Released(Action::Orbit)]);
+        assert!(!dispatcher.is_active(Action::Orbit));
+    }
+
+    #[test]
+    fn test_rebind_takes_the_next_press() {
+        let mut dispatcher: Dispatcher = Dispatcher::default();
+        dispatcher.rebind(Action::ToggleWireframe);
+        dispatcher.dispatch(&window_event(WindowEvent::ModifiersChanged(ModifiersState::SHIFT)));
+        dispatcher.dispatch(&key(VirtualKeyCode::LShift, ElementState::Pressed));
+        let binding: Binding = Binding::key(VirtualKeyCode::F3, ModifiersState::SHIFT);
+        assert_eq!(dispatcher.dispatch(&key(VirtualKeyCode::F3, ElementState::Pressed)), vec![ActionEvent::Rebound(Action::ToggleWireframe, binding)]);
+        dispatcher.dispatch(&key(VirtualKeyCode::F3, ElementState::Released));
+
+        assert_eq!(dispatcher.bindings.get(Action::ToggleWireframe).collect::<Vec<Binding>>(), vec![binding]);
+        assert_eq!(dispatcher.dispatch(&key(VirtualKeyCode::F3, ElementState::Pressed)), vec![ActionEvent::Pressed(Action::ToggleWireframe)]);
+        dispatcher.dispatch(&window_event(WindowEvent::ModifiersChanged(ModifiersState::empty())));
+        dispatcher.dispatch(&key(VirtualKeyCode::F3, ElementState::Released));
+        assert_eq!(dispatcher.dispatch(&key(VirtualKeyCode::W, ElementState::Pressed)), vec![]);
+        assert_eq!(dispatcher.dispatch(&key(VirtualKeyCode::F3, ElementState::Pressed)), vec![ActionEvent::Pressed(Action::ToggleStats)]);
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+// A buffer handed out by the pool, together with its allocated size in bytes
+pub struct PoolBuffer<B> {
+    pub buffer: B,
+    pub size: u64,
+}
+
+// Recycles buffers in power of two size classes, so chunks that grow or shrink
+// reuse released buffers instead of allocating a new one on every edit.
+// Generic over the buffer type so the bookkeeping can be tested without a gpu
+pub struct BufferPool<B> {
+    free: HashMap<u64, Vec<B>>,
+    allocated: u64, // bytes allocated over the lifetime of the pool, buffers are never freed
+}
+
+impl<B> BufferPool<B> {
+    pub const MIN_SIZE: u64 = 4096;
+
+    pub fn new() -> Self {
+        Self {free: HashMap::new(), allocated: 0}
+    }
+
+    pub fn size_class(size: u64) -> u64 {
+        size.max(Self::MIN_SIZE).next_power_of_two()
+    }
+
+    // Take a free buffer of at least `size` bytes, or create one with `allocate`
+    pub fn acquire(&mut self, size: u64, allocate: impl FnOnce(u64) -> B) -> PoolBuffer<B> {
+        let class: u64 = Self::size_class(size);
+        match self.free.get_mut(&class).and_then(Vec::pop) {
+            Some(buffer) => PoolBuffer {buffer, size: class},
+            None => {
+                self.allocated += class;
+                PoolBuffer {buffer: allocate(class), size: class}
+            }
+        }
+    }
+
+    pub fn release(&mut self, buffer: PoolBuffer<B>) {
+        self.free.entry(buffer.size).or_default().push(buffer.buffer);
+    }
+
+    // Bytes in buffers handed out and not released
+    pub fn live_bytes(&self) -> u64 {
+        self.allocated - self.free_bytes()
+    }
+
+    // Bytes in released buffers waiting to be reused
+    pub fn free_bytes(&self) -> u64 {
+        self.free.iter().map(|(size, buffers)| size * buffers.len() as u64).sum()
+    }
+}
+
+impl<B> Default for BufferPool<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer_pool::BufferPool;
+
+    #[test]
+    fn test_buffers_are_reused_per_size_class() {
+        let mut pool: BufferPool<u32> = BufferPool::new();
+        let mut next_id: u32 = 0;
+        let mut allocate = |_| {next_id += 1; next_id};
+
+        let a = pool.acquire(100, &mut allocate);
+        assert_eq!((a.buffer, a.size), (1, 4096));
+
+        let b = pool.acquire(5000, &mut allocate);
+        assert_eq!((b.buffer, b.size), (2, 8192));
+
+        pool.release(a);
+        assert_eq!((pool.live_bytes(), pool.free_bytes()), (8192, 4096));
+
+        // fits in the released class, no new allocation
+        let c = pool.acquire(4000, &mut allocate);
+        assert_eq!(c.buffer, 1);
+        assert_eq!((pool.live_bytes(), pool.free_bytes()), (4096 + 8192, 0));
+
+        // growing moves to a bigger class
+        pool.release(b);
+        let d = pool.acquire(9000, &mut allocate);
+        assert_eq!((d.buffer, d.size), (3, 16384));
+        assert_eq!((pool.live_bytes(), pool.free_bytes()), (4096 + 16384, 8192));
+    }
+}
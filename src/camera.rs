@@ -1,26 +1,28 @@
 use bytemuck::{Pod, Zeroable};
-use cgmath::{Deg, Matrix4, perspective, Point3, SquareMatrix, Vector3};
-use winit::event::{DeviceEvent, ElementState, Event, MouseButton, WindowEvent};
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix4, perspective, Point3, SquareMatrix, Vector3, Vector4};
 use crate::OPENGL_TO_WGPU_MATRIX;
 
-#[derive(Default)]
+// How fast the camera follows the mouse
 pub struct CameraController {
-    sensitivity: f32, // configure camera sensitivity
-
-    m_x: f32, // mouse x
-    m_y: f32, // mouse y
+    pub sensitivity: f32, // degrees of orbit per pixel of mouse motion
+    pub zoom_speed: f32, // fraction of the distance to the target per line scrolled
+}
 
-    is_left_click: bool, // if left mouse button is clicked
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {sensitivity: 0.3, zoom_speed: 0.1}
+    }
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
 pub struct CameraUniform {
-    pub view_proj: [[f32; 4]; 4]
+    pub view_proj: [[f32; 4]; 4],
+    pub view: [[f32; 4]; 4]
 } // wrapper for camera matrices
 impl CameraUniform {
     pub fn new() -> CameraUniform {
-        Self {view_proj: Matrix4::identity().into()}
+        Self {view_proj: Matrix4::identity().into(), view: Matrix4::identity().into()}
     }
 }
 
@@ -41,60 +43,123 @@ pub struct Camera {
 }
 
 impl Camera {
-    // Temporary code to test mouse compatibility
-    pub fn process_events(&mut self, window_event: &WindowEvent, device_event: &DeviceEvent) {
-        match &mut self.controller {
-            Some(controller) => {
-                match window_event {
-                    WindowEvent::MouseInput {
-                        button: MouseButton::Left, state, ..
-                    } => {
-                        controller.is_left_click = *state == ElementState::Pressed;
-                    },
-                    _ => {},
-                }
-
-                match device_event {
-                    DeviceEvent::MouseMotion { delta } => {
-                        if controller.is_left_click {
-                            controller.m_x += delta.0 as f32;
-                            controller.m_y -= delta.1 as f32;
-
-                            let pitch = controller.m_y.to_radians();
-                            let yaw = controller.m_x.to_radians();
-
-                            self.eye.x = (pitch.sin() * 15.0) * yaw.cos();
-                            self.eye.y = pitch.cos() * 15.0;
-                            self.eye.z = (pitch.sin() * 15.0) * yaw.sin();
-                        }
-                    }
-                    _ => {}
-                }
-            }
-
-            _ => {}
+    // Turn around the target, keeping the distance to it. Moving the mouse down looks from higher up
+    pub fn orbit(&mut self, motion: [f32; 2]) {
+        let Some(controller) = &self.controller else {return};
+        let offset: Vector3<f32> = self.eye - self.target;
+        let distance: f32 = offset.magnitude();
+        let yaw: f32 = offset.z.atan2(offset.x) + (motion[0] * controller.sensitivity).to_radians();
+        let limit: f32 = 89.0_f32.to_radians();
+        let pitch: f32 = ((offset.y / distance).asin() + (motion[1] * controller.sensitivity).to_radians()).clamp(-limit, limit);
+        self.eye = self.target + Vector3::new(pitch.cos() * yaw.cos(), pitch.sin(), pitch.cos() * yaw.sin()) * distance;
+    }
+
+    // Circle the eye around the target's vertical axis, counter-clockwise seen from above
+    pub fn turn(&mut self, degrees: f32) {
+        let (sin, cos): (f32, f32) = degrees.to_radians().sin_cos();
+        let offset: Vector3<f32> = self.eye - self.target;
+        self.eye = self.target + Vector3::new(offset.x * cos + offset.z * sin, offset.y, offset.z * cos - offset.x * sin);
+    }
+
+    // Move the eye and the target sideways, so what is under the cursor at the target's depth stays under it
+    pub fn pan(&mut self, motion: [f32; 2], viewport_height: f32) {
+        if self.controller.is_none() {
+            return;
         }
+        let forward: Vector3<f32> = self.target - self.eye;
+        let right: Vector3<f32> = forward.cross(self.up).normalize();
+        let up: Vector3<f32> = right.cross(forward).normalize();
+        let per_pixel: f32 = 2.0 * forward.magnitude() * (self.fov.to_radians() / 2.0).tan() / viewport_height;
+        let shift: Vector3<f32> = (up * motion[1] - right * motion[0]) * per_pixel;
+        self.eye += shift;
+        self.target += shift;
     }
 
-    pub fn update_view_proj(&mut self) {
-        let view: Matrix4<f32> = Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj: Matrix4<f32> = perspective(Deg(self.fov), self.aspect, self.near, self.far);
-        self.uniform.view_proj = (OPENGL_TO_WGPU_MATRIX * (proj * view)).into();
+    // Move towards the target by a fraction of the distance per line, never closer than the near plane
+    pub fn zoom(&mut self, lines: f32) {
+        let Some(controller) = &self.controller else {return};
+        let offset: Vector3<f32> = self.eye - self.target;
+        let distance: f32 = (offset.magnitude() * (1.0 - controller.zoom_speed).powf(lines)).max(2.0 * self.near);
+        self.eye = self.target + offset.normalize() * distance;
+    }
+
+    // Look at the centre of a box from the current direction, far enough away that all of it is in view
+    pub fn frame(&mut self, min: Point3<f32>, max: Point3<f32>) {
+        let direction: Vector3<f32> = match self.eye - self.target {
+            offset if offset.magnitude2() > 0.0 => offset.normalize(),
+            _ => Vector3::new(1.0, 1.0, 1.0).normalize()
+        };
+        let radius: f32 = (max - min).magnitude() / 2.0;
+        // whichever of the vertical and horizontal field of view is narrower
+        let vertical: f32 = (self.fov / 2.0).to_radians();
+        let half_fov: f32 = vertical.min((vertical.tan() * self.aspect).atan());
+        self.target = min.midpoint(max);
+        self.eye = self.target + direction * (radius / half_fov.sin()).max(2.0 * self.near);
+    }
 
-        match &mut self.controller {
-            Some(controller) => {
-                controller.m_x += 0.0 as f32;
-                controller.m_y -= 0.005 as f32;
+    pub fn build_view_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    pub fn build_projection_matrix(&self) -> Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * perspective(Deg(self.fov), self.aspect, self.near, self.far)
+    }
 
-                let pitch = controller.m_y;
-                let yaw = controller.m_x;
+    pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
+        self.build_projection_matrix() * self.build_view_matrix()
+    }
 
-                //self.eye.x = (pitch.sin() * 3.0) * yaw.cos();
-                self.eye.y = pitch.cos() * 150.0;
-                //self.eye.z = 300.0; //(pitch.sin() * 3.0) * yaw.sin();
-            }
+    // Ray through a window pixel, starting on the near plane
+    pub fn ray(&self, x: f32, y: f32, width: f32, height: f32) -> (Point3<f32>, Vector3<f32>) {
+        let inverse: Matrix4<f32> = self.build_view_projection_matrix().invert().unwrap_or(Matrix4::identity());
+        let (ndc_x, ndc_y): (f32, f32) = (x / width * 2.0 - 1.0, 1.0 - y / height * 2.0);
+        let unproject = |z: f32| {
+            let p: Vector4<f32> = inverse * Vector4::new(ndc_x, ndc_y, z, 1.0);
+            Point3::new(p.x / p.w, p.y / p.w, p.z / p.w)
+        };
+        let (near, far): (Point3<f32>, Point3<f32>) = (unproject(0.0), unproject(1.0));
+        (near, (far - near).normalize())
+    }
 
-            _ => {}
-        }
+    pub fn update_view_proj(&mut self) {
+        self.uniform.view_proj = self.build_view_projection_matrix().into();
+        self.uniform.view = self.build_view_matrix().into();
     } // update view matrix inside camera
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, MetricSpace, Point3, Vector3};
+    use crate::camera::{Camera, CameraController, CameraUniform};
+
+    #[test]
+    fn test_orbit_pan_and_zoom() {
+        let mut camera: Camera = Camera {eye: Point3::new(10.0, 0.0, 0.0), target: Point3::new(0.0, 0.0, 0.0), up: Vector3::unit_y(), aspect: 1.0, fov: 90.0, near: 0.1, far: 100.0, uniform: CameraUniform::new(), controller: Some(CameraController::default())};
+
+        // a quarter turn to the side, then looking from straight above as far as the pitch goes
+        camera.orbit([300.0, 0.0]);
+        assert!(camera.eye.distance(Point3::new(0.0, 0.0, 10.0)) < 1e-4, "{:?}", camera.eye);
+        camera.orbit([0.0, 1000.0]);
+        assert!((camera.eye.distance(camera.target) - 10.0).abs() < 1e-4);
+        assert!(camera.eye.y > 9.99 && camera.eye.y < 10.0);
+
+        // turning doesn't need the controller, it keeps the height and the distance
+        let eye: Point3<f32> = camera.eye;
+        camera.turn(360.0);
+        assert!(camera.eye.distance(eye) < 1e-3);
+
+        camera.zoom(1.0);
+        assert!((camera.eye.distance(camera.target) - 9.0).abs() < 1e-4);
+        camera.zoom(1000.0);
+        assert!((camera.eye.distance(camera.target) - 0.2).abs() < 1e-4);
+
+        // dragging half the viewport to the right at 45 degrees to the edge moves by the distance
+        camera.eye = Point3::new(0.0, 0.0, 10.0);
+        camera.target = Point3::new(0.0, 0.0, 0.0);
+        camera.pan([50.0, 0.0], 100.0);
+        assert!((camera.target - Point3::new(-10.0, 0.0, 0.0)).magnitude() < 1e-4, "{:?}", camera.target);
+        assert!((camera.eye - Point3::new(-10.0, 0.0, 10.0)).magnitude() < 1e-4);
+        camera.turn(90.0);
+        assert!((camera.eye - Point3::new(0.0, 0.0, 0.0)).magnitude() < 1e-4, "{:?}", camera.eye);
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use cgmath::{ElementWise, EuclideanSpace, Point3, Vector3};
+use crate::voxel::{Instance, InstanceRaw};
+
+// Edge length of a chunk in voxels
+pub const CHUNK_SIZE: i32 = 32;
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+// Position of a chunk in chunk coordinates (voxel position / CHUNK_SIZE)
+pub type ChunkPos = Vector3<i32>;
+
+// A palette index per cell, None for empty cells
+pub type Voxel = Option<u8>;
+
+// A fixed size block of voxels, the unit in which voxels are uploaded to the gpu
+#[derive(Clone)]
+pub struct Chunk {
+    voxels: Vec<Voxel>,
+    count: usize, // number of filled cells
+    pub dirty: bool, // changed since the last upload
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self {voxels: vec![None; CHUNK_VOLUME], count: 0, dirty: true}
+    }
+
+    fn index(local: Vector3<i32>) -> usize {
+        (local.x + local.y * CHUNK_SIZE + local.z * CHUNK_SIZE * CHUNK_SIZE) as usize
+    }
+
+    pub fn get(&self, local: Vector3<i32>) -> Voxel {
+        self.voxels[Self::index(local)]
+    }
+
+    // Returns the previous voxel, and only marks the chunk dirty when something actually changed
+    pub fn set(&mut self, local: Vector3<i32>, voxel: Voxel) -> Voxel {
+        let cell: &mut Voxel = &mut self.voxels[Self::index(local)];
+        let previous: Voxel = std::mem::replace(cell, voxel);
+
+        if previous != voxel {
+            self.dirty = true;
+            match (previous, voxel) {
+                (None, Some(_)) => self.count += 1,
+                (Some(_), None) => self.count -= 1,
+                _ => {}
+            }
+        }
+        previous
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // Filled cells as (local position, palette index)
+    pub fn iter(&self) -> impl Iterator<Item = (Vector3<i32>, u8)> + '_ {
+        self.voxels.iter().enumerate().filter_map(|(i, voxel)| {
+            let i: i32 = i as i32;
+            voxel.map(|v| (Vector3::new(i % CHUNK_SIZE, (i / CHUNK_SIZE) % CHUNK_SIZE, i / (CHUNK_SIZE * CHUNK_SIZE)), v))
+        })
+    }
+
+    // Downsample by 2^lod per axis. A cell is filled when any voxel inside it is, with the most common colour
+    pub fn downsample(&self, lod: usize) -> Vec<Voxel> {
+        if lod == 0 {
+            return self.voxels.clone();
+        }
+
+        let factor: i32 = 1 << lod;
+        let side: i32 = CHUNK_SIZE / factor;
+        let mut cells: Vec<Voxel> = vec![None; (side * side * side) as usize];
+        let mut counts: Vec<u8> = Vec::with_capacity((factor * factor * factor) as usize);
+
+        for (i, cell) in cells.iter_mut().enumerate() {
+            let i: i32 = i as i32;
+            let base: Vector3<i32> = Vector3::new(i % side, (i / side) % side, i / (side * side)) * factor;
+
+            counts.clear();
+            for z in 0..factor {
+                for y in 0..factor {
+                    for x in 0..factor {
+                        if let Some(v) = self.get(base + Vector3::new(x, y, z)) {
+                            counts.push(v);
+                        }
+                    }
+                }
+            }
+
+            counts.sort_unstable();
+            *cell = counts.chunk_by(|a, b| a == b).max_by_key(|run| run.len()).map(|run| run[0]);
+        }
+        cells
+    }
+
+    // Instance data for every visible voxel at a level of detail, positioned relative to the chunk origin.
+    // `see_through` is indexed by palette index, see `Palette::see_through`
+    pub fn instances(&self, lod: usize, see_through: &[bool]) -> Vec<InstanceRaw> {
+        let factor: i32 = 1 << lod;
+        let side: i32 = CHUNK_SIZE / factor;
+        let cells: Vec<Voxel> = self.downsample(lod);
+        let cell = |p: Vector3<i32>| cells[(p.x + p.y * side + p.z * side * side) as usize];
+
+        // a face is drawn unless the neighbour behind it inside the chunk hides it, faces on the chunk border always are.
+        // See-through neighbours only hide faces of the same palette entry. Offsets are in the face order of the cube vertices
+        let faces = |p: Vector3<i32>, index: u8| [Vector3::unit_z(), -Vector3::unit_z(), Vector3::unit_x(), -Vector3::unit_x(), Vector3::unit_y(), -Vector3::unit_y()].iter().enumerate().fold(0u8, |faces, (i, offset)| {
+            let n: Vector3<i32> = p + offset;
+            let inside: bool = (0..side).contains(&n.x) && (0..side).contains(&n.y) && (0..side).contains(&n.z);
+            let covered: bool = inside && cell(n).is_some_and(|neighbour| neighbour == index || !see_through.get(neighbour as usize).copied().unwrap_or(false));
+            faces | ((!covered as u8) << i)
+        });
+
+        cells.iter().enumerate().filter_map(|(i, voxel)| {
+            let i: i32 = i as i32;
+            let p: Vector3<i32> = Vector3::new(i % side, (i / side) % side, i / (side * side));
+            let index: u8 = (*voxel)?;
+            let faces: u8 = faces(p, index);
+            // cells with every face covered are skipped entirely
+            (faces != 0).then(|| Instance::new((p * factor).cast().unwrap(), lod as u32, index).with_faces(faces).encode())
+        }).collect()
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Sparse voxel storage, split into chunks
+#[derive(Clone, Default)]
+pub struct ChunkMap {
+    chunks: HashMap<ChunkPos, Chunk>,
+}
+
+impl ChunkMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Split a voxel position into its chunk and the position inside that chunk
+    pub fn split(position: Vector3<i32>) -> (ChunkPos, Vector3<i32>) {
+        let chunk: ChunkPos = position.map(|c| c.div_euclid(CHUNK_SIZE));
+        (chunk, position - chunk * CHUNK_SIZE)
+    }
+
+    pub fn get(&self, position: Vector3<i32>) -> Voxel {
+        let (chunk, local) = Self::split(position);
+        self.chunks.get(&chunk).and_then(|c| c.get(local))
+    }
+
+    pub fn set(&mut self, position: Vector3<i32>, voxel: Voxel) -> Voxel {
+        let (chunk, local) = Self::split(position);
+        match (self.chunks.get_mut(&chunk), voxel) {
+            (Some(c), _) => c.set(local, voxel),
+            (None, Some(_)) => self.chunks.entry(chunk).or_default().set(local, voxel),
+            (None, None) => None
+        }
+    }
+
+    pub fn chunk(&self, pos: ChunkPos) -> Option<&Chunk> {
+        self.chunks.get(&pos)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (&ChunkPos, &Chunk)> {
+        self.chunks.iter()
+    }
+
+    // Every filled voxel with its world position
+    pub fn iter(&self) -> impl Iterator<Item = (Vector3<i32>, u8)> + '_ {
+        self.chunks.iter().flat_map(|(pos, chunk)| chunk.iter().map(move |(local, index)| (*pos * CHUNK_SIZE + local, index)))
+    }
+
+    // Smallest and largest position of any filled voxel, None when the map is empty
+    pub fn bounds(&self) -> Option<(Vector3<i32>, Vector3<i32>)> {
+        self.iter().fold(None, |bounds, (p, _)| Some(match bounds {
+            Some((min, max)) => (Vector3::new(p.x.min(min.x), p.y.min(min.y), p.z.min(min.z)), Vector3::new(p.x.max(max.x), p.y.max(max.y), p.z.max(max.z))),
+            None => (p, p)
+        }))
+    }
+
+    // First filled voxel along the ray within `max_distance`, and the normal of the face it was entered through.
+    // Voxel p covers p - 0.5 to p + 0.5, a ray starting inside a voxel hits it with a zero normal
+    pub fn raycast(&self, origin: Point3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<(Vector3<i32>, Vector3<i32>)> {
+        let o: Vector3<f32> = origin.to_vec() + Vector3::new(0.5, 0.5, 0.5);
+        let inverse: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0).div_element_wise(direction);
+        let step: Vector3<i32> = direction.map(|d| if d > 0.0 {1} else if d < 0.0 {-1} else {0});
+        let mut cell: Vector3<i32> = o.map(|c| c.floor() as i32);
+        let mut normal: Vector3<i32> = Vector3::new(0, 0, 0);
+
+        let mut t_max: Vector3<f32> = Vector3::<usize>::new(0, 1, 2).map(|i| match step[i] {
+            0 => f32::INFINITY,
+            s => ((cell[i] + (s > 0) as i32) as f32 - o[i]) * inverse[i]
+        });
+        let t_delta: Vector3<f32> = inverse.map(|i| i.abs());
+
+        let mut t: f32 = 0.0;
+        while t <= max_distance {
+            if self.get(cell).is_some() {
+                return Some((cell, normal));
+            }
+            let axis: usize = if t_max.x < t_max.y {if t_max.x < t_max.z {0} else {2}} else if t_max.y < t_max.z {1} else {2};
+            t = t_max[axis];
+            cell[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            normal = Vector3::new(0, 0, 0);
+            normal[axis] = -step[axis];
+        }
+        None
+    }
+
+    // Number of filled voxels over all chunks
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(Chunk::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dirty_chunks(&self) -> Vec<ChunkPos> {
+        self.chunks.iter().filter(|(_, c)| c.dirty).map(|(pos, _)| *pos).collect()
+    }
+
+    // Clear the dirty flag, dropping the chunk when it became empty
+    pub fn mark_clean(&mut self, pos: ChunkPos) {
+        if let Some(chunk) = self.chunks.get_mut(&pos) {
+            chunk.dirty = false;
+            if chunk.is_empty() {
+                self.chunks.remove(&pos);
+            }
+        }
+    }
+
+    // Rebuild every chunk on the next sync, after something all instances depend on changed
+    pub fn mark_all_dirty(&mut self) {
+        for chunk in self.chunks.values_mut() {
+            chunk.dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, Vector3};
+    use crate::chunk::{ChunkMap, CHUNK_SIZE};
+    use crate::voxel::Instance;
+
+    #[test]
+    fn test_split_negative_positions() {
+        assert_eq!(ChunkMap::split(Vector3::new(-1, 0, CHUNK_SIZE)), (Vector3::new(-1, 0, 1), Vector3::new(CHUNK_SIZE - 1, 0, 0)));
+    }
+
+    #[test]
+    fn test_dirty_tracking() {
+        let mut map: ChunkMap = ChunkMap::new();
+        map.set(Vector3::new(1, 2, 3), Some(4));
+        map.set(Vector3::new(-1, 2, 3), Some(4));
+
+        let mut dirty = map.dirty_chunks();
+        dirty.sort_by_key(|p| p.x);
+        assert_eq!(dirty, vec![Vector3::new(-1, 0, 0), Vector3::new(0, 0, 0)]);
+
+        for pos in dirty {
+            map.mark_clean(pos);
+        }
+        assert!(map.dirty_chunks().is_empty());
+
+        // writing the same value again doesn't touch the chunk
+        map.set(Vector3::new(1, 2, 3), Some(4));
+        assert!(map.dirty_chunks().is_empty());
+
+        // only the edited chunk becomes dirty, and is dropped once emptied and uploaded
+        assert_eq!(map.set(Vector3::new(1, 2, 3), None), Some(4));
+        assert_eq!(map.dirty_chunks(), vec![Vector3::new(0, 0, 0)]);
+        map.mark_clean(Vector3::new(0, 0, 0));
+        assert!(map.chunk(Vector3::new(0, 0, 0)).is_none());
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn test_downsampled_levels() {
+        let mut map: ChunkMap = ChunkMap::new();
+        map.set(Vector3::new(CHUNK_SIZE + 2, 0, 0), Some(7));
+        map.set(Vector3::new(CHUNK_SIZE + 3, 1, 1), Some(7));
+        map.set(Vector3::new(CHUNK_SIZE + 2, 1, 0), Some(9));
+
+        let chunk = map.chunk(Vector3::new(1, 0, 0)).unwrap();
+        let level1: Vec<Instance> = chunk.instances(1, &[]).into_iter().map(Instance::decode).collect();
+        assert_eq!(level1, vec![Instance::new(Vector3::new(2, 0, 0), 1, 7)]);
+
+        let level2: Vec<Instance> = chunk.instances(2, &[]).into_iter().map(Instance::decode).collect();
+        assert_eq!(level2, vec![Instance::new(Vector3::new(0, 0, 0), 2, 7)]);
+    }
+
+    #[test]
+    fn test_hidden_voxels_are_skipped() {
+        let mut map: ChunkMap = ChunkMap::new();
+        for x in 0..3 {
+            for y in 0..3 {
+                for z in 0..3 {
+                    map.set(Vector3::new(x, y, z), Some(0));
+                }
+            }
+        }
+
+        let chunk = map.chunk(Vector3::new(0, 0, 0)).unwrap();
+        assert_eq!(chunk.len(), 27);
+        let instances: Vec<Instance> = chunk.instances(0, &[]).into_iter().map(Instance::decode).collect();
+        assert_eq!(instances.len(), 26);
+
+        // the center of a face only shows that face, a corner shows three
+        let faces = |position: Vector3<u32>| instances.iter().find(|instance| instance.position == position).unwrap().faces;
+        assert_eq!(faces(Vector3::new(1, 1, 2)), 0b00_0001);
+        assert_eq!(faces(Vector3::new(0, 0, 0)), 0b10_1010);
+
+        // a see-through center shows the faces around it, and is skipped itself since its neighbours hide it
+        map.set(Vector3::new(1, 1, 1), Some(1));
+        let chunk = map.chunk(Vector3::new(0, 0, 0)).unwrap();
+        let mut see_through: Vec<bool> = vec![false; 256];
+        see_through[1] = true;
+        let instances: Vec<Instance> = chunk.instances(0, &see_through).into_iter().map(Instance::decode).collect();
+        assert_eq!(instances.len(), 26);
+        assert!(instances.iter().all(|instance| instance.position != Vector3::new(1, 1, 1)));
+        let faces = |position: Vector3<u32>| instances.iter().find(|instance| instance.position == position).unwrap().faces;
+        assert_eq!(faces(Vector3::new(1, 1, 2)), 0b00_0011);
+    }
+
+    #[test]
+    fn test_raycast_hits_the_first_voxel() {
+        let mut map: ChunkMap = ChunkMap::new();
+        map.set(Vector3::new(-3, 0, 0), Some(1));
+        map.set(Vector3::new(-6, 0, 0), Some(2));
+
+        // entered through the +x face, across a chunk border
+        assert_eq!(map.raycast(Point3::new(2.0, 0.2, -0.1), -Vector3::unit_x(), 100.0), Some((Vector3::new(-3, 0, 0), Vector3::unit_x())));
+        assert_eq!(map.raycast(Point3::new(2.0, 0.2, -0.1), -Vector3::unit_x(), 4.0), None);
+        assert_eq!(map.raycast(Point3::new(2.0, 0.2, -0.1), Vector3::unit_x(), 100.0), None);
+        assert_eq!(map.raycast(Point3::new(-6.0, 10.0, 0.0), -Vector3::unit_y(), 100.0), Some((Vector3::new(-6, 0, 0), Vector3::unit_y())));
+
+        let mut filled: Vec<(Vector3<i32>, u8)> = map.iter().collect();
+        filled.sort_by_key(|(position, _)| position.x);
+        assert_eq!(filled, vec![(Vector3::new(-6, 0, 0), 2), (Vector3::new(-3, 0, 0), 1)]);
+    }
+}
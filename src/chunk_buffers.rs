@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::mem::size_of_val;
+use std::sync::atomic::{AtomicU64, Ordering};
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBinding, BufferBindingType, BufferDescriptor, BufferSize, BufferUsages, Device, Queue, ShaderStages};
+use crate::buffer_pool::{BufferPool, PoolBuffer};
+use crate::chunk::{ChunkMap, ChunkPos, CHUNK_SIZE};
+use crate::culling::{Aabb, LOD_LEVELS};
+use crate::voxel::InstanceRaw;
+
+// Instance buffer of one level of detail of a chunk
+pub struct GpuLevel {
+    pub buffer: PoolBuffer<Buffer>,
+    pub count: u32, // number of instances in the buffer
+}
+
+// All levels of detail of a chunk, index 0 is full resolution
+pub struct GpuChunk {
+    pub levels: Vec<GpuLevel>,
+    pub slot: u32, // entry in the origin buffer
+}
+
+// Placement of a chunk, read by the vertex shader through a dynamic offset: the matrix of the object it belongs to
+// and the position of its local origin in the object. The fourth component tells onion skins from the model
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+struct ChunkUniform {
+    model: [[f32; 4]; 4],
+    origin: [f32; 4],
+}
+
+// Generations are unique over all chunk buffers, so swapping one set of buffers for another is noticed too
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn next_generation() -> u64 {
+    GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+// Which neighbouring frame of an animation the chunks show, the shader tints them differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnionSkin {
+    Previous = 1,
+    Next = 2,
+}
+
+// Mirrors a ChunkMap on the gpu, one instance buffer per chunk and level of detail
+pub struct ChunkBuffers {
+    pub chunks: HashMap<ChunkPos, GpuChunk>,
+    pool: BufferPool<Buffer>,
+    generation: u64, // changes on every upload
+
+    pub bind_group_layout: BindGroupLayout,
+    pub bind_group: BindGroup,
+    origin_buffer: Buffer,
+    origin_stride: u64, // one origin per uniform offset alignment
+    capacity: u32, // slots in the origin buffer
+    free_slots: Vec<u32>,
+    onion_skin: Option<OnionSkin>,
+    transform: Matrix4<f32>, // of the object the chunks belong to
+}
+
+impl ChunkBuffers {
+    pub fn new(device: &Device) -> Self {
+        let bind_group_layout: BindGroupLayout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<ChunkUniform>() as u64),
+                    },
+                    count: None
+                }
+            ],
+            label: Some("Chunk Bind Group Layout")
+        });
+
+        let origin_stride: u64 = (device.limits().min_uniform_buffer_offset_alignment as u64).max(std::mem::size_of::<ChunkUniform>() as u64);
+        let capacity: u32 = 64;
+        let (origin_buffer, bind_group) = Self::create_origin_buffer(device, &bind_group_layout, origin_stride, capacity);
+
+        Self {chunks: HashMap::new(), pool: BufferPool::new(), generation: next_generation(), bind_group_layout, bind_group, origin_buffer, origin_stride, capacity, free_slots: (0..capacity).rev().collect(), onion_skin: None, transform: Matrix4::identity()}
+    }
+
+    // Chunks of another frame, drawn see-through around the current one
+    pub fn onion_skin(device: &Device, onion_skin: OnionSkin) -> Self {
+        Self {onion_skin: Some(onion_skin), ..Self::new(device)}
+    }
+
+    fn create_origin_buffer(device: &Device, layout: &BindGroupLayout, stride: u64, capacity: u32) -> (Buffer, BindGroup) {
+        let buffer: Buffer = device.create_buffer(&BufferDescriptor {label: Some("Chunk Origin Buffer"), size: stride * capacity as u64, usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST, mapped_at_creation: false});
+        let bind_group: BindGroup = device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {buffer: &buffer, offset: 0, size: BufferSize::new(std::mem::size_of::<ChunkUniform>() as u64)})
+                }
+            ],
+            label: Some("Chunk Bind Group")
+        });
+        (buffer, bind_group)
+    }
+
+    // Dynamic offset to bind the origin of a chunk with
+    pub fn origin_offset(&self, chunk: &GpuChunk) -> u32 {
+        (chunk.slot as u64 * self.origin_stride) as u32
+    }
+
+    fn write_origin(&self, queue: &Queue, pos: ChunkPos, slot: u32) {
+        let origin: [f32; 3] = (pos * CHUNK_SIZE).cast::<f32>().unwrap().into();
+        let kind: f32 = self.onion_skin.map_or(0.0, |onion_skin| onion_skin as u32 as f32);
+        queue.write_buffer(&self.origin_buffer, slot as u64 * self.origin_stride, cast_slice(&[ChunkUniform {model: self.transform.into(), origin: [origin[0], origin[1], origin[2], kind]}]));
+    }
+
+    // Place the chunks in the world by the matrix of their object
+    pub fn set_transform(&mut self, queue: &Queue, transform: Matrix4<f32>) {
+        if transform != self.transform {
+            self.transform = transform;
+            for (pos, chunk) in &self.chunks {
+                self.write_origin(queue, *pos, chunk.slot);
+            }
+        }
+    }
+
+    pub fn transform(&self) -> Matrix4<f32> {
+        self.transform
+    }
+
+    fn acquire_slot(&mut self, device: &Device, queue: &Queue) -> u32 {
+        if self.free_slots.is_empty() {
+            // double the origin buffer, and write every origin again
+            let capacity: u32 = self.capacity * 2;
+            (self.origin_buffer, self.bind_group) = Self::create_origin_buffer(device, &self.bind_group_layout, self.origin_stride, capacity);
+            self.free_slots.extend((self.capacity..capacity).rev());
+            self.capacity = capacity;
+            for (pos, chunk) in &self.chunks {
+                self.write_origin(queue, *pos, chunk.slot);
+            }
+        }
+        self.free_slots.pop().unwrap()
+    }
+
+    // Re-upload only the chunks that changed since the last sync, `see_through` as in `Chunk::instances`
+    pub fn sync(&mut self, device: &Device, queue: &Queue, map: &mut ChunkMap, see_through: &[bool]) {
+        for pos in map.dirty_chunks() {
+            let levels: Vec<Vec<InstanceRaw>> = match map.chunk(pos) {
+                Some(chunk) if !chunk.is_empty() => (0..LOD_LEVELS).map(|lod| chunk.instances(lod, see_through)).collect(),
+                _ => Vec::new()
+            };
+            self.upload(device, queue, pos, &levels);
+            map.mark_clean(pos);
+        }
+    }
+
+    // Remove the chunks `map` doesn't have, before syncing a map that replaces the one uploaded so far
+    pub fn retain(&mut self, device: &Device, queue: &Queue, map: &ChunkMap) {
+        let removed: Vec<ChunkPos> = self.chunks.keys().copied().filter(|pos| map.chunk(*pos).is_none()).collect();
+        for pos in removed {
+            self.upload(device, queue, pos, &[]);
+        }
+    }
+
+    // Replace the instances of a chunk, an empty list of levels removes it
+    pub fn upload(&mut self, device: &Device, queue: &Queue, pos: ChunkPos, levels: &[Vec<InstanceRaw>]) {
+        self.generation = next_generation();
+        let (mut old, slot): (Vec<GpuLevel>, Option<u32>) = match self.chunks.remove(&pos) {
+            Some(chunk) => (chunk.levels, Some(chunk.slot)),
+            None => (Vec::new(), None)
+        };
+        old.reverse();
+
+        let mut uploaded: Vec<GpuLevel> = Vec::with_capacity(levels.len());
+        for instances in levels {
+            let bytes: u64 = size_of_val(instances.as_slice()) as u64;
+
+            // keep the previous buffer of this level when the instances still fit
+            let buffer: PoolBuffer<Buffer> = match old.pop() {
+                Some(level) if level.buffer.size >= bytes => level.buffer,
+                previous => {
+                    if let Some(level) = previous {
+                        self.pool.release(level.buffer);
+                    }
+                    self.pool.acquire(bytes, |size| device.create_buffer(&BufferDescriptor {label: Some("Chunk Instance Buffer"), size, usage: BufferUsages::VERTEX | BufferUsages::COPY_DST, mapped_at_creation: false}))
+                }
+            };
+
+            queue.write_buffer(&buffer.buffer, 0, cast_slice(instances));
+            uploaded.push(GpuLevel {buffer, count: instances.len() as u32});
+        }
+
+        for level in old {
+            self.pool.release(level.buffer);
+        }
+
+        match (uploaded.is_empty(), slot) {
+            (true, Some(slot)) => self.free_slots.push(slot),
+            (true, None) => {}
+            (false, slot) => {
+                let slot: u32 = match slot {
+                    Some(slot) => slot,
+                    None => {
+                        let slot: u32 = self.acquire_slot(device, queue);
+                        self.write_origin(queue, pos, slot);
+                        slot
+                    }
+                };
+                self.chunks.insert(pos, GpuChunk {levels: uploaded, slot});
+            }
+        }
+    }
+
+    // Number of full resolution instances over all chunks
+    pub fn instance_count(&self) -> u32 {
+        self.chunks.values().map(|c| c.levels[0].count).sum()
+    }
+
+    // World bounds of every uploaded chunk, None when there are none
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.chunks.keys().map(|pos| Aabb::chunk(*pos)).reduce(|a, b| a.union(&b)).map(|bounds| bounds.transformed(self.transform))
+    }
+
+    // Changes whenever a chunk was uploaded or removed
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Bytes of instance buffers holding chunks, and of released ones kept for reuse
+    pub fn buffer_bytes(&self) -> (u64, u64) {
+        (self.pool.live_bytes(), self.pool.free_bytes())
+    }
+}
@@ -0,0 +1,368 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use anyhow::{anyhow, bail, Context, Result};
+use cgmath::{EuclideanSpace, Point3, Vector3};
+use image::RgbaImage;
+use pollster::FutureExt;
+use crate::assets::Assets;
+use crate::animation::{turntable, CameraPath, CameraPose, FrameWriter};
+use crate::camera::{Camera, CameraUniform};
+use crate::chunk::ChunkMap;
+use crate::model::{Frame, Model};
+use crate::pathtrace::{PathTracer, TraceCamera, TraceScene, TraceSettings};
+use crate::settings::WindowSettings;
+use crate::shadow::DirectionalLight;
+use crate::state::State;
+
+pub const USAGE: &str = "\
+usage: voxelart [window flags]                open the editor with the test scene
+       voxelart open <file> [window flags]    open a model in the editor
+       voxelart convert <input> <output>      convert a model between .vox, .voxelart and .glb, e.g. model.vox to model.glb
+       voxelart render <file> [options]       render a model to an image without a window
+       voxelart info <file>                   print the voxel count, bounds, layers and palette of a model
+
+render options:
+  -o, --output <image>   image to write, the model's name with .png by default
+  --camera <x,y,z>       eye position, looking at the model from the front right by default
+  --target <x,y,z>       point looked at, the model's centre by default
+  --fov <degrees>        vertical field of view, 45 by default
+  --width <pixels>       1280 by default
+  --height <pixels>      720 by default
+  --samples <count>      path trace with this many samples per pixel on the cpu instead of rasterising
+  --turntable <frames>   render an animation circling the camera once around the target
+  --path <file>          render an animation along the camera path in a file, one `seconds eye target [fov]` key per line
+  --fps <rate>           frames per second of animations, 24 by default. Animations written to an .apng file are
+                         animated pngs, to a .png file numbered images: out.png becomes out_0000.png, out_0001.png, ...
+
+window flags: --width, --height, --position x,y, --maximized, --windowed, --borderless, --fullscreen, --vsync, --no-vsync, --physical";
+
+// Exit codes, for scripts telling a mistake in the command line from a failure
+const EXIT_FAILURE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    pub output: Option<PathBuf>,
+    pub eye: Option<Point3<f32>>,
+    pub target: Option<Point3<f32>>,
+    pub fov: f32,
+    pub width: u32,
+    pub height: u32,
+    pub samples: Option<u32>,
+    pub turntable: Option<u32>, // frames of a turntable animation
+    pub path: Option<PathBuf>, // camera path file of an animation
+    pub fps: f32, // frames per second of animations
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {output: None, eye: None, target: None, fov: 45.0, width: 1280, height: 720, samples: None, turntable: None, path: None, fps: 24.0}
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Edit {file: Option<PathBuf>}, // opens a window
+    Convert {input: PathBuf, output: PathBuf},
+    Render {input: PathBuf, options: RenderOptions},
+    Info {file: PathBuf},
+    Help,
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| anyhow!("invalid {} {:?}", flag, value))
+}
+
+fn parse_point(flag: &str, value: &str) -> Result<Point3<f32>> {
+    let coordinates: Vec<f32> = value.split(',').map(|c| parse_value(flag, c.trim())).collect::<Result<_>>()?;
+    match coordinates[..] {
+        [x, y, z] => Ok(Point3::new(x, y, z)),
+        _ => bail!("invalid {} {:?}, expected x,y,z", flag, value)
+    }
+}
+
+// Exactly `count` file arguments of a command
+fn files(command: &str, args: Vec<String>, count: usize) -> Result<Vec<PathBuf>> {
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with('-')) {
+        bail!("unknown option {:?} for {}", flag, command);
+    }
+    match args.len() == count {
+        true => Ok(args.into_iter().map(PathBuf::from).collect()),
+        false => bail!("{} expects {} file{}, got {}", command, count, if count == 1 {""} else {"s"}, args.len())
+    }
+}
+
+impl Command {
+    // The command line without the program name. The editor's window flags are applied to `window`
+    pub fn parse(args: Vec<String>, window: &mut WindowSettings) -> Result<Self> {
+        let mut args = args.into_iter();
+        let command: Option<String> = args.next();
+        match command.as_deref() {
+            None => Ok(Command::Edit {file: None}),
+            Some("help" | "-h" | "--help") => Ok(Command::Help),
+            Some("open") => {
+                let rest: Vec<String> = window.apply_args(args)?;
+                Ok(Command::Edit {file: files("open", rest, 1)?.pop()})
+            }
+            Some("convert") => {
+                let mut files: Vec<PathBuf> = files("convert", args.collect(), 2)?;
+                let output: PathBuf = files.pop().unwrap();
+                Ok(Command::Convert {input: files.pop().unwrap(), output})
+            }
+            Some("info") => Ok(Command::Info {file: files("info", args.collect(), 1)?.pop().unwrap()}),
+            Some("render") => {
+                let mut options: RenderOptions = RenderOptions::default();
+                let mut input: Option<PathBuf> = None;
+                while let Some(arg) = args.next() {
+                    let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+                    match arg.as_str() {
+                        "-o" | "--output" => options.output = Some(PathBuf::from(value()?)),
+                        "--camera" => options.eye = Some(parse_point("camera position", &value()?)?),
+                        "--target" => options.target = Some(parse_point("target", &value()?)?),
+                        "--fov" => options.fov = parse_value("field of view", &value()?)?,
+                        "--width" => options.width = parse_value("width", &value()?)?,
+                        "--height" => options.height = parse_value("height", &value()?)?,
+                        "--samples" => options.samples = Some(parse_value("sample count", &value()?)?),
+                        "--turntable" => options.turntable = Some(parse_value("frame count", &value()?)?),
+                        "--path" => options.path = Some(PathBuf::from(value()?)),
+                        "--fps" => options.fps = parse_value("frame rate", &value()?)?,
+                        flag if flag.starts_with('-') => bail!("unknown option {:?} for render", flag),
+                        _ if input.is_some() => bail!("render expects 1 file, got another {:?}", arg),
+                        _ => input = Some(PathBuf::from(arg))
+                    }
+                }
+                if options.width == 0 || options.height == 0 || options.samples == Some(0) || options.turntable == Some(0) || options.fps.is_nan() || options.fps <= 0.0 {
+                    bail!("the image size, sample count, frame count and frame rate have to be positive");
+                }
+                if options.turntable.is_some() && options.path.is_some() {
+                    bail!("--turntable and --path can't be used together");
+                }
+                if !(1.0..180.0).contains(&options.fov) {
+                    bail!("the field of view has to be between 1 and 180 degrees");
+                }
+                Ok(Command::Render {input: input.context("render expects a file")?, options})
+            }
+            // window flags without a command open the editor
+            Some(flag) if flag.starts_with('-') => {
+                let rest: Vec<String> = window.apply_args(std::iter::once(flag.to_string()).chain(args))?;
+                match rest.first() {
+                    Some(arg) => bail!("unexpected argument {:?}", arg),
+                    None => Ok(Command::Edit {file: None})
+                }
+            }
+            Some(command) => bail!("unknown command {:?}", command)
+        }
+    }
+}
+
+// The camera of a render, framing the model unless the options place it
+fn render_camera(options: &RenderOptions, model: &Model) -> Camera {
+    let mut camera: Camera = Camera {
+        eye: Point3::new(1.0, 0.8, 1.5),
+        target: Point3::new(0.0, 0.0, 0.0),
+        up: Vector3::unit_y(),
+        aspect: options.width as f32 / options.height as f32,
+        fov: options.fov, near: 0.1, far: 10000.0,
+        uniform: CameraUniform::new(),
+        controller: None
+    };
+    if let Some((min, max)) = model.scene().bounds() {
+        let half: Vector3<f32> = Vector3::new(0.5, 0.5, 0.5);
+        camera.frame(Point3::from_vec(min.cast().unwrap() - half), Point3::from_vec(max.cast().unwrap() + half));
+    }
+    // a given eye keeps looking at the model's centre, and the other way around
+    camera.eye = options.eye.unwrap_or(camera.eye);
+    camera.target = options.target.unwrap_or(camera.target);
+    camera
+}
+
+// Renders a frame of a render command from a camera
+type FrameRenderer<'a> = Box<dyn FnMut(&Camera) -> Result<RgbaImage> + 'a>;
+
+fn render(input: &Path, options: &RenderOptions) -> Result<()> {
+    let model: Model = Model::load(input)?;
+    let mut camera: Camera = render_camera(options, &model);
+    let output: PathBuf = options.output.clone().unwrap_or_else(|| input.with_extension("png"));
+    let poses: Vec<CameraPose> = match (&options.path, options.turntable) {
+        (Some(path), _) => CameraPath::load(path)?.frames(options.fps),
+        (None, Some(frames)) => turntable(&camera, frames),
+        (None, None) => vec![CameraPose::from(&camera)]
+    };
+
+    // the rasteriser sets up the gpu once for every frame, the path tracer needs no gpu
+    let mut render_frame: FrameRenderer = match options.samples {
+        Some(samples) => {
+            let scene: ChunkMap = model.scene();
+            let settings: TraceSettings = TraceSettings {width: options.width, height: options.height, samples, ..Default::default()};
+            Box::new(move |camera: &Camera| {
+                let mut trace_scene: TraceScene = TraceScene::new(&scene, &model.palette);
+                trace_scene.sky.sun_direction = DirectionalLight::default().direction;
+                let mut tracer: PathTracer = PathTracer::new(trace_scene, TraceCamera::from(camera), settings);
+                tracer.render(|_| {});
+                Ok(tracer.image())
+            })
+        }
+        None => {
+            let mut state: State = State::new(None, Assets::from_env()).block_on().context("failed to set up the gpu, --samples renders on the cpu instead")?;
+            state.set_model(model)?;
+            // renders show the first frame by itself
+            state.timeline.onion_skin = false;
+            Box::new(move |camera: &Camera| {
+                CameraPose::from(camera).apply(&mut state.camera);
+                state.render_image(options.width, options.height)
+            })
+        }
+    };
+
+    if options.turntable.is_none() && options.path.is_none() {
+        let image: RgbaImage = render_frame(&camera)?;
+        return image.save(&output).with_context(|| format!("failed to write {}", output.display()));
+    }
+    let mut writer: FrameWriter = FrameWriter::create(&output, poses.len() as u32, options.fps, options.width, options.height)?;
+    for (frame, pose) in poses.iter().enumerate() {
+        pose.apply(&mut camera);
+        writer.write(frame as u32, &render_frame(&camera)?)?;
+        log::info!("rendered frame {}/{}", frame + 1, poses.len());
+    }
+    writer.finish()
+}
+
+// What `info` prints about a model
+pub fn describe(path: &Path, model: &Model) -> String {
+    let mut text: String = format!("{}\n", path.display());
+    // objects are described in the world's grid, with the layers of all of them
+    let frames: Vec<Frame> = model.flatten();
+    let scene: ChunkMap = frames.first().map_or_else(ChunkMap::new, Frame::scene);
+    text += &format!("voxels: {}\n", scene.len());
+    text += &match scene.bounds() {
+        Some((min, max)) => {
+            let size: Vector3<i32> = max - min + Vector3::new(1, 1, 1);
+            format!("bounds: {},{},{} to {},{},{} ({}x{}x{})\n", min.x, min.y, min.z, max.x, max.y, max.z, size.x, size.y, size.z)
+        }
+        None => "bounds: empty\n".to_string()
+    };
+    text += &format!("objects: {}\n", model.objects.len());
+    text += &format!("frames: {}\n", frames.len());
+    text += "layers:\n";
+    for layer in frames.first().map_or(&[][..], |frame| &frame.layers) {
+        text += &format!("  {}: {} voxels{}\n", layer.name, layer.chunks.len(), if layer.visible {""} else {", hidden"});
+    }
+
+    // entries in use with the number of visible voxels of each
+    let mut counts: Vec<usize> = vec![0; 256];
+    for (_, index) in scene.iter() {
+        counts[index as usize] += 1;
+    }
+    text += &format!("palette: {} entries, {} in use\n", model.palette.len(), counts.iter().filter(|count| **count > 0).count());
+    for (index, count) in counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+        let color: String = match model.palette.get(index) {
+            Some(entry) => [entry.color.x, entry.color.y, entry.color.z, entry.color.w].iter().map(|c| format!("{:02x}", (c.clamp(0.0, 1.0) * 255.0).round() as u8)).collect(),
+            None => "missing".to_string()
+        };
+        text += &format!("  {}: {} ({} voxels)\n", index, color, count);
+    }
+    text
+}
+
+fn execute(command: Command, window: WindowSettings) -> Result<()> {
+    match command {
+        Command::Edit {file} => pollster::block_on(crate::run(window, file)),
+        Command::Convert {input, output} => Model::load(&input)?.save(&output),
+        Command::Render {input, options} => render(&input, &options),
+        Command::Info {file} => {
+            print!("{}", describe(&file, &Model::load(&file)?));
+            Ok(())
+        }
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
+
+// Run the binary with its arguments. Mistakes in the command line exit with 2 and the usage, failures with 1
+pub fn main(args: Vec<String>) -> ExitCode {
+    let mut window: WindowSettings = WindowSettings::load();
+    let command: Command = match Command::parse(args, &mut window) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {:#}\n\n{}", e, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    match execute(command, window) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use cgmath::{Point3, Vector3, Vector4};
+    use crate::chunk::ChunkMap;
+    use crate::cli::{describe, Command, RenderOptions};
+    use crate::layers::Layer;
+    use crate::model::{Frame, Model};
+    use crate::palette::{Palette, PaletteEntry};
+    use crate::settings::{WindowMode, WindowSettings};
+
+    fn parse(args: &[&str]) -> anyhow::Result<Command> {
+        Command::parse(args.iter().map(|arg| arg.to_string()).collect(), &mut WindowSettings::default())
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse(&[]).unwrap(), Command::Edit {file: None});
+        assert_eq!(parse(&["convert", "a.vox", "b.glb"]).unwrap(), Command::Convert {input: PathBuf::from("a.vox"), output: PathBuf::from("b.glb")});
+        assert_eq!(parse(&["info", "a.vox"]).unwrap(), Command::Info {file: PathBuf::from("a.vox")});
+        assert_eq!(parse(&["render", "a.vox", "--camera", "1,2.5,-3", "-o", "out.png", "--samples", "16"]).unwrap(), Command::Render {
+            input: PathBuf::from("a.vox"),
+            options: RenderOptions {output: Some(PathBuf::from("out.png")), eye: Some(Point3::new(1.0, 2.5, -3.0)), samples: Some(16), ..Default::default()}
+        });
+
+        // window flags only belong to the editor
+        let mut window: WindowSettings = WindowSettings::default();
+        assert_eq!(Command::parse(["open", "--fullscreen", "a.vox"].map(String::from).to_vec(), &mut window).unwrap(), Command::Edit {file: Some(PathBuf::from("a.vox"))});
+        assert_eq!(window.mode, WindowMode::Fullscreen);
+        assert_eq!(parse(&["--width", "800"]).unwrap(), Command::Edit {file: None});
+
+        assert_eq!(parse(&["render", "a.vox", "--turntable", "36", "-o", "spin.apng"]).unwrap(), Command::Render {
+            input: PathBuf::from("a.vox"),
+            options: RenderOptions {output: Some(PathBuf::from("spin.apng")), turntable: Some(36), ..Default::default()}
+        });
+
+        for args in [&["render", "a.vox", "--turntable", "4", "--path", "p.txt"][..], &["render", "a.vox", "--fps", "0"], &["open"], &["convert", "a.vox"], &["info", "a.vox", "--fullscreen"], &["render"], &["render", "a.vox", "--camera", "1,2"], &["render", "a.vox", "--width"], &["frobnicate"], &["--width", "800", "a.vox"]] {
+            assert!(parse(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_describe_model() {
+        let mut chunks: ChunkMap = ChunkMap::new();
+        chunks.set(Vector3::new(-1, 0, 2), Some(1));
+        chunks.set(Vector3::new(3, 4, 2), Some(1));
+        let mut hidden: Layer = Layer::new("Hidden", ChunkMap::new());
+        hidden.chunks.set(Vector3::new(10, 10, 10), Some(0));
+        hidden.visible = false;
+        let palette: Palette = Palette::new(vec![PaletteEntry::new(Vector4::new(1.0, 1.0, 1.0, 1.0)), PaletteEntry::new(Vector4::new(1.0, 0.0, 0.5, 1.0))]);
+        let model: Model = Model::new(vec![Frame::new(vec![Layer::new("Base", chunks), hidden])], palette);
+
+        assert_eq!(describe(Path::new("a.vox"), &model), "\
+a.vox
+voxels: 2
+bounds: -1,0,2 to 3,4,2 (5x5x1)
+objects: 1
+frames: 1
+layers:
+  Base: 2 voxels
+  Hidden: 1 voxels, hidden
+palette: 2 entries, 1 in use
+  1: ff0080ff (2 voxels)
+");
+    }
+}
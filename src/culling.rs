@@ -0,0 +1,148 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Point3, Vector3, Vector4};
+use crate::chunk::{ChunkPos, CHUNK_SIZE};
+
+// Number of detail levels per chunk, every level halves the resolution (1x, 2x, 4x voxels)
+pub const LOD_LEVELS: usize = 3;
+
+// Axis aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    // Bounds of all cubes in a chunk, voxels are centered on their integer position
+    pub fn chunk(pos: ChunkPos) -> Self {
+        let min: Vector3<f32> = (pos * CHUNK_SIZE).cast::<f32>().unwrap() - Vector3::new(0.5, 0.5, 0.5);
+        Self {min, max: min + Vector3::new(CHUNK_SIZE as f32, CHUNK_SIZE as f32, CHUNK_SIZE as f32)}
+    }
+
+    // Smallest box containing both boxes
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: Vector3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Vector3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z))
+        }
+    }
+
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| Vector3::new(
+            if i & 1 == 0 {self.min.x} else {self.max.x},
+            if i & 2 == 0 {self.min.y} else {self.max.y},
+            if i & 4 == 0 {self.min.z} else {self.max.z},
+        ))
+    }
+
+    // Box around this one moved by `matrix`
+    pub fn transformed(&self, matrix: Matrix4<f32>) -> Self {
+        let corners: [Vector3<f32>; 8] = self.corners().map(|corner| (matrix * corner.extend(1.0)).truncate());
+        corners.iter().fold(Self {min: corners[0], max: corners[0]}, |aabb, corner| aabb.union(&Self {min: *corner, max: *corner}))
+    }
+
+    // Distance from a point to the closest point of the box, 0 when inside
+    pub fn distance(&self, point: Point3<f32>) -> f32 {
+        let closest: Vector3<f32> = Vector3::new(point.x.clamp(self.min.x, self.max.x), point.y.clamp(self.min.y, self.max.y), point.z.clamp(self.min.z, self.max.z));
+        (Vector3::new(point.x, point.y, point.z) - closest).magnitude()
+    }
+}
+
+// The six planes of a view frustum, pointing inwards
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // Extract the planes from a wgpu view projection matrix (clip space depth in 0..1)
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let (r0, r1, r2, r3) = (view_proj.row(0), view_proj.row(1), view_proj.row(2), view_proj.row(3));
+        let planes: [Vector4<f32>; 6] = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2];
+        Self {planes: planes.map(|p| p / p.truncate().magnitude())}
+    }
+
+    // Conservative test, boxes that straddle a corner outside the frustum may still pass
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane normal
+            let corner: Vector3<f32> = Vector3::new(
+                if plane.x >= 0.0 {aabb.max.x} else {aabb.min.x},
+                if plane.y >= 0.0 {aabb.max.y} else {aabb.min.y},
+                if plane.z >= 0.0 {aabb.max.z} else {aabb.min.z},
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+// Distances at which chunks switch to the next lower level of detail
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodSettings {
+    pub distances: [f32; LOD_LEVELS - 1],
+}
+
+impl LodSettings {
+    pub fn level(&self, distance: f32) -> usize {
+        self.distances.iter().take_while(|d| distance >= **d).count()
+    }
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {distances: [256.0, 512.0]}
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Culled,
+    Visible(usize), // level of detail to draw the chunk with
+}
+
+// Decide whether and at which detail a chunk is drawn
+pub fn classify(frustum: &Frustum, eye: Point3<f32>, lod: &LodSettings, pos: ChunkPos) -> Visibility {
+    let aabb: Aabb = Aabb::chunk(pos);
+    if !frustum.intersects(&aabb) {
+        return Visibility::Culled;
+    }
+    Visibility::Visible(lod.level(aabb.distance(eye)))
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, Vector3};
+    use crate::camera::{Camera, CameraUniform};
+    use crate::chunk::CHUNK_SIZE;
+    use crate::culling::{classify, Frustum, LodSettings, Visibility};
+
+    fn camera(eye: Point3<f32>, target: Point3<f32>) -> Camera {
+        Camera {eye, target, up: Vector3::unit_y(), aspect: 1.0, fov: 90.0, near: 0.1, far: 1000.0, uniform: CameraUniform::new(), controller: None}
+    }
+
+    #[test]
+    fn test_chunks_outside_the_frustum_are_culled() {
+        // looking down -z from the middle of chunk (0, 0, 0)
+        let eye: Point3<f32> = Point3::new(16.0, 16.0, 16.0);
+        let camera: Camera = camera(eye, Point3::new(16.0, 16.0, -100.0));
+        let frustum: Frustum = Frustum::from_matrix(camera.build_view_projection_matrix());
+        let lod: LodSettings = LodSettings::default();
+
+        assert_eq!(classify(&frustum, eye, &lod, Vector3::new(0, 0, 0)), Visibility::Visible(0));
+        assert_eq!(classify(&frustum, eye, &lod, Vector3::new(0, 0, -2)), Visibility::Visible(0));
+        assert_eq!(classify(&frustum, eye, &lod, Vector3::new(0, 0, 2)), Visibility::Culled); // behind
+        assert_eq!(classify(&frustum, eye, &lod, Vector3::new(5, 0, -1)), Visibility::Culled); // outside the 90 degree cone
+        assert_eq!(classify(&frustum, eye, &lod, Vector3::new(0, 0, -40)), Visibility::Culled); // beyond the far plane
+    }
+
+    #[test]
+    fn test_distant_chunks_use_lower_detail() {
+        let eye: Point3<f32> = Point3::new(16.0, 16.0, 16.0);
+        let camera: Camera = camera(eye, Point3::new(16.0, 16.0, -100.0));
+        let frustum: Frustum = Frustum::from_matrix(camera.build_view_projection_matrix());
+        let lod: LodSettings = LodSettings::default();
+
+        assert_eq!(classify(&frustum, eye, &lod, Vector3::new(0, 0, -256 / CHUNK_SIZE + 1)), Visibility::Visible(0));
+        assert_eq!(classify(&frustum, eye, &lod, Vector3::new(0, 0, -256 / CHUNK_SIZE - 1)), Visibility::Visible(1));
+        assert_eq!(classify(&frustum, eye, &lod, Vector3::new(0, 0, -512 / CHUNK_SIZE - 1)), Visibility::Visible(2));
+    }
+}
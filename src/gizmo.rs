@@ -0,0 +1,317 @@
+use std::f32::consts::TAU;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+use crate::camera::Camera;
+use crate::hud::HudBatch;
+
+// Length of the handles in window pixels, at a gui scale of 1
+pub const GIZMO_SIZE: f32 = 60.0;
+// Smallest scale an object can be dragged down to
+pub const MIN_OBJECT_SCALE: f32 = 0.05;
+// Largest factor voxels can be stretched by, every voxel becomes this many
+pub const MAX_VOXEL_SCALE: f32 = 8.0;
+
+const AXIS_COLORS: [[f32; 4]; 3] = [[0.9, 0.25, 0.25, 1.0], [0.3, 0.85, 0.3, 1.0], [0.3, 0.5, 1.0, 1.0]];
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const RING_SEGMENTS: usize = 48;
+
+// What dragging a handle does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    pub const ALL: [GizmoMode; 3] = [GizmoMode::Translate, GizmoMode::Rotate, GizmoMode::Scale];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GizmoMode::Translate => "MOVE",
+            GizmoMode::Rotate => "TURN",
+            GizmoMode::Scale => "SCALE"
+        }
+    }
+}
+
+fn unit(axis: usize) -> Vector3<f32> {
+    let mut unit: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0);
+    unit[axis] = 1.0;
+    unit
+}
+
+// The other two axes, in the order that turns counterclockwise around `axis`
+fn perpendicular(axis: usize) -> (Vector3<f32>, Vector3<f32>) {
+    (unit((axis + 1) % 3), unit((axis + 2) % 3))
+}
+
+fn distance_to_segment(point: [f32; 2], from: [f32; 2], to: [f32; 2]) -> f32 {
+    let (dx, dy): (f32, f32) = (to[0] - from[0], to[1] - from[1]);
+    let length: f32 = dx * dx + dy * dy;
+    let t: f32 = if length == 0.0 {0.0} else {(((point[0] - from[0]) * dx + (point[1] - from[1]) * dy) / length).clamp(0.0, 1.0)};
+    let (x, y): (f32, f32) = (from[0] + t * dx - point[0], from[1] + t * dy - point[1]);
+    (x * x + y * y).sqrt()
+}
+
+// Handles along the three axes of a space, for moving, turning or scaling what sits in it. The gizmo is drawn over
+// the scene at the same size on screen however far away it is, and picked where it is drawn
+#[derive(Clone, Copy, Debug)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub space: Matrix4<f32>, // from the gizmo's space to the world, without shearing or stretching
+    pub center: Vector3<f32>, // in the gizmo's space
+    pub size: f32, // length of the handles in window pixels
+}
+
+impl Gizmo {
+    // Window position of a point in the gizmo's space, None behind the camera
+    fn project(&self, view_proj: Matrix4<f32>, screen: [f32; 2], point: Vector3<f32>) -> Option<[f32; 2]> {
+        let clip: Vector4<f32> = view_proj * self.space * point.extend(1.0);
+        (clip.w > 1e-4).then(|| [(clip.x / clip.w * 0.5 + 0.5) * screen[0], (0.5 - clip.y / clip.w * 0.5) * screen[1]])
+    }
+
+    // Length in the gizmo's space that covers `size` pixels at its centre
+    fn length(&self, camera: &Camera, screen: [f32; 2]) -> f32 {
+        let center: Vector3<f32> = (self.space * self.center.extend(1.0)).truncate();
+        let distance: f32 = (center - camera.eye.to_vec()).magnitude();
+        let world: f32 = self.size * 2.0 * distance * (camera.fov.to_radians() / 2.0).tan() / screen[1];
+        world / self.space.x.truncate().magnitude()
+    }
+
+    // The line of every handle in the gizmo's space, along its axis or for turning a ring around it
+    fn handles(&self, length: f32) -> [Vec<Vector3<f32>>; 3] {
+        [0, 1, 2].map(|axis| match self.mode {
+            GizmoMode::Rotate => {
+                let (u, v): (Vector3<f32>, Vector3<f32>) = perpendicular(axis);
+                (0..=RING_SEGMENTS).map(|i| {
+                    let angle: f32 = i as f32 / RING_SEGMENTS as f32 * TAU;
+                    self.center + (u * angle.cos() + v * angle.sin()) * length
+                }).collect()
+            }
+            _ => vec![self.center, self.center + unit(axis) * length]
+        })
+    }
+
+    // The handles as lines in the window, parts behind the camera left out
+    fn projected(&self, camera: &Camera, screen: [f32; 2]) -> [Vec<Option<[f32; 2]>>; 3] {
+        let view_proj: Matrix4<f32> = camera.build_view_projection_matrix();
+        self.handles(self.length(camera, screen)).map(|points| points.into_iter().map(|point| self.project(view_proj, screen, point)).collect())
+    }
+
+    // The axis of the handle closest to the cursor, when it is near enough to grab
+    pub fn pick(&self, camera: &Camera, screen: [f32; 2], cursor: [f32; 2]) -> Option<usize> {
+        let reach: f32 = self.size / 8.0;
+        self.projected(camera, screen).iter().enumerate().filter_map(|(axis, points)| {
+            points.windows(2).filter_map(|pair| Some(distance_to_segment(cursor, pair[0]?, pair[1]?))).reduce(f32::min).map(|distance| (axis, distance))
+        }).filter(|(_, distance)| *distance <= reach).min_by(|a, b| a.1.total_cmp(&b.1)).map(|(axis, _)| axis)
+    }
+
+    // Where the cursor is along a handle: the distance from the centre along the axis for moving and scaling, the
+    // angle around the axis in degrees for turning. None when the handle is seen end on or edge on
+    pub fn measure(&self, camera: &Camera, screen: [f32; 2], axis: usize, cursor: [f32; 2]) -> Option<f32> {
+        let (origin, direction) = camera.ray(cursor[0], cursor[1], screen[0], screen[1]);
+        let inverse: Matrix4<f32> = self.space.invert()?;
+        let (origin, direction): (Vector3<f32>, Vector3<f32>) = ((inverse * origin.to_homogeneous()).truncate(), (inverse * direction.extend(0.0)).truncate());
+        let axis_direction: Vector3<f32> = unit(axis);
+        match self.mode {
+            GizmoMode::Rotate => {
+                // where the ray crosses the plane of the ring
+                let along: f32 = direction.dot(axis_direction);
+                if along.abs() < 1e-3 * direction.magnitude() {
+                    return None;
+                }
+                let offset: Vector3<f32> = origin + direction * ((self.center - origin).dot(axis_direction) / along) - self.center;
+                let (u, v): (Vector3<f32>, Vector3<f32>) = perpendicular(axis);
+                Some(offset.dot(v).atan2(offset.dot(u)).to_degrees())
+            }
+            _ => {
+                // the point of the axis closest to the ray
+                let to_origin: Vector3<f32> = origin - self.center;
+                let (b, c): (f32, f32) = (axis_direction.dot(direction), direction.dot(direction));
+                let denominator: f32 = c - b * b;
+                if denominator < 1e-4 * c {
+                    return None;
+                }
+                Some((c * axis_direction.dot(to_origin) - b * direction.dot(to_origin)) / denominator)
+            }
+        }
+    }
+
+    // Add the handles to `batch`, with `highlight` lit up. Arrow heads move, squares scale and rings turn
+    pub fn draw(&self, batch: &mut HudBatch, camera: &Camera, screen: [f32; 2], highlight: Option<usize>) {
+        let (width, head): (f32, f32) = (self.size / 30.0, self.size / 6.0);
+        for (axis, points) in self.projected(camera, screen).iter().enumerate() {
+            let color: [f32; 4] = if highlight == Some(axis) {HIGHLIGHT_COLOR} else {AXIS_COLORS[axis]};
+            for pair in points.windows(2) {
+                if let [Some(from), Some(to)] = pair {
+                    batch.line(*from, *to, width, color);
+                }
+            }
+            let (Some(Some(from)), Some(Some(to))) = (points.first(), points.last()) else {continue};
+            let (dx, dy): (f32, f32) = (to[0] - from[0], to[1] - from[1]);
+            let length: f32 = (dx * dx + dy * dy).sqrt();
+            match self.mode {
+                // an axis pointing at the camera has no direction on screen to point the head in
+                GizmoMode::Translate if length > 1.0 => {
+                    let (x, y): (f32, f32) = (dx / length * head, dy / length * head);
+                    batch.triangle([[to[0] + x, to[1] + y], [to[0] - y / 3.0, to[1] + x / 3.0], [to[0] + y / 3.0, to[1] - x / 3.0]], color);
+                }
+                GizmoMode::Scale => batch.rect(to[0] - head / 3.0, to[1] - head / 3.0, head * 2.0 / 3.0, head * 2.0 / 3.0, color),
+                _ => {}
+            }
+        }
+    }
+}
+
+// A handle being dragged, measuring how far it went since it was grabbed
+#[derive(Clone, Copy, Debug)]
+pub struct GizmoDrag {
+    pub gizmo: Gizmo, // as it was when grabbed, so the whole drag is measured in the same place
+    pub axis: usize,
+    start: f32, // measure when grabbed
+    last: f32,
+    pub amount: f32, // distance moved, degrees turned or factor scaled by
+}
+
+impl GizmoDrag {
+    pub fn new(gizmo: Gizmo, axis: usize, start: f32) -> Self {
+        let amount: f32 = if gizmo.mode == GizmoMode::Scale {1.0} else {0.0};
+        Self {gizmo, axis, start, last: start, amount}
+    }
+
+    // Follow the cursor to a new measure along the handle
+    pub fn update(&mut self, measure: f32) {
+        self.amount = match self.gizmo.mode {
+            GizmoMode::Translate => measure - self.start,
+            // turns add up past half a turn, the measured angle wraps around
+            GizmoMode::Rotate => self.amount + (measure - self.last + 540.0).rem_euclid(360.0) - 180.0,
+            GizmoMode::Scale if self.start.abs() > 1e-3 => measure / self.start,
+            GizmoMode::Scale => 1.0
+        };
+        self.last = measure;
+    }
+
+    // The amount in whole voxel steps. Voxels turn by quarter turns and grow by whole factors, objects turn by any
+    // angle and scale freely
+    pub fn snapped(&self, voxels: bool) -> f32 {
+        match (self.gizmo.mode, voxels) {
+            (GizmoMode::Translate, _) => self.amount.round(),
+            (GizmoMode::Rotate, true) => (self.amount / 90.0).round() * 90.0,
+            (GizmoMode::Rotate, false) => self.amount,
+            (GizmoMode::Scale, true) => self.amount.round().clamp(1.0, MAX_VOXEL_SCALE),
+            (GizmoMode::Scale, false) => self.amount.max(MIN_OBJECT_SCALE)
+        }
+    }
+}
+
+// Voxels moved along `axis` by whole steps, turned around it by quarter turns or stretched along it by a whole
+// factor, around `center`
+pub fn transform_voxels(voxels: &[(Vector3<i32>, u8)], mode: GizmoMode, axis: usize, amount: f32, center: Vector3<i32>) -> Vec<(Vector3<i32>, u8)> {
+    match mode {
+        GizmoMode::Translate => {
+            let mut offset: Vector3<i32> = Vector3::new(0, 0, 0);
+            offset[axis] = amount.round() as i32;
+            voxels.iter().map(|(position, index)| (position + offset, *index)).collect()
+        }
+        GizmoMode::Rotate => {
+            let turns: i32 = ((amount / 90.0).round() as i32).rem_euclid(4);
+            let quarter_turn = |p: Vector3<i32>| match axis {
+                0 => Vector3::new(p.x, -p.z, p.y),
+                1 => Vector3::new(p.z, p.y, -p.x),
+                _ => Vector3::new(-p.y, p.x, p.z)
+            };
+            voxels.iter().map(|(position, index)| ((0..turns).fold(position - center, |p, _| quarter_turn(p)) + center, *index)).collect()
+        }
+        GizmoMode::Scale => {
+            let factor: i32 = amount.round().clamp(1.0, MAX_VOXEL_SCALE) as i32;
+            voxels.iter().flat_map(|(position, index)| (0..factor).map(move |step| {
+                let mut stretched: Vector3<i32> = *position;
+                stretched[axis] = center[axis] + (position[axis] - center[axis]) * factor + step;
+                (stretched, *index)
+            })).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix4, Point3, SquareMatrix, Vector3};
+    use crate::camera::{Camera, CameraUniform};
+    use crate::gizmo::{transform_voxels, Gizmo, GizmoDrag, GizmoMode};
+    use crate::hud::HudBatch;
+
+    const SCREEN: [f32; 2] = [400.0, 400.0];
+
+    fn camera(eye: Point3<f32>) -> Camera {
+        Camera {eye, target: Point3::new(0.0, 0.0, 0.0), up: Vector3::unit_y(), aspect: 1.0, fov: 60.0, near: 0.1, far: 1000.0, uniform: CameraUniform::new(), controller: None}
+    }
+
+    fn gizmo(mode: GizmoMode) -> Gizmo {
+        Gizmo {mode, space: Matrix4::identity(), center: Vector3::new(0.0, 0.0, 0.0), size: 60.0}
+    }
+
+    #[test]
+    fn test_handles_keep_their_size_on_screen() {
+        let gizmo: Gizmo = gizmo(GizmoMode::Translate);
+        for distance in [10.0, 200.0] {
+            let camera: Camera = camera(Point3::new(0.0, 0.0, distance));
+            let [center, end] = [0.0, 1.0].map(|t| gizmo.project(camera.build_view_projection_matrix(), SCREEN, Vector3::new(t * gizmo.length(&camera, SCREEN), 0.0, 0.0)).unwrap());
+            assert!((end[0] - center[0] - 60.0).abs() < 0.5, "{:?} {:?}", center, end);
+        }
+
+        // handles are grabbed where they are drawn, the x axis runs right from the middle of the window
+        let camera: Camera = camera(Point3::new(0.0, 0.0, 50.0));
+        assert_eq!(gizmo.pick(&camera, SCREEN, [230.0, 203.0]), Some(0));
+        assert_eq!(gizmo.pick(&camera, SCREEN, [202.0, 170.0]), Some(1));
+        assert_eq!(gizmo.pick(&camera, SCREEN, [300.0, 300.0]), None);
+        let mut batch: HudBatch = HudBatch::default();
+        gizmo.draw(&mut batch, &camera, SCREEN, Some(0));
+        assert!(!batch.is_empty());
+    }
+
+    #[test]
+    fn test_drags_snap_for_voxels() {
+        let camera: Camera = camera(Point3::new(0.0, 0.0, 50.0));
+        let translate: Gizmo = gizmo(GizmoMode::Translate);
+        let start: f32 = translate.measure(&camera, SCREEN, 0, [230.0, 200.0]).unwrap();
+        let mut drag: GizmoDrag = GizmoDrag::new(translate, 0, start);
+        drag.update(translate.measure(&camera, SCREEN, 0, [260.0, 230.0]).unwrap());
+        assert!(drag.amount > 0.0);
+        assert_eq!(drag.snapped(true), drag.amount.round());
+        // the z axis points at the camera, there is nowhere along it to drag to
+        assert_eq!(translate.measure(&camera, SCREEN, 2, [200.0, 200.0]), None);
+
+        // turning keeps counting past half a turn, voxels turn by quarter turns
+        let mut drag: GizmoDrag = GizmoDrag::new(gizmo(GizmoMode::Rotate), 2, 100.0);
+        drag.update(170.0);
+        drag.update(-140.0);
+        assert!((drag.amount - 120.0).abs() < 1e-3, "{}", drag.amount);
+        assert_eq!((drag.snapped(true), drag.snapped(false)), (90.0, drag.amount));
+
+        let mut drag: GizmoDrag = GizmoDrag::new(gizmo(GizmoMode::Scale), 0, 4.0);
+        drag.update(-1.0);
+        assert_eq!((drag.snapped(true), drag.snapped(false)), (1.0, 0.05));
+        drag.update(10.0);
+        assert_eq!(drag.snapped(true), 3.0);
+
+        // grabbed right next to the centre, a small move makes a huge factor that voxels can't follow
+        let mut drag: GizmoDrag = GizmoDrag::new(gizmo(GizmoMode::Scale), 0, 0.002);
+        drag.update(5.0);
+        assert!(drag.amount > 1000.0);
+        assert_eq!(drag.snapped(true), 8.0);
+        let voxels: Vec<(Vector3<i32>, u8)> = vec![(Vector3::new(0, 0, 0), 1)];
+        assert_eq!(transform_voxels(&voxels, GizmoMode::Scale, 0, drag.amount, Vector3::new(0, 0, 0)).len(), 8);
+    }
+
+    #[test]
+    fn test_voxels_turn_and_stretch_on_the_grid() {
+        let voxels: Vec<(Vector3<i32>, u8)> = vec![(Vector3::new(1, 0, 0), 1), (Vector3::new(2, 0, 0), 2)];
+        let center: Vector3<i32> = Vector3::new(1, 0, 0);
+        assert_eq!(transform_voxels(&voxels, GizmoMode::Translate, 1, -2.0, center), vec![(Vector3::new(1, -2, 0), 1), (Vector3::new(2, -2, 0), 2)]);
+        assert_eq!(transform_voxels(&voxels, GizmoMode::Rotate, 2, 90.0, center), vec![(Vector3::new(1, 0, 0), 1), (Vector3::new(1, 1, 0), 2)]);
+        assert_eq!(transform_voxels(&voxels, GizmoMode::Rotate, 1, -360.0, center), voxels);
+        assert_eq!(transform_voxels(&voxels, GizmoMode::Scale, 0, 2.0, center), vec![
+            (Vector3::new(1, 0, 0), 1), (Vector3::new(2, 0, 0), 1), (Vector3::new(3, 0, 0), 2), (Vector3::new(4, 0, 0), 2)
+        ]);
+    }
+}
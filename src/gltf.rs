@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use cgmath::{ElementWise, Vector3};
+use crate::chunk::ChunkMap;
+use crate::palette::{Palette, PaletteEntry};
+
+// glTF binary export: one mesh with a primitive and a material per palette entry in use.
+// Every visible voxel face is a quad, faces between voxels are left out like on the gpu
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+// The faces of a single palette entry
+#[derive(Default)]
+struct Primitive {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl Primitive {
+    // A quad facing `normal`, wound counter-clockwise seen from outside
+    fn face(&mut self, position: Vector3<i32>, normal: Vector3<i32>) {
+        let axis: usize = (0..3).find(|i| normal[*i] != 0).unwrap();
+        let (mut u, mut v): (Vector3<f32>, Vector3<f32>) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+        u[(axis + 1) % 3] = 0.5;
+        v[(axis + 2) % 3] = 0.5;
+        if normal[axis] < 0 {
+            std::mem::swap(&mut u, &mut v);
+        }
+        let normal: Vector3<f32> = normal.cast().unwrap();
+        let center: Vector3<f32> = position.cast::<f32>().unwrap() + normal * 0.5;
+
+        let first: u32 = self.positions.len() as u32;
+        for corner in [-u - v, u - v, u + v, v - u] {
+            self.positions.push((center + corner).into());
+            self.normals.push(normal.into());
+        }
+        self.indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+    }
+}
+
+fn material(index: u8, entry: &PaletteEntry) -> String {
+    let [r, g, b, _]: [f32; 4] = entry.color.into();
+    let material = &entry.material;
+    let mut json: String = format!(r#"{{"name":"entry {}","pbrMetallicRoughness":{{"baseColorFactor":[{},{},{},{}],"metallicFactor":{},"roughnessFactor":{}}}"#, index, r, g, b, entry.opacity(), material.metalness, material.roughness);
+    if material.emission > 0.0 {
+        let emissive: Vector3<f32> = Vector3::new(r, g, b).mul_element_wise(material.emission.min(1.0));
+        json += &format!(r#","emissiveFactor":[{},{},{}]"#, emissive.x, emissive.y, emissive.z);
+        // emission past the colour needs the strength extension
+        if material.emission > 1.0 {
+            json += &format!(r#","extensions":{{"KHR_materials_emissive_strength":{{"emissiveStrength":{}}}}}"#, material.emission);
+        }
+    }
+    if entry.is_see_through() {
+        json += r#","alphaMode":"BLEND""#;
+    }
+    json + "}"
+}
+
+// The voxels of `scene` as a .glb file, voxel p spans p - 0.5 to p + 0.5 like in the viewer
+pub fn to_glb(scene: &ChunkMap, palette: &Palette) -> Vec<u8> {
+    let see_through: Vec<bool> = palette.see_through();
+    let offsets: [Vector3<i32>; 6] = [Vector3::unit_x(), -Vector3::unit_x(), Vector3::unit_y(), -Vector3::unit_y(), Vector3::unit_z(), -Vector3::unit_z()];
+    let mut primitives: BTreeMap<u8, Primitive> = BTreeMap::new();
+    for (position, index) in scene.iter() {
+        for normal in offsets {
+            let covered: bool = scene.get(position + normal).is_some_and(|neighbour| neighbour == index || !see_through[neighbour as usize]);
+            if !covered {
+                primitives.entry(index).or_default().face(position, normal);
+            }
+        }
+    }
+
+    // every attribute gets its own view of the binary chunk, they are all multiples of 4 bytes long
+    let mut binary: Vec<u8> = Vec::new();
+    let (mut views, mut accessors, mut materials, mut meshes): (Vec<String>, Vec<String>, Vec<String>, Vec<String>) = Default::default();
+    let mut emissive_strength: bool = false;
+    for (index, primitive) in &primitives {
+        let mut view = |bytes: &[u8], target: u32| {
+            views.push(format!(r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#, binary.len(), bytes.len(), target));
+            binary.extend_from_slice(bytes);
+            views.len() - 1
+        };
+        let position: usize = view(bytemuck::cast_slice(&primitive.positions), ARRAY_BUFFER);
+        let normal: usize = view(bytemuck::cast_slice(&primitive.normals), ARRAY_BUFFER);
+        let indices: usize = view(bytemuck::cast_slice(&primitive.indices), ELEMENT_ARRAY_BUFFER);
+
+        let (min, max): ([f32; 3], [f32; 3]) = primitive.positions.iter().fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), p| ([0, 1, 2].map(|i| min[i].min(p[i])), [0, 1, 2].map(|i| max[i].max(p[i]))));
+        let count: usize = primitive.positions.len();
+        accessors.push(format!(r#"{{"bufferView":{},"componentType":{},"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#, position, FLOAT, count, min[0], min[1], min[2], max[0], max[1], max[2]));
+        accessors.push(format!(r#"{{"bufferView":{},"componentType":{},"count":{},"type":"VEC3"}}"#, normal, FLOAT, count));
+        accessors.push(format!(r#"{{"bufferView":{},"componentType":{},"count":{},"type":"SCALAR"}}"#, indices, UNSIGNED_INT, primitive.indices.len()));
+
+        // voxels without a palette entry export black like on the gpu
+        let entry: PaletteEntry = palette.get(*index as usize).copied().unwrap_or(PaletteEntry::new(cgmath::Vector4::new(0.0, 0.0, 0.0, 0.0)));
+        emissive_strength |= entry.material.emission > 1.0;
+        materials.push(material(*index, &entry));
+        let first: usize = accessors.len() - 3;
+        meshes.push(format!(r#"{{"attributes":{{"POSITION":{},"NORMAL":{}}},"indices":{},"material":{}}}"#, first, first + 1, first + 2, materials.len() - 1));
+    }
+
+    let mut json: String = r#"{"asset":{"version":"2.0","generator":"voxelart"},"scene":0,"scenes":[{"nodes":[0]}]"#.to_string();
+    match primitives.is_empty() {
+        // a mesh needs at least one primitive
+        true => json += r#","nodes":[{"name":"voxels"}]"#,
+        false => {
+            json += &format!(r#","nodes":[{{"name":"voxels","mesh":0}}],"meshes":[{{"name":"voxels","primitives":[{}]}}]"#, meshes.join(","));
+            json += &format!(r#","materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}}}]"#, materials.join(","), accessors.join(","), views.join(","), binary.len());
+        }
+    }
+    if emissive_strength {
+        json += r#","extensionsUsed":["KHR_materials_emissive_strength"]"#;
+    }
+    json += "}";
+
+    // chunks are padded to 4 bytes, the json with spaces
+    let mut json: Vec<u8> = json.into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    binary.resize(binary.len().next_multiple_of(4), 0);
+
+    let chunks: usize = 8 + json.len() + if binary.is_empty() {0} else {8 + binary.len()};
+    let mut glb: Vec<u8> = Vec::with_capacity(12 + chunks);
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&(12 + chunks as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+    if !binary.is_empty() {
+        glb.extend_from_slice(&(binary.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&binary);
+    }
+    glb
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Vector4};
+    use crate::chunk::ChunkMap;
+    use crate::gltf::to_glb;
+    use crate::palette::{Palette, PaletteEntry};
+
+    #[test]
+    fn test_glb_leaves_out_covered_faces() {
+        let mut scene: ChunkMap = ChunkMap::new();
+        scene.set(Vector3::new(0, 0, 0), Some(0));
+        scene.set(Vector3::new(1, 0, 0), Some(0));
+        scene.set(Vector3::new(0, 1, 0), Some(1));
+        let palette: Palette = Palette::new(vec![PaletteEntry::new(Vector4::new(1.0, 0.0, 0.0, 1.0)), PaletteEntry::new(Vector4::new(0.0, 0.0, 1.0, 0.5))]);
+        let glb: Vec<u8> = to_glb(&scene, &palette);
+
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize, glb.len());
+        let json_length: usize = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        let json: &str = std::str::from_utf8(&glb[20..20 + json_length]).unwrap();
+        assert_eq!(&glb[24 + json_length..28 + json_length], b"BIN\0");
+
+        // the opaque pair loses the faces between them, the see-through voxel the face on the opaque one below it
+        assert!(json.contains(r#""count":40,"type":"VEC3","min":[-0.5,-0.5,-0.5],"max":[1.5,0.5,0.5]"#), "{}", json);
+        assert!(json.contains(r#""count":20,"type":"VEC3","min":[-0.5,0.5,-0.5],"max":[0.5,1.5,0.5]"#), "{}", json);
+        assert!(json.contains(r#""baseColorFactor":[0,0,1,0.5],"metallicFactor":0,"roughnessFactor":1},"alphaMode":"BLEND""#), "{}", json);
+
+        let empty: Vec<u8> = to_glb(&ChunkMap::new(), &palette);
+        assert_eq!(u32::from_le_bytes(empty[8..12].try_into().unwrap()) as usize, empty.len());
+    }
+}
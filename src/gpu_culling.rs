@@ -0,0 +1,279 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{Matrix4, Point3};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, CommandEncoder, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, DownlevelCapabilities, DownlevelFlags, MapMode, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor, ShaderSource, ShaderStages};
+use wgpu::util::DrawIndexedIndirect;
+use crate::assets::Assets;
+use crate::chunk::ChunkPos;
+use crate::chunk_buffers::ChunkBuffers;
+use crate::culling::{Aabb, Frustum, LodSettings, LOD_LEVELS};
+use crate::utils::create_wgpu_buffer;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    eye: [f32; 4],
+    lod_distances: [f32; 4],
+    chunk_count: u32,
+    index_count: u32,
+    _pad: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+struct ChunkInfo {
+    min: [f32; 4],
+    max: [f32; 4],
+    counts: [u32; 4], // instances per level of detail
+}
+
+// Frustum culling and level of detail selection on the gpu. A compute pass tests every chunk and
+// writes the indirect draw arguments of all its levels, levels that shouldn't be drawn get 0 instances.
+// It also counts the chunks drawn, read back a frame or two later for the stats.
+// Only frustum culling is done, there is no occlusion culling
+pub struct GpuCuller {
+    pipeline: ComputePipeline,
+    bind_group_layout: BindGroupLayout,
+    uniform_buffer: Buffer,
+    info_buffer: Buffer,
+    pub indirect_buffer: Buffer,
+    bind_group: BindGroup,
+    drawn_buffer: Buffer,
+    drawn_readback: Buffer,
+    drawn_copied: bool, // the count was copied for readback this frame
+    drawn_mapping: Option<Arc<AtomicBool>>, // set once the readback buffer is mapped
+    drawn: u32,
+    capacity: usize, // chunks that fit in the info and indirect buffers
+    index_count: u32,
+
+    pub order: Vec<ChunkPos>, // chunk of every slot in the indirect buffer
+    generation: Option<u64>, // ChunkBuffers generation the slots were built from
+}
+
+impl GpuCuller {
+    // Indirect draws and compute shaders aren't available on every downlevel backend
+    pub fn supported(capabilities: &DownlevelCapabilities) -> bool {
+        capabilities.flags.contains(DownlevelFlags::COMPUTE_SHADERS | DownlevelFlags::INDIRECT_EXECUTION)
+    }
+
+    pub fn new(device: &Device, assets: &Assets, index_count: u32) -> anyhow::Result<Self> {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {label: Some("Cull Shader Module"), source: ShaderSource::Wgsl(assets.load_shader("cull.wgsl")?.into())});
+
+        let storage = |binding: u32, read_only: bool| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {ty: BufferBindingType::Storage {read_only}, has_dynamic_offset: false, min_binding_size: None},
+            count: None
+        };
+        let bind_group_layout: BindGroupLayout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {ty: BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None},
+                    count: None
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+            ],
+            label: Some("Cull Bind Group Layout")
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {label: Some("Cull Pipeline Layout"), bind_group_layouts: &[&bind_group_layout], push_constant_ranges: &[]});
+        let pipeline: ComputePipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {label: Some("Cull Pipeline"), layout: Some(&layout), module: &shader, entry_point: "cs_main"});
+
+        let uniform_buffer: Buffer = create_wgpu_buffer(device, Some("Cull Buffer"), cast_slice(&[CullUniform::default()]), BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let (info_buffer, indirect_buffer) = Self::create_buffers(device, 1);
+        let drawn_buffer: Buffer = device.create_buffer(&BufferDescriptor {label: Some("Drawn Chunks Buffer"), size: 4, usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST, mapped_at_creation: false});
+        let drawn_readback: Buffer = device.create_buffer(&BufferDescriptor {label: Some("Drawn Chunks Readback Buffer"), size: 4, usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST, mapped_at_creation: false});
+        let bind_group: BindGroup = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, &info_buffer, &indirect_buffer, &drawn_buffer);
+
+        Ok(Self {
+            pipeline, bind_group_layout, uniform_buffer, info_buffer, indirect_buffer, bind_group,
+            drawn_buffer, drawn_readback, drawn_copied: false, drawn_mapping: None, drawn: 0,
+            capacity: 1, index_count, order: Vec::new(), generation: None
+        })
+    }
+
+    fn create_buffers(device: &Device, capacity: usize) -> (Buffer, Buffer) {
+        let info: Buffer = device.create_buffer(&BufferDescriptor {label: Some("Chunk Info Buffer"), size: (capacity * size_of::<ChunkInfo>()) as BufferAddress, usage: BufferUsages::STORAGE | BufferUsages::COPY_DST, mapped_at_creation: false});
+        let indirect: Buffer = device.create_buffer(&BufferDescriptor {label: Some("Indirect Buffer"), size: (capacity * LOD_LEVELS * size_of::<DrawIndexedIndirect>()) as BufferAddress, usage: BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_SRC, mapped_at_creation: false});
+        (info, indirect)
+    }
+
+    fn create_bind_group(device: &Device, layout: &BindGroupLayout, uniform: &Buffer, info: &Buffer, indirect: &Buffer, drawn: &Buffer) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &[
+                BindGroupEntry {binding: 0, resource: uniform.as_entire_binding()},
+                BindGroupEntry {binding: 1, resource: info.as_entire_binding()},
+                BindGroupEntry {binding: 2, resource: indirect.as_entire_binding()},
+                BindGroupEntry {binding: 3, resource: drawn.as_entire_binding()},
+            ],
+            label: Some("Cull Bind Group")
+        })
+    }
+
+    // Byte offset of the draw arguments of a slot and level of detail
+    pub fn indirect_offset(slot: usize, lod: usize) -> BufferAddress {
+        ((slot * LOD_LEVELS + lod) * size_of::<DrawIndexedIndirect>()) as BufferAddress
+    }
+
+    // Rebuild the chunk slots whenever the uploaded chunks changed
+    pub fn update_chunks(&mut self, device: &Device, queue: &Queue, chunks: &ChunkBuffers) {
+        if self.generation == Some(chunks.generation()) {
+            return;
+        }
+        self.generation = Some(chunks.generation());
+
+        self.order = chunks.chunks.keys().copied().collect();
+        let infos: Vec<ChunkInfo> = self.order.iter().map(|pos| {
+            let aabb: Aabb = Aabb::chunk(*pos);
+            let mut counts: [u32; 4] = [0; 4];
+            for (count, level) in counts.iter_mut().zip(&chunks.chunks[pos].levels) {
+                *count = level.count;
+            }
+            ChunkInfo {min: aabb.min.extend(0.0).into(), max: aabb.max.extend(0.0).into(), counts}
+        }).collect();
+
+        if infos.len() > self.capacity {
+            self.capacity = infos.len().next_power_of_two();
+            (self.info_buffer, self.indirect_buffer) = Self::create_buffers(device, self.capacity);
+            self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, &self.info_buffer, &self.indirect_buffer, &self.drawn_buffer);
+        }
+        if !infos.is_empty() {
+            queue.write_buffer(&self.info_buffer, 0, cast_slice(&infos));
+        }
+    }
+
+    // Record the culling pass, must run before the render pass that draws indirectly
+    pub fn dispatch(&self, encoder: &mut CommandEncoder, queue: &Queue, view_proj: Matrix4<f32>, eye: Point3<f32>, lod: &LodSettings) {
+        if self.order.is_empty() {
+            return;
+        }
+
+        let uniform: CullUniform = CullUniform {
+            planes: Frustum::from_matrix(view_proj).planes.map(|p| p.into()),
+            eye: [eye.x, eye.y, eye.z, 0.0],
+            lod_distances: [lod.distances[0], lod.distances[1], f32::MAX, f32::MAX],
+            chunk_count: self.order.len() as u32,
+            index_count: self.index_count,
+            _pad: [0; 2]
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniform]));
+        encoder.clear_buffer(&self.drawn_buffer, 0, None);
+
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {label: Some("Cull Pass")});
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups((self.order.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    // Copy the number of chunks drawn for readback, after the culling pass was recorded. Skipped while the
+    // previous count is still being read back
+    pub fn read_drawn(&mut self, encoder: &mut CommandEncoder) {
+        self.drawn_copied = self.drawn_mapping.is_none();
+        if self.drawn_copied {
+            encoder.copy_buffer_to_buffer(&self.drawn_buffer, 0, &self.drawn_readback, 0, 4);
+        }
+    }
+
+    // Call after submitting, returns the chunks drawn in the last frame whose count was read back
+    pub fn collect_drawn(&mut self, device: &Device) -> u32 {
+        if self.drawn_copied {
+            self.drawn_copied = false;
+            let mapped: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+            let flag: Arc<AtomicBool> = mapped.clone();
+            self.drawn_readback.slice(..).map_async(MapMode::Read, move |result| flag.store(result.is_ok(), Ordering::Release));
+            self.drawn_mapping = Some(mapped);
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        if self.drawn_mapping.as_ref().is_some_and(|mapped| mapped.load(Ordering::Acquire)) {
+            self.drawn_mapping = None;
+            self.drawn = bytemuck::cast_slice::<u8, u32>(&self.drawn_readback.slice(..).get_mapped_range())[0];
+            self.drawn_readback.unmap();
+        }
+        self.drawn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix4, Point3, Vector3};
+    use pollster::FutureExt;
+    use wgpu::{BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Maintain, MapMode};
+    use wgpu::util::DrawIndexedIndirect;
+    use crate::assets::Assets;
+    use crate::camera::{Camera, CameraUniform};
+    use crate::chunk::ChunkMap;
+    use crate::chunk_buffers::ChunkBuffers;
+    use crate::culling::{classify, Frustum, LodSettings, Visibility, LOD_LEVELS};
+    use crate::gpu_culling::GpuCuller;
+    use crate::state::State;
+
+    // The compute pass has to agree with the cpu implementation in culling.rs
+    #[test]
+    fn test_gpu_culling_matches_cpu() {
+        let state: State = State::new(None, Assets::default()).block_on().unwrap();
+        if !GpuCuller::supported(&state.downlevel) {
+            return;
+        }
+        let (device, queue) = (&state.device, &state.queue);
+
+        let mut map: ChunkMap = ChunkMap::new();
+        for z in -20..4 {
+            for x in -4..4 {
+                map.set(Vector3::new(x * 32, 0, z * 32), Some(1));
+            }
+        }
+        let mut chunks: ChunkBuffers = ChunkBuffers::new(device);
+        chunks.sync(device, queue, &mut map, &[]);
+
+        let eye: Point3<f32> = Point3::new(16.0, 16.0, 16.0);
+        let camera: Camera = Camera {eye, target: Point3::new(16.0, 16.0, -100.0), up: Vector3::unit_y(), aspect: 1.0, fov: 90.0, near: 0.1, far: 500.0, uniform: CameraUniform::new(), controller: None};
+        let view_proj: Matrix4<f32> = camera.build_view_projection_matrix();
+        let lod: LodSettings = LodSettings {distances: [100.0, 300.0]};
+
+        let mut culler: GpuCuller = GpuCuller::new(device, &Assets::default(), 36).unwrap();
+        culler.update_chunks(device, queue, &chunks);
+
+        let size: u64 = GpuCuller::indirect_offset(culler.order.len(), 0);
+        let readback = device.create_buffer(&BufferDescriptor {label: None, size, usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST, mapped_at_creation: false});
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {label: None});
+        culler.dispatch(&mut encoder, queue, view_proj, eye, &lod);
+        encoder.copy_buffer_to_buffer(&culler.indirect_buffer, 0, &readback, 0, size);
+        culler.read_drawn(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+
+        readback.slice(..).map_async(MapMode::Read, |r| r.unwrap());
+        device.poll(Maintain::Wait);
+        let data = readback.slice(..).get_mapped_range();
+        let draws: &[[u32; 5]] = bytemuck::cast_slice(&data);
+        assert_eq!(std::mem::size_of::<DrawIndexedIndirect>(), 20);
+
+        let frustum: Frustum = Frustum::from_matrix(view_proj);
+        let mut drawn: usize = 0;
+        for (slot, pos) in culler.order.iter().enumerate() {
+            let expected: Visibility = classify(&frustum, eye, &lod, *pos);
+            for level in 0..LOD_LEVELS {
+                let instances: u32 = draws[slot * LOD_LEVELS + level][1];
+                let count: u32 = chunks.chunks[pos].levels[level].count;
+                assert_eq!(instances, if expected == Visibility::Visible(level) {count} else {0}, "chunk {:?} level {}", pos, level);
+                drawn += (instances > 0) as usize;
+            }
+        }
+        assert!(drawn > 0 && drawn < culler.order.len());
+
+        // only chunks with instances count as drawn
+        culler.collect_drawn(device);
+        device.poll(Maintain::Wait);
+        assert_eq!(culler.collect_drawn(device), drawn as u32);
+    }
+}
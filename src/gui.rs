@@ -0,0 +1,692 @@
+use std::ops::RangeInclusive;
+use cgmath::{Matrix4, Vector3, Vector4};
+use winit::event::{ElementState, MouseButton, WindowEvent};
+use crate::chunk::Voxel;
+use crate::bindings::{Action, Binding};
+use crate::culling::Aabb;
+use crate::font::{text_width, GLYPH_HEIGHT};
+use crate::gizmo::{GizmoMode, MIN_OBJECT_SCALE};
+use crate::hud::HudBatch;
+use crate::objects::{world_bounds, Transform};
+use crate::palette::{Palette, PaletteEntry};
+use crate::pathtrace::TraceSettings;
+use crate::state::State;
+
+const PANEL_COLOR: [f32; 4] = [0.08, 0.08, 0.1, 0.85];
+const BUTTON_COLOR: [f32; 4] = [0.2, 0.2, 0.24, 1.0];
+const HOVER_COLOR: [f32; 4] = [0.3, 0.3, 0.36, 1.0];
+const ACTIVE_COLOR: [f32; 4] = [0.25, 0.45, 0.8, 1.0];
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// Palette swatches per row
+const SWATCH_COLUMNS: usize = 16;
+
+// Area in window pixels, from the top left corner
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {x, y, width, height}
+    }
+
+    pub fn contains(&self, point: [f32; 2]) -> bool {
+        point[0] >= self.x && point[0] < self.x + self.width && point[1] >= self.y && point[1] < self.y + self.height
+    }
+}
+
+// What a click in the viewport does to the model
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tool {
+    #[default]
+    Place, // add a voxel on the face under the cursor
+    Erase,
+    Paint, // recolour the voxel under the cursor
+    Pick, // take the colour of the voxel under the cursor
+    Select, // edit the object under the cursor
+}
+
+impl Tool {
+    pub const ALL: [Tool; 5] = [Tool::Place, Tool::Erase, Tool::Paint, Tool::Pick, Tool::Select];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Place => "PLACE",
+            Tool::Erase => "ERASE",
+            Tool::Paint => "PAINT",
+            Tool::Pick => "PICK",
+            Tool::Select => "SELECT"
+        }
+    }
+}
+
+// Mouse state the widgets see, the button only counts when it went down on a panel
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GuiInput {
+    pub cursor: Option<[f32; 2]>, // None while the cursor is outside the window
+    pub held: bool, // left button held since a press on a panel
+    pub pressed: bool, // left button went down on a panel since the last frame
+}
+
+// Immediate mode gui, the panels are laid out again every frame from the state they show
+pub struct Gui {
+    pub input: GuiInput,
+    pub visible: bool,
+    pub scale: f32, // window pixels per font pixel
+    pub tool: Tool,
+    pub color: u8, // palette index placed and painted with
+    pub show_bindings: bool,
+    pub show_objects: bool,
+    pub gizmo: Option<GizmoMode>, // the handles shown in the viewport, None for none
+    pub gizmo_voxels: bool, // the gizmo moves the voxels of the active layer instead of the selected object
+    panels: Vec<Rect>, // areas covered last frame, mouse input over them doesn't reach the scene
+}
+
+impl Default for Gui {
+    fn default() -> Self {
+        Self {input: GuiInput::default(), visible: true, scale: 2.0, tool: Tool::default(), color: 0, show_bindings: false, show_objects: false, gizmo: None, gizmo_voxels: false, panels: Vec::new()}
+    }
+}
+
+impl Gui {
+    // Track the mouse, returns true when the event was meant for the gui
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved {position, ..} => {
+                self.input.cursor = Some([position.x as f32, position.y as f32]);
+                self.input.held // dragging a slider
+            }
+            WindowEvent::CursorLeft {..} => {
+                self.input.cursor = None;
+                false
+            }
+            WindowEvent::MouseInput {state: ElementState::Released, button: MouseButton::Left, ..} if self.input.held => {
+                self.input.held = false;
+                true
+            }
+            // the panels only use the left button, other buttons and releases of presses that started in the scene
+            // go to the scene, so it sees every press and release in pairs
+            WindowEvent::MouseInput {state: ElementState::Pressed, button: MouseButton::Left, ..} if self.is_over_panel() => {
+                self.input.held = true;
+                self.input.pressed = true;
+                true
+            }
+            WindowEvent::MouseWheel {..} => self.is_over_panel(),
+            _ => false
+        }
+    }
+
+    pub fn is_over_panel(&self) -> bool {
+        match self.input.cursor {
+            Some(cursor) => self.visible && self.panels.iter().any(|panel| panel.contains(cursor)),
+            None => false
+        }
+    }
+
+    // Start laying out a frame
+    pub fn begin(&self) -> Ui {
+        Ui {input: self.input, scale: self.scale, batch: HudBatch::default(), panels: Vec::new()}
+    }
+
+    // Remember where the panels ended up and return what to draw
+    pub fn end(&mut self, ui: Ui) -> HudBatch {
+        self.panels = ui.panels;
+        self.input.pressed = false;
+        ui.batch
+    }
+}
+
+// Widgets of a single frame, each one draws itself and reports what the mouse did to it
+pub struct Ui {
+    input: GuiInput,
+    pub scale: f32,
+    pub batch: HudBatch,
+    panels: Vec<Rect>,
+}
+
+impl Ui {
+    // Height of a button or a line of text including its padding
+    pub fn row_height(&self) -> f32 {
+        (GLYPH_HEIGHT + 6) as f32 * self.scale
+    }
+
+    pub fn padding(&self) -> f32 {
+        2.0 * self.scale
+    }
+
+    pub fn text_width(&self, text: &str) -> f32 {
+        text_width(text) as f32 * self.scale + 2.0 * self.padding()
+    }
+
+    fn hovered(&self, rect: Rect) -> bool {
+        self.input.cursor.is_some_and(|cursor| rect.contains(cursor))
+    }
+
+    // Background that keeps clicks from reaching the scene
+    pub fn panel(&mut self, rect: Rect) {
+        self.batch.rect(rect.x, rect.y, rect.width, rect.height, PANEL_COLOR);
+        self.panels.push(rect);
+    }
+
+    // Text vertically centred in a row starting at (x, y)
+    pub fn label(&mut self, x: f32, y: f32, text: &str) {
+        let top: f32 = y + (self.row_height() - GLYPH_HEIGHT as f32 * self.scale) / 2.0;
+        self.batch.text(x + self.padding(), top, self.scale, TEXT_COLOR, text);
+    }
+
+    // Returns true when clicked
+    pub fn button(&mut self, rect: Rect, text: &str, active: bool) -> bool {
+        let hovered: bool = self.hovered(rect);
+        let color: [f32; 4] = if active {ACTIVE_COLOR} else if hovered {HOVER_COLOR} else {BUTTON_COLOR};
+        self.batch.rect(rect.x, rect.y, rect.width, rect.height, color);
+        self.label(rect.x, rect.y + (rect.height - self.row_height()) / 2.0, text);
+        hovered && self.input.pressed
+    }
+
+    // A colour sample with a frame when selected, returns true when clicked
+    pub fn swatch(&mut self, rect: Rect, color: [f32; 4], selected: bool) -> bool {
+        if selected {
+            self.batch.rect(rect.x - self.scale, rect.y - self.scale, rect.width + 2.0 * self.scale, rect.height + 2.0 * self.scale, TEXT_COLOR);
+        }
+        self.batch.rect(rect.x, rect.y, rect.width, rect.height, color);
+        self.hovered(rect) && self.input.pressed
+    }
+
+    // Horizontal slider, the value follows the cursor while the button is held over it. Returns true when it changed
+    pub fn slider(&mut self, rect: Rect, text: &str, value: &mut f32, range: RangeInclusive<f32>) -> bool {
+        let (min, max): (f32, f32) = (*range.start(), *range.end());
+        let mut changed: bool = false;
+        if let Some(cursor) = self.input.cursor.filter(|_| self.input.held && self.hovered(rect)) {
+            let new: f32 = min + ((cursor[0] - rect.x) / rect.width).clamp(0.0, 1.0) * (max - min);
+            changed = new != *value;
+            *value = new;
+        }
+        let fill: f32 = ((*value - min) / (max - min)).clamp(0.0, 1.0) * rect.width;
+        self.batch.rect(rect.x, rect.y, rect.width, rect.height, BUTTON_COLOR);
+        self.batch.rect(rect.x, rect.y, fill, rect.height, ACTIVE_COLOR);
+        self.label(rect.x, rect.y + (rect.height - self.row_height()) / 2.0, &format!("{} {:.2}", text, value));
+        changed
+    }
+}
+
+// Lays out the editor panels, which change the state directly. Returns the part of the window left for the scene
+pub(crate) fn editor(state: &mut State, ui: &mut Ui) -> Rect {
+    let (width, height): (f32, f32) = (state.size.width as f32, state.size.height as f32);
+    let (row, padding): (f32, f32) = (ui.row_height(), ui.padding());
+    let bar: f32 = row + 2.0 * padding;
+
+    selection(state, ui);
+    toolbar(state, ui, Rect::new(0.0, 0.0, width, bar));
+    status_bar(state, ui, Rect::new(0.0, height - bar, width, bar));
+    timeline(state, ui, Rect::new(0.0, height - 2.0 * bar, width, bar));
+
+    let column: f32 = 104.0 * ui.scale;
+    let bottom: f32 = layers(state, ui, Rect::new(0.0, bar, column, 0.0));
+    settings(state, ui, Rect::new(0.0, bottom, column, 0.0));
+    let swatches: f32 = SWATCH_COLUMNS as f32 * 6.0 * ui.scale + 2.0 * padding;
+    palette(state, ui, Rect::new(width - swatches, bar, swatches, 0.0));
+    if state.gui.show_bindings {
+        bindings(state, ui, Rect::new(column, bar, 0.0, 0.0));
+    }
+    if state.gui.show_objects {
+        let objects_width: f32 = ui.text_width("PIVOT -000.0 -000.0 -000.0") + 2.0 * padding;
+        objects(state, ui, Rect::new(width - swatches - objects_width, bar, objects_width, 0.0));
+    }
+    Rect::new(column, bar, width - column - swatches, height - 3.0 * bar)
+}
+
+fn toolbar(state: &mut State, ui: &mut Ui, area: Rect) {
+    ui.panel(area);
+    let (row, padding): (f32, f32) = (ui.row_height(), ui.padding());
+    let mut x: f32 = padding;
+    for tool in Tool::ALL {
+        let width: f32 = ui.text_width(tool.name());
+        if ui.button(Rect::new(x, area.y + padding, width, row), tool.name(), state.gui.tool == tool) {
+            state.gui.tool = tool;
+        }
+        x += width + padding;
+    }
+
+    x += 4.0 * padding;
+    let gizmos: [(GizmoMode, Action); 3] = [(GizmoMode::Translate, Action::TranslateGizmo), (GizmoMode::Rotate, Action::RotateGizmo), (GizmoMode::Scale, Action::ScaleGizmo)];
+    for (mode, action) in gizmos {
+        let width: f32 = ui.text_width(mode.name());
+        if ui.button(Rect::new(x, area.y + padding, width, row), mode.name(), state.gui.gizmo == Some(mode)) {
+            state.run_action(action);
+        }
+        x += width + padding;
+    }
+    if ui.button(Rect::new(x, area.y + padding, ui.text_width("VOXELS"), row), "VOXELS", state.gui.gizmo_voxels) {
+        state.run_action(Action::ToggleGizmoVoxels);
+    }
+    x += ui.text_width("VOXELS") + padding;
+
+    x += 4.0 * padding;
+    let mode: String = format!("{:?}", state.render_mode).to_uppercase();
+    let width: f32 = ui.text_width("WIREFRAME");
+    if ui.button(Rect::new(x, area.y + padding, width, row), &mode, false) {
+        state.toggle_wireframe();
+    }
+    x += width + padding;
+    if ui.button(Rect::new(x, area.y + padding, ui.text_width("STATS"), row), "STATS", state.show_stats) {
+        state.show_stats = !state.show_stats;
+    }
+    x += ui.text_width("STATS") + padding;
+    if ui.button(Rect::new(x, area.y + padding, ui.text_width("KEYS"), row), "KEYS", state.gui.show_bindings) {
+        state.gui.show_bindings = !state.gui.show_bindings;
+    }
+    x += ui.text_width("KEYS") + padding;
+    if ui.button(Rect::new(x, area.y + padding, ui.text_width("OBJECTS"), row), "OBJECTS", state.gui.show_objects) {
+        state.gui.show_objects = !state.gui.show_objects;
+    }
+}
+
+// A frame around the selected object on screen, when there are others to tell it apart from
+fn selection(state: &State, ui: &mut Ui) {
+    let selected: usize = state.objects.selected;
+    if state.objects.len() < 2 || !state.objects.is_visible(selected) {
+        return;
+    }
+    let Some(bounds) = world_bounds(&state.chunks, state.objects.world(selected)) else {return};
+    let view_proj: Matrix4<f32> = state.camera.uniform.view_proj.into();
+    let (width, height): (f32, f32) = (state.size.width as f32, state.size.height as f32);
+    let corners: Vec<[f32; 2]> = bounds.corners().iter().map(|corner| view_proj * corner.extend(1.0)).filter(|clip: &Vector4<f32>| clip.w > 0.0).map(|clip| {
+        [(clip.x / clip.w * 0.5 + 0.5) * width, (0.5 - clip.y / clip.w * 0.5) * height]
+    }).collect();
+    // an object partly behind the camera has no frame that makes sense
+    if corners.len() < 8 {
+        return;
+    }
+    let screen: Aabb = corners.iter().fold(Aabb {min: Vector3::new(f32::MAX, f32::MAX, 0.0), max: Vector3::new(f32::MIN, f32::MIN, 0.0)}, |screen, [x, y]| screen.union(&Aabb {min: Vector3::new(*x, *y, 0.0), max: Vector3::new(*x, *y, 0.0)}));
+    let (min, size, line): (Vector3<f32>, Vector3<f32>, f32) = (screen.min, screen.max - screen.min, ui.scale);
+    ui.batch.rect(min.x, min.y, size.x, line, ACTIVE_COLOR);
+    ui.batch.rect(min.x, screen.max.y - line, size.x, line, ACTIVE_COLOR);
+    ui.batch.rect(min.x, min.y, line, size.y, ACTIVE_COLOR);
+    ui.batch.rect(screen.max.x - line, min.y, line, size.y, ACTIVE_COLOR);
+}
+
+// Every action with its bindings, clicking one waits for the next key or button to bind to it
+fn bindings(state: &mut State, ui: &mut Ui, area: Rect) {
+    let (row, padding): (f32, f32) = (ui.row_height(), ui.padding());
+    let name_width: f32 = ui.text_width("TOGGLE_ONION_SKIN") + padding;
+    let width: f32 = name_width + ui.text_width("CTRL+SHIFT+Z MOUSE-MIDDLE") + 2.0 * padding;
+    let height: f32 = (Action::ALL.len() + 1) as f32 * (row + padding) + padding;
+    ui.panel(Rect {width, height, ..area});
+    ui.label(area.x, area.y + padding, "ACTION");
+    ui.label(area.x + name_width, area.y + padding, "CLICK TO REBIND");
+
+    let mut y: f32 = area.y + row + 2.0 * padding;
+    for action in Action::ALL {
+        ui.label(area.x, y, &action.name().to_uppercase());
+        let rebinding: bool = state.dispatcher.rebinding() == Some(action);
+        let text: String = match rebinding {
+            true => "PRESS A KEY OR BUTTON".to_string(),
+            false => state.dispatcher.bindings.get(action).map(|binding: Binding| binding.to_string().to_uppercase()).collect::<Vec<String>>().join(" ")
+        };
+        if ui.button(Rect::new(area.x + name_width, y, width - name_width - padding, row), &text, rebinding) {
+            state.dispatcher.rebind(action);
+        }
+        y += row + padding;
+    }
+}
+
+// Cursor position, the voxel under it and what a click would do
+fn status_bar(state: &mut State, ui: &mut Ui, area: Rect) {
+    ui.panel(area);
+    let mut text: String = match ui.input.cursor {
+        Some([x, y]) => format!("X {} Y {}", x as i32, y as i32),
+        None => "X - Y -".to_string()
+    };
+    if let Some((position, _)) = state.pick() {
+        let voxel: Voxel = state.chunks.get(position);
+        text += &format!("  VOXEL {} {} {}  COLOR {}", position.x, position.y, position.z, voxel.map_or(0, u32::from));
+    }
+    text += &format!("  TOOL {}  OBJECT {}  LAYER {}  FRAME {}/{}  VOXELS {}", state.gui.tool.name(), state.objects.selected().name.to_uppercase(), state.layers.active().name.to_uppercase(), state.timeline.current() + 1, state.timeline.len(), state.chunks.len());
+    if let Some(drag) = state.gizmo_drag() {
+        text += &format!("  {} {:.1}", drag.gizmo.mode.name(), drag.snapped(state.gui.gizmo_voxels));
+    }
+    ui.label(area.x, area.y + ui.padding(), &text);
+}
+
+// Playback controls, a button per frame and the playback speed and duration of the current frame
+fn timeline(state: &mut State, ui: &mut Ui, area: Rect) {
+    ui.panel(area);
+    let (row, padding): (f32, f32) = (ui.row_height(), ui.padding());
+    let y: f32 = area.y + padding;
+    let mut x: f32 = padding;
+    let mut button = |ui: &mut Ui, text: &str, active: bool| {
+        let width: f32 = ui.text_width(text);
+        let clicked: bool = ui.button(Rect::new(x, y, width, row), text, active);
+        x += width + padding;
+        clicked
+    };
+    if button(ui, "<", false) {
+        state.run_action(Action::PreviousFrame);
+    }
+    if button(ui, if state.timeline.playing {"STOP"} else {"PLAY"}, state.timeline.playing) {
+        state.run_action(Action::Play);
+    }
+    if button(ui, ">", false) {
+        state.run_action(Action::NextFrame);
+    }
+    if button(ui, "+ ADD", false) {
+        state.add_frame();
+    }
+    if button(ui, "- DEL", false) {
+        state.remove_frame();
+    }
+    if button(ui, "ONION", state.timeline.onion_skin) {
+        state.timeline.onion_skin = !state.timeline.onion_skin;
+    }
+
+    // the sliders take the right end, the frames whatever is left in between around the current one
+    let slider: f32 = ui.text_width("SPEED 0.00") + 8.0 * ui.scale;
+    let sliders: f32 = area.width - 2.0 * (slider + padding);
+    ui.slider(Rect::new(sliders, y, slider, row), "SPEED", &mut state.timeline.speed, 0.1..=4.0);
+    let current: usize = state.timeline.current();
+    let mut duration: f32 = state.timeline.duration(current);
+    if ui.slider(Rect::new(sliders + slider + padding, y, slider, row), "TIME", &mut duration, 0.02..=1.0) {
+        state.timeline.set_duration(current, duration);
+    }
+
+    let cell: f32 = ui.text_width("000");
+    let count: usize = (((sliders - x) / (cell + padding)).floor().max(0.0) as usize).min(state.timeline.len());
+    let first: usize = current.saturating_sub(count / 2).min(state.timeline.len() - count);
+    for frame in first..first + count {
+        if ui.button(Rect::new(x, y, cell, row), &(frame + 1).to_string(), frame == current) {
+            state.switch_frame(frame);
+        }
+        x += cell + padding;
+    }
+}
+
+// One row per layer with a visibility toggle, returns the bottom of the panel
+fn layers(state: &mut State, ui: &mut Ui, area: Rect) -> f32 {
+    let (row, padding): (f32, f32) = (ui.row_height(), ui.padding());
+    let height: f32 = (state.layers.len() + 2) as f32 * (row + padding) + padding;
+    ui.panel(Rect {height, ..area});
+    ui.label(area.x, area.y + padding, "LAYERS");
+
+    let mut y: f32 = area.y + row + 2.0 * padding;
+    // topmost layer first, like it covers the others
+    for index in (0..state.layers.len()).rev() {
+        let visible: bool = state.layers.layers[index].visible;
+        if ui.button(Rect::new(area.x + padding, y, row, row), if visible {"V"} else {"-"}, false) {
+            state.set_layer_visible(index, !visible);
+        }
+        let name: String = state.layers.layers[index].name.to_uppercase();
+        if ui.button(Rect::new(area.x + row + 2.0 * padding, y, area.width - row - 3.0 * padding, row), &name, index == state.layers.active) {
+            state.layers.active = index;
+        }
+        y += row + padding;
+    }
+
+    let half: f32 = (area.width - 3.0 * padding) / 2.0;
+    if ui.button(Rect::new(area.x + padding, y, half, row), "+ ADD", false) {
+        let name: String = format!("Layer {}", state.layers.len() + 1);
+        state.add_layer(&name);
+    }
+    if ui.button(Rect::new(area.x + half + 2.0 * padding, y, half, row), "- DEL", false) {
+        state.remove_layer(state.layers.active);
+    }
+    area.y + height
+}
+
+// The scene graph with children indented under their parents, and the placement of the selected object
+fn objects(state: &mut State, ui: &mut Ui, area: Rect) {
+    let (row, padding): (f32, f32) = (ui.row_height(), ui.padding());
+    let height: f32 = (state.objects.len() + 10) as f32 * (row + padding) + padding;
+    ui.panel(Rect {height, ..area});
+    ui.label(area.x, area.y + padding, "OBJECTS");
+
+    let width: f32 = area.width - 2.0 * padding;
+    let mut y: f32 = area.y + row + 2.0 * padding;
+    for (index, depth) in state.objects.tree() {
+        let visible: bool = state.objects.objects[index].visible;
+        if ui.button(Rect::new(area.x + padding, y, row, row), if visible {"V"} else {"-"}, false) {
+            state.objects.objects[index].visible = !visible;
+        }
+        let indent: f32 = depth as f32 * ui.text_width(" ");
+        let name: String = state.objects.objects[index].name.to_uppercase();
+        if ui.button(Rect::new(area.x + row + 2.0 * padding + indent, y, width - row - padding - indent, row), &name, index == state.objects.selected) {
+            state.select_object(index);
+        }
+        y += row + padding;
+    }
+
+    let half: f32 = (width - padding) / 2.0;
+    if ui.button(Rect::new(area.x + padding, y, half, row), "+ ADD", false) {
+        state.add_object();
+    }
+    if ui.button(Rect::new(area.x + half + 2.0 * padding, y, half, row), "- DEL", false) {
+        state.remove_object(state.objects.selected);
+    }
+    y += row + padding;
+
+    // clicking moves the object under the next object that can take it, or to the top
+    let selected: usize = state.objects.selected;
+    let parent: String = state.objects.selected().parent.map_or("NONE".to_string(), |parent| state.objects.objects[parent].name.to_uppercase());
+    if ui.button(Rect::new(area.x + padding, y, width, row), &format!("PARENT {}", parent), false) {
+        let (count, current): (usize, Option<usize>) = (state.objects.len(), state.objects.selected().parent);
+        let start: usize = current.map_or(0, |parent| parent + 1);
+        let candidates: Vec<Option<usize>> = (start..count).map(Some).chain([None]).chain((0..start).map(Some)).collect();
+        for candidate in candidates.into_iter().filter(|candidate| *candidate != current) {
+            if state.objects.set_parent(selected, candidate) {
+                break;
+            }
+        }
+    }
+    y += row + padding;
+
+    // a row of values and a row of nudges per part of the placement, steps of a voxel and 15 degrees
+    let transform: Transform = state.objects.selected().transform;
+    let angles: Vector3<f32> = transform.angles();
+    let rows: [(&str, Vector3<f32>); 3] = [("POS", transform.position), ("ROT", angles), ("PIVOT", transform.pivot)];
+    let button: f32 = (width - 5.0 * padding) / 6.0;
+    for (part, (name, values)) in rows.into_iter().enumerate() {
+        let text: String = match part {
+            1 => format!("{} {:.0} {:.0} {:.0}", name, values.x, values.y, values.z),
+            _ => format!("{} {:.1} {:.1} {:.1}", name, values.x, values.y, values.z)
+        };
+        ui.label(area.x, y, &text);
+        y += row + padding;
+        for (i, text) in ["-X", "+X", "-Y", "+Y", "-Z", "+Z"].into_iter().enumerate() {
+            if ui.button(Rect::new(area.x + padding + i as f32 * (button + padding), y, button, row), text, false) {
+                let mut axis: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0);
+                axis[i / 2] = if i % 2 == 0 {-1.0} else {1.0};
+                let transform: &mut Transform = &mut state.objects.objects[selected].transform;
+                match part {
+                    0 => transform.position += axis,
+                    1 => transform.rotate(axis, 15.0),
+                    _ => {
+                        // the object stays where it is, only what it turns around moves
+                        transform.position += transform.rotation * axis;
+                        transform.pivot += axis;
+                    }
+                }
+            }
+        }
+        y += row + padding;
+    }
+
+    // coarse steps, the scale gizmo sets any size in between
+    ui.label(area.x, y, &format!("SCALE {:.2}", transform.scale));
+    for (i, (text, factor)) in [("/2", 0.5), ("X2", 2.0)].into_iter().enumerate() {
+        if ui.button(Rect::new(area.x + padding + (i + 4) as f32 * (button + padding), y, button, row), text, false) {
+            let transform: &mut Transform = &mut state.objects.objects[selected].transform;
+            transform.scale = (transform.scale * factor).max(MIN_OBJECT_SCALE);
+        }
+    }
+}
+
+// Camera and render settings
+fn settings(state: &mut State, ui: &mut Ui, area: Rect) {
+    let (row, padding): (f32, f32) = (ui.row_height(), ui.padding());
+    let height: f32 = 10.0 * (row + padding) + padding;
+    ui.panel(Rect {height, ..area});
+    ui.label(area.x, area.y + padding, "VIEW");
+
+    let width: f32 = area.width - 2.0 * padding;
+    let mut y: f32 = area.y + row + 2.0 * padding;
+    let mut next = || {
+        let rect: Rect = Rect::new(area.x + padding, y, width, row);
+        y += row + padding;
+        rect
+    };
+
+    ui.slider(next(), "FOV", &mut state.camera.fov, 30.0..=120.0);
+    ui.slider(next(), "EXPOSURE", &mut state.post.settings.exposure, 0.1..=4.0);
+    ui.slider(next(), "AMBIENT", &mut state.light.ambient, 0.0..=1.0);
+    if ui.button(next(), &format!("MSAA {}X", state.sample_count()), false) {
+        let next: u32 = state.sample_counts.iter().copied().find(|count| *count > state.sample_count()).unwrap_or(1);
+        state.set_sample_count(next);
+    }
+    let toggles: [(&str, &mut bool); 4] = [
+        ("SSAO", &mut state.post.settings.ssao),
+        ("OUTLINE", &mut state.post.settings.outline),
+        ("TONEMAP", &mut state.post.settings.tonemap),
+        ("SHADOWS", &mut state.light.shadows)
+    ];
+    for (text, value) in toggles {
+        if ui.button(next(), text, *value) {
+            *value = !*value;
+        }
+    }
+    if ui.button(next(), "PATH TRACE", false) {
+        state.path_trace_view(TraceSettings {width: state.size.width.max(1), height: state.size.height.max(1), ..Default::default()});
+    }
+}
+
+// Swatches of every palette entry, and sliders editing the selected one
+fn palette(state: &mut State, ui: &mut Ui, area: Rect) {
+    let (row, padding): (f32, f32) = (ui.row_height(), ui.padding());
+    let size: f32 = 6.0 * ui.scale;
+    let rows: usize = state.palette.len().div_ceil(SWATCH_COLUMNS);
+    let swatches: f32 = rows as f32 * size;
+    let height: f32 = row + swatches + 6.0 * (row + padding) + 3.0 * padding;
+    ui.panel(Rect {height, ..area});
+    ui.label(area.x, area.y + padding, &format!("PALETTE {}", state.gui.color));
+
+    let top: f32 = area.y + row + 2.0 * padding;
+    for index in 0..state.palette.len() {
+        let rect: Rect = Rect::new(area.x + padding + (index % SWATCH_COLUMNS) as f32 * size, top + (index / SWATCH_COLUMNS) as f32 * size, size, size);
+        let color: [f32; 4] = Into::<[f32; 4]>::into(state.palette.entries[index].color);
+        if ui.swatch(rect, color, index == state.gui.color as usize) {
+            state.gui.color = index as u8;
+        }
+    }
+
+    let entry: Option<&PaletteEntry> = state.palette.get(state.gui.color as usize);
+    let Some(mut entry) = entry.cloned() else {return};
+    let width: f32 = area.width - 2.0 * padding;
+    let mut y: f32 = top + swatches + padding;
+    let mut next = || {
+        let rect: Rect = Rect::new(area.x + padding, y, width, row);
+        y += row + padding;
+        rect
+    };
+
+    let mut changed: bool = false;
+    for (i, text) in ["R", "G", "B", "A"].into_iter().enumerate() {
+        changed |= ui.slider(next(), text, &mut entry.color[i], 0.0..=1.0);
+    }
+    changed |= ui.slider(next(), "ROUGH", &mut entry.material.roughness, 0.0..=1.0);
+    changed |= ui.slider(next(), "METAL", &mut entry.material.metalness, 0.0..=1.0);
+    if changed {
+        let mut palette: Palette = state.palette.clone();
+        palette.entries[state.gui.color as usize] = entry;
+        state.set_palette(palette);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::dpi::PhysicalPosition;
+    use winit::event::{DeviceId, ElementState, ModifiersState, MouseButton, WindowEvent};
+    use crate::gui::{Gui, Rect, Ui};
+
+    fn click(state: ElementState) -> WindowEvent<'static> {
+        click_with(MouseButton::Left, state)
+    }
+
+    #[allow(deprecated)]
+    fn click_with(button: MouseButton, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::MouseInput {device_id: unsafe {DeviceId::dummy()}, state, button, modifiers: ModifiersState::empty()}
+    }
+
+    #[allow(deprecated)]
+    fn move_to(x: f64, y: f64) -> WindowEvent<'static> {
+        WindowEvent::CursorMoved {device_id: unsafe {DeviceId::dummy()}, position: PhysicalPosition::new(x, y), modifiers: ModifiersState::empty()}
+    }
+
+    #[test]
+    fn test_panels_consume_clicks() {
+        let mut gui: Gui = Gui::default();
+        let button: Rect = Rect::new(10.0, 10.0, 50.0, 20.0);
+        let frame = |gui: &mut Gui| {
+            let mut ui: Ui = gui.begin();
+            ui.panel(Rect::new(0.0, 0.0, 100.0, 40.0));
+            let clicked: bool = ui.button(button, "OK", false);
+            gui.end(ui);
+            clicked
+        };
+        assert!(!frame(&mut gui));
+
+        // outside the panels everything goes to the scene
+        assert!(!gui.handle_event(&move_to(200.0, 200.0)));
+        assert!(!gui.handle_event(&click(ElementState::Pressed)));
+        assert!(!gui.handle_event(&click(ElementState::Released)));
+        assert!(!frame(&mut gui));
+
+        // a click on the button is used up, and reported for a single frame
+        assert!(!gui.handle_event(&move_to(20.0, 15.0)));
+        assert!(gui.handle_event(&click(ElementState::Pressed)));
+        assert!(frame(&mut gui));
+        assert!(!frame(&mut gui));
+
+        // the release still belongs to the gui after dragging off the panel
+        assert!(gui.handle_event(&move_to(200.0, 200.0)));
+        assert!(gui.handle_event(&click(ElementState::Released)));
+        assert!(!gui.input.held);
+
+        // other buttons are left to the scene, press and release alike
+        assert!(!gui.handle_event(&move_to(20.0, 15.0)));
+        assert!(!gui.handle_event(&click_with(MouseButton::Right, ElementState::Pressed)));
+        assert!(!gui.handle_event(&click_with(MouseButton::Right, ElementState::Released)));
+        assert!(!frame(&mut gui));
+
+        gui.visible = false;
+        assert!(!gui.handle_event(&move_to(20.0, 15.0)));
+        assert!(!gui.handle_event(&click(ElementState::Pressed)));
+    }
+
+    #[test]
+    fn test_slider_follows_the_held_cursor() {
+        let mut gui: Gui = Gui::default();
+        let slider: Rect = Rect::new(0.0, 0.0, 100.0, 20.0);
+        let frame = |gui: &mut Gui, value: &mut f32| {
+            let mut ui: Ui = gui.begin();
+            ui.panel(slider);
+            let changed: bool = ui.slider(slider, "A", value, 0.0..=2.0);
+            gui.end(ui);
+            changed
+        };
+        let mut value: f32 = 0.5;
+        frame(&mut gui, &mut value);
+
+        gui.handle_event(&move_to(75.0, 10.0));
+        assert!(!frame(&mut gui, &mut value));
+        gui.handle_event(&click(ElementState::Pressed));
+        assert!(frame(&mut gui, &mut value));
+        assert!(!frame(&mut gui, &mut value));
+        gui.handle_event(&click(ElementState::Released));
+        gui.handle_event(&move_to(25.0, 10.0));
+        assert!(!frame(&mut gui, &mut value));
+        assert_eq!(value, 1.5);
+    }
+}
@@ -0,0 +1,54 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+// Polls the modification time of a file, cheap enough to do once per frame
+pub struct FileWatcher {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl FileWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path: PathBuf = path.into();
+        let modified: Option<SystemTime> = Self::modified(&path);
+        Self {path, modified}
+    }
+
+    fn modified(path: &PathBuf) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    // Returns true once for every change of the file's modification time
+    pub fn changed(&mut self) -> bool {
+        let modified: Option<SystemTime> = Self::modified(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+    use crate::hot_reload::FileWatcher;
+
+    #[test]
+    fn test_file_watcher() {
+        let path = std::env::temp_dir().join(format!("voxelart-watch-{}.wgsl", std::process::id()));
+        fs::write(&path, "a").unwrap();
+
+        let mut watcher: FileWatcher = FileWatcher::new(&path);
+        assert!(!watcher.changed());
+
+        fs::File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed());
+    }
+}
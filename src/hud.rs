@@ -0,0 +1,183 @@
+use std::mem::size_of;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CommandEncoder, Device, FragmentState, FrontFace, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureView, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
+use winit::dpi::PhysicalSize;
+use crate::assets::Assets;
+use crate::font::{glyph, text_width, Glyph, ADVANCE, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::utils::create_wgpu_buffer;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct HudVertex {
+    pub position: [f32; 2], // pixels from the top left corner of the window
+    pub color: [f32; 4],
+}
+
+impl HudVertex {
+    fn desc() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: size_of::<HudVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &[
+                VertexAttribute {offset: 0, shader_location: 0, format: VertexFormat::Float32x2},
+                VertexAttribute {offset: size_of::<[f32; 2]>() as BufferAddress, shader_location: 1, format: VertexFormat::Float32x4},
+            ]
+        }
+    }
+}
+
+// Rectangles and text queued for the next frame, every filled font pixel is its own rectangle
+#[derive(Default)]
+pub struct HudBatch {
+    pub vertices: Vec<HudVertex>,
+}
+
+impl HudBatch {
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        let corners: [[f32; 2]; 4] = [[x, y], [x + width, y], [x + width, y + height], [x, y + height]];
+        self.vertices.extend([0, 1, 2, 0, 2, 3].map(|i| HudVertex {position: corners[i], color}));
+    }
+
+    pub fn triangle(&mut self, corners: [[f32; 2]; 3], color: [f32; 4]) {
+        self.vertices.extend(corners.map(|position| HudVertex {position, color}));
+    }
+
+    // A line `width` pixels thick between two points
+    pub fn line(&mut self, from: [f32; 2], to: [f32; 2], width: f32, color: [f32; 4]) {
+        let (dx, dy): (f32, f32) = (to[0] - from[0], to[1] - from[1]);
+        let length: f32 = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            return;
+        }
+        let (x, y): (f32, f32) = (-dy / length * width / 2.0, dx / length * width / 2.0);
+        let corners: [[f32; 2]; 4] = [[from[0] + x, from[1] + y], [to[0] + x, to[1] + y], [to[0] - x, to[1] - y], [from[0] - x, from[1] - y]];
+        self.vertices.extend([0, 1, 2, 0, 2, 3].map(|i| HudVertex {position: corners[i], color}));
+    }
+
+    // Draw a line of text with its top left corner at (x, y), each font pixel `scale` window pixels wide
+    pub fn text(&mut self, x: f32, y: f32, scale: f32, color: [f32; 4], text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            let rows: Glyph = glyph(c);
+            let left: f32 = x + (i as u32 * ADVANCE) as f32 * scale;
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                        self.rect(left + column as f32 * scale, y + row as f32 * scale, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+
+    // Lines of text on a translucent background, top left aligned
+    pub fn panel(&mut self, x: f32, y: f32, scale: f32, lines: &[String]) {
+        let padding: f32 = 2.0 * scale;
+        let line_height: f32 = (GLYPH_HEIGHT + 2) as f32 * scale;
+        let width: f32 = lines.iter().map(|l| text_width(l)).max().unwrap_or(0) as f32 * scale;
+        self.rect(x, y, width + 2.0 * padding, lines.len() as f32 * line_height + padding, [0.0, 0.0, 0.0, 0.6]);
+        for (i, line) in lines.iter().enumerate() {
+            self.text(x + padding, y + padding + i as f32 * line_height, scale, [1.0, 1.0, 1.0, 1.0], line);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+}
+
+// Draws a HudBatch on top of the finished frame
+pub struct Hud {
+    pipeline: RenderPipeline,
+    screen_buffer: Buffer,
+    bind_group: BindGroup,
+    vertex_buffer: Buffer,
+    capacity: usize, // vertices that fit in the vertex buffer
+}
+
+impl Hud {
+    pub fn new(device: &Device, assets: &Assets, format: TextureFormat) -> anyhow::Result<Self> {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {label: Some("Hud Shader Module"), source: ShaderSource::Wgsl(assets.load_shader("hud.wgsl")?.into())});
+
+        let bind_group_layout: BindGroupLayout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {ty: BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None},
+                    count: None
+                }
+            ],
+            label: Some("Hud Bind Group Layout")
+        });
+        let screen_buffer: Buffer = create_wgpu_buffer(device, Some("Hud Screen Buffer"), cast_slice(&[[1.0f32; 4]]), BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let bind_group: BindGroup = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {binding: 0, resource: screen_buffer.as_entire_binding()}],
+            label: Some("Hud Bind Group")
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {label: Some("Hud Pipeline Layout"), bind_group_layouts: &[&bind_group_layout], push_constant_ranges: &[]});
+        let pipeline: RenderPipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Hud Pipeline"),
+            layout: Some(&layout),
+            vertex: VertexState {module: &shader, entry_point: "vs_main", buffers: &[HudVertex::desc()]},
+            fragment: Some(FragmentState {module: &shader, entry_point: "fs_main", targets: &[Some(ColorTargetState {format, blend: Some(BlendState::ALPHA_BLENDING), write_mask: ColorWrites::ALL})]}),
+            primitive: PrimitiveState {topology: PrimitiveTopology::TriangleList, strip_index_format: None, front_face: FrontFace::Ccw, cull_mode: None, polygon_mode: PolygonMode::Fill, unclipped_depth: false, conservative: false},
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None
+        });
+
+        let capacity: usize = 1024;
+        let vertex_buffer: Buffer = Self::create_vertex_buffer(device, capacity);
+        Ok(Self {pipeline, screen_buffer, bind_group, vertex_buffer, capacity})
+    }
+
+    fn create_vertex_buffer(device: &Device, capacity: usize) -> Buffer {
+        device.create_buffer(&BufferDescriptor {label: Some("Hud Vertex Buffer"), size: (capacity * size_of::<HudVertex>()) as BufferAddress, usage: BufferUsages::VERTEX | BufferUsages::COPY_DST, mapped_at_creation: false})
+    }
+
+    // Record a pass drawing the batch over `view`, keeping what is already there
+    pub fn draw(&mut self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder, view: &TextureView, size: PhysicalSize<u32>, batch: &HudBatch) {
+        if batch.is_empty() {
+            return;
+        }
+        if batch.vertices.len() > self.capacity {
+            self.capacity = batch.vertices.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, cast_slice(&batch.vertices));
+        queue.write_buffer(&self.screen_buffer, 0, cast_slice(&[size.width as f32, size.height as f32, 0.0, 0.0]));
+
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Hud Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {view, resolve_target: None, ops: Operations {load: LoadOp::Load, store: true}})],
+            depth_stencil_attachment: None
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..batch.vertices.len() as u32, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::font::{text_width, ADVANCE, GLYPH_HEIGHT};
+    use crate::hud::HudBatch;
+
+    #[test]
+    fn test_text_stays_inside_its_box() {
+        let mut batch: HudBatch = HudBatch::default();
+        batch.text(10.0, 20.0, 2.0, [1.0; 4], "FPS 60");
+        assert!(!batch.is_empty());
+        assert_eq!(batch.vertices.len() % 6, 0);
+
+        let (right, bottom) = (10.0 + text_width("FPS 60") as f32 * 2.0, 20.0 + GLYPH_HEIGHT as f32 * 2.0);
+        for vertex in &batch.vertices {
+            assert!(vertex.position[0] >= 10.0 && vertex.position[0] <= right);
+            assert!(vertex.position[1] >= 20.0 && vertex.position[1] <= bottom);
+        }
+        assert_eq!(text_width("ab"), 2 * ADVANCE - 1);
+    }
+}
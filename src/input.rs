@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, ModifiersState, MouseScrollDelta, WindowEvent};
+use crate::bindings::Trigger;
+
+// Pixels of a touchpad scroll that count as one line of a mouse wheel
+const PIXELS_PER_LINE: f32 = 40.0;
+
+// Keys, buttons and the mouse as seen over both of winit's event streams. Held state lasts until the release,
+// the deltas and the pressed and released sets only cover the current frame and are reset by `end_frame`
+#[derive(Default)]
+pub struct InputState {
+    pub held: HashSet<Trigger>,
+    pub modifiers: ModifiersState,
+    pub cursor: Option<[f32; 2]>, // window pixels, None while the cursor is outside the window
+
+    pub pressed: HashSet<Trigger>,
+    pub released: HashSet<Trigger>,
+    pub motion: [f32; 2], // raw mouse motion, keeps counting when the cursor hits the window border
+    pub cursor_moved: bool,
+    pub scroll: f32, // lines, positive away from the user
+}
+
+impl InputState {
+    pub fn handle_event(&mut self, event: &Event<()>) {
+        match event {
+            Event::WindowEvent {event, ..} => match event {
+                WindowEvent::KeyboardInput {input: KeyboardInput {state, virtual_keycode: Some(key), ..}, ..} => self.set(Trigger::Key(*key), *state),
+                WindowEvent::MouseInput {state, button, ..} => self.set(Trigger::Mouse(*button), *state),
+                WindowEvent::ModifiersChanged(modifiers) => self.modifiers = *modifiers,
+                WindowEvent::CursorMoved {position, ..} => {
+                    self.cursor = Some([position.x as f32, position.y as f32]);
+                    self.cursor_moved = true;
+                }
+                WindowEvent::CursorLeft {..} => self.cursor = None,
+                WindowEvent::MouseWheel {delta, ..} => self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE
+                },
+                // the releases would go to the other window
+                WindowEvent::Focused(false) => {
+                    self.released.extend(self.held.drain());
+                    self.modifiers = ModifiersState::empty();
+                }
+                _ => {}
+            },
+            Event::DeviceEvent {event: DeviceEvent::MouseMotion {delta}, ..} => {
+                self.motion[0] += delta.0 as f32;
+                self.motion[1] += delta.1 as f32;
+            }
+            _ => {}
+        }
+    }
+
+    fn set(&mut self, trigger: Trigger, state: ElementState) {
+        match state {
+            // key repeats are presses of a held key
+            ElementState::Pressed => if self.held.insert(trigger) {
+                self.pressed.insert(trigger);
+            },
+            ElementState::Released => if self.held.remove(&trigger) {
+                self.released.insert(trigger);
+            }
+        }
+    }
+
+    pub fn is_held(&self, trigger: Trigger) -> bool {
+        self.held.contains(&trigger)
+    }
+
+    // Start collecting the next frame's deltas
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.motion = [0.0; 2];
+        self.cursor_moved = false;
+        self.scroll = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::dpi::PhysicalPosition;
+    use winit::event::{DeviceEvent, DeviceId, ElementState, Event, ModifiersState, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent};
+    use winit::window::WindowId;
+    use crate::bindings::Trigger;
+    use crate::input::InputState;
+
+    fn window_event(event: WindowEvent<'static>) -> Event<'static, ()> {
+        Event::WindowEvent {window_id: unsafe {WindowId::dummy()}, event}
+    }
+
+    #[allow(deprecated)]
+    fn button(state: ElementState) -> Event<'static, ()> {
+        window_event(WindowEvent::MouseInput {device_id: unsafe {DeviceId::dummy()}, state, button: MouseButton::Right, modifiers: ModifiersState::empty()})
+    }
+
+    fn motion(x: f64, y: f64) -> Event<'static, ()> {
+        Event::DeviceEvent {device_id: unsafe {DeviceId::dummy()}, event: DeviceEvent::MouseMotion {delta: (x, y)}}
+    }
+
+    #[test]
+    fn test_deltas_last_a_frame() {
+        let mut input: InputState = InputState::default();
+        let right: Trigger = Trigger::Mouse(MouseButton::Right);
+
+        input.handle_event(&button(ElementState::Pressed));
+        input.handle_event(&motion(3.0, -1.0));
+        input.handle_event(&motion(2.0, 4.0));
+        #[allow(deprecated)]
+        input.handle_event(&window_event(WindowEvent::MouseWheel {device_id: unsafe {DeviceId::dummy()}, delta: MouseScrollDelta::LineDelta(0.0, 2.0), phase: TouchPhase::Moved, modifiers: ModifiersState::empty()}));
+        assert!(input.is_held(right) && input.pressed.contains(&right));
+        assert_eq!((input.motion, input.scroll), ([5.0, 3.0], 2.0));
+
+        input.end_frame();
+        assert!(input.is_held(right) && input.pressed.is_empty());
+        assert_eq!((input.motion, input.scroll), ([0.0, 0.0], 0.0));
+
+        // a second press while held is a repeat
+        input.handle_event(&button(ElementState::Pressed));
+        assert!(input.pressed.is_empty());
+        input.handle_event(&button(ElementState::Released));
+        assert!(!input.is_held(right) && input.released.contains(&right));
+
+        input.handle_event(&button(ElementState::Pressed));
+        input.handle_event(&window_event(WindowEvent::Focused(false)));
+        assert!(input.held.is_empty() && input.released.contains(&right));
+        #[allow(deprecated)]
+        input.handle_event(&window_event(WindowEvent::CursorMoved {device_id: unsafe {DeviceId::dummy()}, position: PhysicalPosition::new(4.0, 5.0), modifiers: ModifiersState::empty()}));
+        assert_eq!(input.cursor, Some([4.0, 5.0]));
+        assert!(input.cursor_moved);
+    }
+}
@@ -0,0 +1,148 @@
+use cgmath::Vector3;
+use crate::chunk::{ChunkMap, Voxel};
+
+// A named set of voxels that can be hidden as a whole
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    pub chunks: ChunkMap,
+}
+
+impl Layer {
+    pub fn new(name: &str, chunks: ChunkMap) -> Self {
+        Self {name: name.to_string(), visible: true, chunks}
+    }
+}
+
+// A voxel change, remembered to undo it later
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Edit {
+    pub layer: usize,
+    pub position: Vector3<i32>,
+    pub voxel: Voxel,
+}
+
+// The layers of a model, later layers cover earlier ones where both have a voxel.
+// The scene map that gets drawn is kept equal to the visible layers stacked on top of each other
+pub struct Layers {
+    pub layers: Vec<Layer>,
+    pub active: usize, // layer that edits go to
+}
+
+impl Layers {
+    // A single layer holding what is already in the scene
+    pub fn new(scene: &ChunkMap) -> Self {
+        Self {layers: vec![Layer::new("Base", scene.clone())], active: 0}
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn active(&self) -> &Layer {
+        &self.layers[self.active]
+    }
+
+    // The voxel of the topmost visible layer at `position`
+    pub fn resolve(&self, position: Vector3<i32>) -> Voxel {
+        self.layers.iter().rev().filter(|layer| layer.visible).find_map(|layer| layer.chunks.get(position))
+    }
+
+    // Change a voxel of the active layer and bring the scene up to date, returns the layer's previous voxel
+    pub fn set(&mut self, scene: &mut ChunkMap, position: Vector3<i32>, voxel: Voxel) -> Voxel {
+        self.apply(scene, Edit {layer: self.active, position, voxel}).voxel
+    }
+
+    // Make an edit to any layer, returns the edit that reverts it
+    pub fn apply(&mut self, scene: &mut ChunkMap, edit: Edit) -> Edit {
+        let previous: Voxel = self.layers[edit.layer].chunks.set(edit.position, edit.voxel);
+        scene.set(edit.position, self.resolve(edit.position));
+        Edit {voxel: previous, ..edit}
+    }
+
+    // Add an empty layer on top and make it the active one, returns its index
+    pub fn add(&mut self, name: &str) -> usize {
+        self.layers.push(Layer::new(name, ChunkMap::new()));
+        self.active = self.layers.len() - 1;
+        self.active
+    }
+
+    // Remove a layer and its voxels from the scene, the last remaining layer is kept
+    pub fn remove(&mut self, scene: &mut ChunkMap, index: usize) {
+        if self.layers.len() <= 1 || index >= self.layers.len() {
+            return;
+        }
+        let layer: Layer = self.layers.remove(index);
+        self.active = self.active.min(self.layers.len() - 1);
+        self.refresh(scene, layer.chunks.iter().map(|(position, _)| position));
+    }
+
+    pub fn set_visible(&mut self, scene: &mut ChunkMap, index: usize, visible: bool) {
+        if self.layers[index].visible != visible {
+            self.layers[index].visible = visible;
+            let positions: Vec<Vector3<i32>> = self.layers[index].chunks.iter().map(|(position, _)| position).collect();
+            self.refresh(scene, positions);
+        }
+    }
+
+    // Swap in other voxels for every layer, like those of another frame, and return the ones replaced
+    pub fn replace_chunks(&mut self, scene: &mut ChunkMap, chunks: Vec<ChunkMap>) -> Vec<ChunkMap> {
+        debug_assert_eq!(chunks.len(), self.layers.len());
+        let previous: Vec<ChunkMap> = self.layers.iter_mut().zip(chunks).map(|(layer, chunks)| std::mem::replace(&mut layer.chunks, chunks)).collect();
+        let positions: Vec<Vector3<i32>> = previous.iter().chain(self.layers.iter().map(|layer| &layer.chunks)).flat_map(|chunks| chunks.iter().map(|(position, _)| position)).collect();
+        self.refresh(scene, positions);
+        previous
+    }
+
+    // Recompute the scene at the given positions
+    fn refresh(&self, scene: &mut ChunkMap, positions: impl IntoIterator<Item = Vector3<i32>>) {
+        for position in positions {
+            scene.set(position, self.resolve(position));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+    use crate::chunk::ChunkMap;
+    use crate::layers::{Edit, Layers};
+
+    #[test]
+    fn test_hidden_layers_uncover_the_ones_below() {
+        let (a, b): (Vector3<i32>, Vector3<i32>) = (Vector3::new(0, 0, 0), Vector3::new(1, 0, 0));
+        let mut scene: ChunkMap = ChunkMap::new();
+        scene.set(a, Some(1));
+        let mut layers: Layers = Layers::new(&scene);
+
+        assert_eq!(layers.add("Top"), 1);
+        layers.set(&mut scene, a, Some(2));
+        layers.set(&mut scene, b, Some(3));
+        assert_eq!((scene.get(a), scene.get(b)), (Some(2), Some(3)));
+
+        layers.set_visible(&mut scene, 1, false);
+        assert_eq!((scene.get(a), scene.get(b)), (Some(1), None));
+        assert_eq!(layers.layers[1].chunks.len(), 2);
+
+        // edits to a hidden layer stay out of the scene
+        layers.set(&mut scene, b, None);
+        layers.set_visible(&mut scene, 1, true);
+        assert_eq!((scene.get(a), scene.get(b)), (Some(2), None));
+
+        // applying the returned edit reverts the change, on whichever layer it was made
+        layers.active = 0;
+        let revert: Edit = layers.apply(&mut scene, Edit {layer: 1, position: a, voxel: Some(5)});
+        assert_eq!(revert, Edit {layer: 1, position: a, voxel: Some(2)});
+        assert_eq!(scene.get(a), Some(5));
+        layers.apply(&mut scene, revert);
+        assert_eq!(scene.get(a), Some(2));
+
+        layers.remove(&mut scene, 1);
+        assert_eq!((layers.len(), layers.active), (1, 0));
+        assert_eq!(scene.get(a), Some(1));
+    }
+}
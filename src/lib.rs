@@ -1,22 +1,60 @@
 use std::mem::size_of;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use anyhow::Context;
 use bytemuck::{Pod, Zeroable};
 use cgmath::Matrix4;
 use wgpu::{BufferAddress, SurfaceError, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode};
-use winit::{event::*, event_loop::{ControlFlow, EventLoop}, window::WindowBuilder};
-use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::{event::*, event_loop::{ControlFlow, EventLoop}};
 use winit::window::Window;
-use crate::state::State;
+use crate::assets::Assets;
+use crate::model::{Model, PROJECT_EXTENSION};
+use crate::settings::{WindowMode, WindowSettings};
+use crate::state::{State, StateError};
 
-mod state;
-mod texture;
-mod camera;
+pub mod state;
+pub mod texture;
+pub mod camera;
 pub mod utils;
-mod voxel;
+pub mod voxel;
+pub mod palette;
+pub mod atlas;
+pub mod assets;
+pub mod pipeline;
+pub mod chunk;
+pub mod chunk_buffers;
+pub mod buffer_pool;
+pub mod culling;
+pub mod gpu_culling;
+pub mod stats;
+pub mod font;
+pub mod hud;
+pub mod gui;
+pub mod bindings;
+pub mod input;
+pub mod settings;
+pub mod layers;
+pub mod objects;
+pub mod gizmo;
+pub mod timeline;
+pub mod timestep;
+pub mod animation;
+pub mod model;
+pub mod project;
+pub mod vox;
+pub mod gltf;
+pub mod cli;
+pub mod shadow;
+pub mod post;
+pub mod pathtrace;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 struct Vertex {
     position: [f32; 3],
+    uv: [f32; 2],
 }
 
 unsafe impl Zeroable for Vertex {}
@@ -33,55 +71,115 @@ impl Vertex {
                     shader_location: 0,
                     format: VertexFormat::Float32x3
                 },
+                VertexAttribute {
+                    offset: size_of::<[f32; 3]>() as BufferAddress,
+                    shader_location: 1,
+                    format: VertexFormat::Float32x2
+                },
             ]
         }
     }
 }
 
+// Maps OpenGL clip space depth (-w..w) to wgpu's (0..w). Matrix4::new takes columns, so the
+// 0.5 offset belongs in the last column
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
-pub async fn run() {
-    env_logger::init();
+// Open the editor, with the model in `file` in place of the test scene when given. Saving writes a project next to it
+pub async fn run(settings: WindowSettings, file: Option<PathBuf>) -> anyhow::Result<()> {
+    // a file that can't be read fails before a window opens
+    let model: Option<Model> = file.as_deref().map(Model::load).transpose()?;
 
     // Create new event loop, and link window events to it
     let event_loop: EventLoop<()> = EventLoop::new();
-    let window: Window = WindowBuilder::new().with_inner_size(PhysicalSize::new(1920, 1080)).with_position(PhysicalPosition::new(1600, 0)).build(&event_loop).unwrap();
+    let window: Window = settings.window_builder(&event_loop).build(&event_loop).context("failed to create window")?;
 
-    let mut state: State = State::new(Some(window)).await;
+    let mut state: State = match State::new(Some(window), Assets::from_env()).await {
+        Ok(state) => state,
+        Err(e) => {
+            match &e {
+                StateError::NoAdapter => log::error!("voxelart needs a Vulkan, Metal, DirectX 12 or OpenGL capable driver"),
+                StateError::SurfaceUnsupported(_) => log::error!("the graphics adapter can't draw to this window, try another backend with WGPU_BACKEND"),
+                _ => {}
+            }
+            return Err(e.into());
+        }
+    };
+    state.set_vsync(settings.vsync);
+    if let (Some(file), Some(model)) = (file, model) {
+        state.set_model(model)?;
+        state.project = file.with_extension(PROJECT_EXTENSION);
+    }
 
     // Start main event loop
+    let mut last_frame: Instant = Instant::now();
     event_loop.run(move |event, _, control_flow| {
         state.input(control_flow, &event);
-        match &state.window {
-            Some(window) => {
-                match event {
-                    Event::WindowEvent {ref event, window_id} if window_id == window.id() => match event {
-                        WindowEvent::CloseRequested => {*control_flow = ControlFlow::ExitWithCode(0)}
-                        WindowEvent::Resized(physical_size) => {state.resize(*physical_size)}
-                        WindowEvent::ScaleFactorChanged {new_inner_size, .. } => {state.resize(**new_inner_size)}
-                        _ => {}
+        #[cfg(feature = "hot-reload")]
+        if let Event::MainEventsCleared = event {
+            state.reload_changed_shader();
+        }
+        if let Some(window) = &state.window {
+            match event {
+                Event::WindowEvent {ref event, window_id} if window_id == window.id() => match event {
+                    WindowEvent::CloseRequested => {
+                        save_geometry(window, settings.mode);
+                        *control_flow = ControlFlow::ExitWithCode(0)
                     }
+                    WindowEvent::Resized(physical_size) => {state.resize(*physical_size)}
+                    WindowEvent::ScaleFactorChanged {new_inner_size, .. } => {state.resize(**new_inner_size)}
+                    _ => {}
+                }
 
-                    Event::RedrawRequested(window_id)
-                    if window_id == window.id() => {
-                        state.update();
-                        match state.render() {Ok(_) => {}, Err(SurfaceError::Lost) => state.resize(state.size), Err(SurfaceError::OutOfMemory) => *control_flow = ControlFlow::ExitWithCode(-1), Err(e) => eprintln!("{:?}", e) }
-                    }
+                Event::RedrawRequested(window_id)
+                if window_id == window.id() => {
+                    let now: Instant = Instant::now();
+                    state.update(now - last_frame);
+                    last_frame = now;
+                    match state.render() {Ok(_) => {}, Err(SurfaceError::Lost) => state.resize(state.size), Err(SurfaceError::OutOfMemory) => *control_flow = ControlFlow::ExitWithCode(-1), Err(e) => eprintln!("{:?}", e) }
+                }
 
-                    Event::MainEventsCleared => {
+                // sleep until the next event unless something has to be drawn
+                Event::MainEventsCleared => {
+                    if state.wants_redraw() {
                         window.request_redraw();
                     }
-                    _ => {}
+                    if !matches!(control_flow, ControlFlow::ExitWithCode(_)) {
+                        *control_flow = match state.is_animating() {
+                            true => ControlFlow::Poll,
+                            false => idle_control_flow()
+                        };
+                    }
                 }
+                _ => {}
             }
-
-            _ => {}
         }
     });
-}
\ No newline at end of file
+}
+
+// Without hot reloading nothing changes until an event arrives, with it the shader file is checked a few times a second
+fn idle_control_flow() -> ControlFlow {
+    match cfg!(feature = "hot-reload") {
+        true => ControlFlow::WaitUntil(Instant::now() + Duration::from_millis(250)),
+        false => ControlFlow::Wait
+    }
+}
+
+// Store where the window is in the user's window settings, so the next session opens it there again
+fn save_geometry(window: &Window, mode: WindowMode) {
+    if mode != WindowMode::Windowed {
+        return;
+    }
+    let Some(path) = WindowSettings::path() else {return};
+    let mut settings: WindowSettings = WindowSettings::load();
+    settings.set_geometry(window.inner_size(), window.outer_position().ok(), window.is_maximized(), window.scale_factor());
+    if let Err(e) = settings.save(&path) {
+        log::error!("{:#}", e);
+    }
+}
@@ -1,5 +1,6 @@
-use voxelart::run;
+use std::process::ExitCode;
 
-fn main() {
-    pollster::block_on(run());
-}
\ No newline at end of file
+fn main() -> ExitCode {
+    env_logger::init();
+    voxelart::cli::main(std::env::args().skip(1).collect())
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use crate::chunk::ChunkMap;
+use crate::layers::Layer;
+use crate::objects::{bake, Object, Objects};
+use crate::palette::Palette;
+
+// Extension of the editor's own project files, which keep everything the editor knows about a model
+pub const PROJECT_EXTENSION: &str = "voxelart";
+
+// Seconds a frame is shown for when nothing says otherwise
+pub const DEFAULT_FRAME_DURATION: f32 = 0.1;
+
+// One frame of an animation. Every frame of a model has the same layers, with voxels of its own
+pub struct Frame {
+    pub layers: Vec<Layer>,
+    pub duration: f32, // seconds
+}
+
+impl Frame {
+    pub fn new(layers: Vec<Layer>) -> Self {
+        Self {layers, duration: DEFAULT_FRAME_DURATION}
+    }
+
+    // The visible layers stacked, later layers cover earlier ones like in `Layers`
+    pub fn scene(&self) -> ChunkMap {
+        let mut scene: ChunkMap = ChunkMap::new();
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            for (position, index) in layer.chunks.iter() {
+                scene.set(position, Some(index));
+            }
+        }
+        scene
+    }
+}
+
+// An object of a model with its frames. Objects animate apart from each other, with frames of their own
+pub struct ModelObject {
+    pub object: Object,
+    pub frames: Vec<Frame>,
+}
+
+// A model as stored in a file: its objects and the palette they share. A model that doesn't move has a single frame
+pub struct Model {
+    pub objects: Vec<ModelObject>,
+    pub palette: Palette,
+}
+
+// Lower case extension of a path, for picking a file format
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase()
+}
+
+impl Model {
+    // A model of a single object where its voxels are
+    pub fn new(frames: Vec<Frame>, palette: Palette) -> Self {
+        Self {objects: vec![ModelObject {object: Object::new("Model"), frames}], palette}
+    }
+
+    // Read a model, the format is picked by the file extension: MagicaVoxel .vox files or projects
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes: Vec<u8> = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        match extension(path).as_str() {
+            "vox" => crate::vox::parse(&bytes),
+            PROJECT_EXTENSION => crate::project::parse(&String::from_utf8_lossy(&bytes)),
+            other => bail!("can't read .{} files, expected a .vox or .{} file", other, PROJECT_EXTENSION)
+        }.with_context(|| format!("failed to load {}", path.display()))
+    }
+
+    // Write the model, the format is picked by the file extension. Projects keep the objects, .vox files every frame
+    // of the objects flattened into one grid, and glTF binaries (.glb) only the visible voxels of the first frame
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let bytes: Vec<u8> = match extension(path).as_str() {
+            "glb" => crate::gltf::to_glb(&self.scene(), &self.palette),
+            "vox" => crate::vox::write(self),
+            PROJECT_EXTENSION => crate::project::to_text(self).into_bytes(),
+            other => bail!("can't write .{} files, expected a .glb, .vox or .{} file", other, PROJECT_EXTENSION)
+        };
+        fs::write(path, bytes).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    // The scene graph of the objects
+    pub fn graph(&self) -> Objects {
+        Objects::new(self.objects.iter().map(|object| object.object.clone()).collect())
+    }
+
+    // The layers of the first frame of the first object
+    pub fn layers(&self) -> &[Layer] {
+        self.objects.first().and_then(|object| object.frames.first()).map_or(&[], |frame| &frame.layers)
+    }
+
+    // The frames of every object put into the world's grid, for formats without objects. Every frame has the layers
+    // of all objects, named after their object when there are several. Objects with fewer frames hold their last one
+    pub fn flatten(&self) -> Vec<Frame> {
+        let graph: Objects = self.graph();
+        let count: usize = self.objects.iter().map(|object| object.frames.len()).max().unwrap_or(0);
+        (0..count).map(|frame| {
+            let duration: f32 = self.objects.iter().find_map(|object| object.frames.get(frame)).map_or(DEFAULT_FRAME_DURATION, |frame| frame.duration);
+            let mut layers: Vec<Layer> = Vec::new();
+            for (index, object) in self.objects.iter().enumerate() {
+                let Some(source) = object.frames.get(frame).or(object.frames.last()) else {continue};
+                for layer in &source.layers {
+                    let mut chunks: ChunkMap = ChunkMap::new();
+                    bake(&layer.chunks, graph.world(index), &mut chunks);
+                    let name: String = match self.objects.len() {
+                        1 => layer.name.clone(),
+                        _ => format!("{}/{}", object.object.name, layer.name)
+                    };
+                    layers.push(Layer {name, visible: layer.visible && graph.is_visible(index), chunks});
+                }
+            }
+            Frame {layers, duration}
+        }).collect()
+    }
+
+    // The visible voxels of the first frame of every object, put into the world's grid
+    pub fn scene(&self) -> ChunkMap {
+        let graph: Objects = self.graph();
+        let mut scene: ChunkMap = ChunkMap::new();
+        for (index, object) in self.objects.iter().enumerate().filter(|(index, _)| graph.is_visible(*index)) {
+            if let Some(frame) = object.frames.first() {
+                bake(&frame.scene(), graph.world(index), &mut scene);
+            }
+        }
+        scene
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{bail, Result};
+use cgmath::{Deg, Euler, InnerSpace, Matrix4, One, Quaternion, Rad, Rotation3, SquareMatrix, Vector3, Vector4};
+use crate::chunk::ChunkMap;
+use crate::culling::Aabb;
+
+// Where an object sits in its parent: scaled and turned by `rotation` around its pivot, with the pivot moved to
+// `position`. The scale is the same along every axis, so children keep their shape whatever their parents do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub position: Vector3<f32>, // of the pivot, in the parent's space
+    pub rotation: Quaternion<f32>,
+    pub scale: f32, // size of a voxel in the parent's space
+    pub pivot: Vector3<f32>, // in the object's own voxel coordinates
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {position: Vector3::new(0.0, 0.0, 0.0), rotation: Quaternion::one(), scale: 1.0, pivot: Vector3::new(0.0, 0.0, 0.0)}
+    }
+}
+
+impl Transform {
+    // From the object's voxel coordinates to its parent's space
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position) * Matrix4::from(self.rotation) * Matrix4::from_scale(self.scale) * Matrix4::from_translation(-self.pivot)
+    }
+
+    // Turn around the pivot, about an axis of the parent's space
+    pub fn rotate(&mut self, axis: Vector3<f32>, degrees: f32) {
+        self.rotation = (Quaternion::from_axis_angle(axis.normalize(), Deg(degrees)) * self.rotation).normalize();
+    }
+
+    // The rotation as angles in degrees around x, y and z, for showing it
+    pub fn angles(&self) -> Vector3<f32> {
+        let euler: Euler<Rad<f32>> = Euler::from(self.rotation);
+        Vector3::new(Deg::from(euler.x).0, Deg::from(euler.y).0, Deg::from(euler.z).0)
+    }
+
+    // This transform followed by `parent`'s, as a single transform keeping the pivot
+    pub fn then(&self, parent: &Transform) -> Self {
+        Self {position: transform_point(parent.matrix(), self.position), rotation: (parent.rotation * self.rotation).normalize(), scale: parent.scale * self.scale, pivot: self.pivot}
+    }
+
+    // The transform that, followed by this one, gives `world`
+    pub fn relative(&self, world: &Transform) -> Self {
+        let inverse: Matrix4<f32> = self.matrix().invert().unwrap_or_else(Matrix4::identity);
+        Self {position: transform_point(inverse, world.position), rotation: (self.rotation.conjugate() * world.rotation).normalize(), scale: world.scale / self.scale, pivot: world.pivot}
+    }
+}
+
+fn transform_point(matrix: Matrix4<f32>, point: Vector3<f32>) -> Vector3<f32> {
+    (matrix * point.extend(1.0)).truncate()
+}
+
+// A voxel object of the scene, with its own voxels placed by its transform
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object {
+    pub name: String,
+    pub parent: Option<usize>, // moves the object along with it
+    pub transform: Transform,
+    pub visible: bool,
+}
+
+impl Object {
+    pub fn new(name: &str) -> Self {
+        Self {name: name.to_string(), parent: None, transform: Transform::default(), visible: true}
+    }
+}
+
+// The scene graph. Every object is placed in its parent's space, or in the world without a parent. The selected
+// object is the one being edited
+pub struct Objects {
+    pub objects: Vec<Object>,
+    pub selected: usize,
+}
+
+impl Objects {
+    pub fn new(objects: Vec<Object>) -> Self {
+        Self {objects, selected: 0}
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn selected(&self) -> &Object {
+        &self.objects[self.selected]
+    }
+
+    // Parents have to exist, and no object can be its own ancestor
+    pub fn validate(&self) -> Result<()> {
+        for (index, object) in self.objects.iter().enumerate() {
+            let mut parent: Option<usize> = object.parent;
+            for _ in 0..self.len() {
+                match parent {
+                    Some(parent) if parent >= self.len() => bail!("object {} has a parent {} that doesn't exist", index, parent),
+                    Some(next) => parent = self.objects[next].parent,
+                    None => break
+                }
+            }
+            if parent.is_some() {
+                bail!("object {} is its own ancestor", index);
+            }
+        }
+        Ok(())
+    }
+
+    // The placement of an object in the world, through its parents
+    pub fn world_transform(&self, index: usize) -> Transform {
+        let object: &Object = &self.objects[index];
+        match object.parent {
+            Some(parent) => object.transform.then(&self.world_transform(parent)),
+            None => object.transform
+        }
+    }
+
+    // From an object's voxel coordinates to the world
+    pub fn world(&self, index: usize) -> Matrix4<f32> {
+        self.world_transform(index).matrix()
+    }
+
+    // Hidden objects hide their children
+    pub fn is_visible(&self, index: usize) -> bool {
+        let object: &Object = &self.objects[index];
+        object.visible && object.parent.is_none_or(|parent| self.is_visible(parent))
+    }
+
+    // Whether `ancestor` is `index` or one of its parents
+    fn is_ancestor(&self, ancestor: usize, index: usize) -> bool {
+        ancestor == index || self.objects[index].parent.is_some_and(|parent| self.is_ancestor(ancestor, parent))
+    }
+
+    // Move an object under another one, or to the top without a parent. It stays where it is in the world.
+    // Returns false when the parent is the object itself or one of its children
+    pub fn set_parent(&mut self, index: usize, parent: Option<usize>) -> bool {
+        if parent.is_some_and(|parent| parent >= self.len() || self.is_ancestor(index, parent)) {
+            return false;
+        }
+        let world: Transform = self.world_transform(index);
+        self.objects[index].transform = match parent {
+            Some(parent) => self.world_transform(parent).relative(&world),
+            None => world
+        };
+        self.objects[index].parent = parent;
+        true
+    }
+
+    // Add an object after the others without a parent, returns its index
+    pub fn add(&mut self, object: Object) -> usize {
+        self.objects.push(object);
+        self.len() - 1
+    }
+
+    // Remove an object, its children move to its parent and stay where they are. The last remaining object is kept
+    pub fn remove(&mut self, index: usize) -> bool {
+        if self.len() <= 1 || index >= self.len() {
+            return false;
+        }
+        for child in 0..self.len() {
+            if self.objects[child].parent == Some(index) {
+                self.set_parent(child, self.objects[index].parent);
+            }
+        }
+        self.objects.remove(index);
+        for object in &mut self.objects {
+            object.parent = object.parent.map(|parent| if parent > index {parent - 1} else {parent});
+        }
+        if self.selected > index || self.selected == self.len() {
+            self.selected -= 1;
+        }
+        true
+    }
+
+    // Every object after its parent, with its depth in the graph
+    pub fn tree(&self) -> Vec<(usize, usize)> {
+        let mut tree: Vec<(usize, usize)> = Vec::with_capacity(self.len());
+        let mut stack: Vec<(usize, usize)> = (0..self.len()).rev().filter(|index| self.objects[*index].parent.is_none()).map(|index| (index, 0)).collect();
+        while let Some((index, depth)) = stack.pop() {
+            tree.push((index, depth));
+            stack.extend((0..self.len()).rev().filter(|child| self.objects[*child].parent == Some(index)).map(|child| (child, depth + 1)));
+        }
+        tree
+    }
+}
+
+// Put the voxels of `chunks` into the grid of `into`, moved by `matrix`. Moves by whole voxels and turns by right
+// angles keep every voxel, other placements and scales take each cell of `into` from the voxel its centre falls in
+pub fn bake(chunks: &ChunkMap, matrix: Matrix4<f32>, into: &mut ChunkMap) {
+    let whole = |value: f32| (value - value.round()).abs() < 1e-4;
+    let round = |point: Vector4<f32>| Vector3::new(point.x.round() as i32, point.y.round() as i32, point.z.round() as i32);
+    // whole entries of at most 1 in the rotation part leave only right angles without scaling
+    if (0..4).all(|column| (0..4).all(|row| whole(matrix[column][row]) && (column == 3 || matrix[column][row].abs() < 1.5))) {
+        for (position, index) in chunks.iter() {
+            into.set(round(matrix * position.cast::<f32>().unwrap().extend(1.0)), Some(index));
+        }
+        return;
+    }
+
+    let Some(inverse) = matrix.invert() else {return};
+    for (pos, chunk) in chunks.chunks() {
+        if chunk.is_empty() {
+            continue;
+        }
+        let bounds: Aabb = Aabb::chunk(*pos).transformed(matrix);
+        let (min, max): (Vector3<i32>, Vector3<i32>) = (bounds.min.map(|c| c.round() as i32), bounds.max.map(|c| c.round() as i32));
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let local: Vector3<i32> = round(inverse * Vector4::new(x as f32, y as f32, z as f32, 1.0));
+                    if ChunkMap::split(local).0 == *pos {
+                        if let Some(index) = chunks.get(local) {
+                            into.set(Vector3::new(x, y, z), Some(index));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// World bounds of the voxels of an object, None when it has none
+pub fn world_bounds(chunks: &ChunkMap, matrix: Matrix4<f32>) -> Option<Aabb> {
+    let (min, max): (Vector3<i32>, Vector3<i32>) = chunks.bounds()?;
+    // voxels reach half a voxel past their position
+    let half: Vector3<f32> = Vector3::new(0.5, 0.5, 0.5);
+    Some(Aabb {min: min.cast().unwrap() - half, max: max.cast().unwrap() + half}.transformed(matrix))
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Matrix4, Vector3};
+    use crate::chunk::ChunkMap;
+    use crate::objects::{bake, Object, Objects};
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).magnitude() < 1e-4
+    }
+
+    #[test]
+    fn test_children_follow_their_parents() {
+        let mut body: Object = Object::new("Body");
+        body.transform.position = Vector3::new(10.0, 0.0, 0.0);
+        let mut arm: Object = Object::new("Arm");
+        arm.parent = Some(0);
+        arm.transform.position = Vector3::new(2.0, 5.0, 0.0);
+        arm.transform.pivot = Vector3::new(0.0, 3.0, 0.0);
+        let mut objects: Objects = Objects::new(vec![body, arm, Object::new("Hand")]);
+        let at = |objects: &Objects, index: usize, point: Vector3<f32>| (objects.world(index) * point.extend(1.0)).truncate();
+
+        // the arm's pivot lands on its position in the body
+        assert!(close(at(&objects, 1, Vector3::new(0.0, 3.0, 0.0)), Vector3::new(12.0, 5.0, 0.0)));
+        objects.objects[1].transform.rotate(Vector3::unit_z(), 90.0);
+        assert!(close(at(&objects, 1, Vector3::new(0.0, 0.0, 0.0)), Vector3::new(15.0, 5.0, 0.0)));
+        objects.objects[0].transform.position.y = 1.0;
+        assert!(close(at(&objects, 1, Vector3::new(0.0, 0.0, 0.0)), Vector3::new(15.0, 6.0, 0.0)));
+        // a bigger body makes the arm bigger as well
+        objects.objects[0].transform.scale = 2.0;
+        assert!(close(at(&objects, 1, Vector3::new(0.0, 0.0, 0.0)), Vector3::new(20.0, 11.0, 0.0)));
+
+        // parenting keeps the world placement, and loops are refused
+        let hand: Vector3<f32> = at(&objects, 2, Vector3::new(1.0, 2.0, 3.0));
+        assert!(objects.set_parent(2, Some(1)));
+        assert!(close(at(&objects, 2, Vector3::new(1.0, 2.0, 3.0)), hand));
+        assert!(!objects.set_parent(0, Some(2)));
+        assert!(!objects.set_parent(1, Some(1)));
+        assert_eq!(objects.tree(), vec![(0, 0), (1, 1), (2, 2)]);
+        objects.objects[0].visible = false;
+        assert!(!objects.is_visible(2));
+
+        // removing the arm hands the hand to the body, where it stays
+        objects.selected = 2;
+        assert!(objects.remove(1));
+        assert_eq!((objects.objects[1].parent, objects.selected), (Some(0), 1));
+        assert!(close(at(&objects, 1, Vector3::new(1.0, 2.0, 3.0)), hand));
+        objects.objects[1].parent = Some(1);
+        assert!(objects.validate().is_err());
+    }
+
+    #[test]
+    fn test_bake_keeps_right_angles_exact() {
+        let mut chunks: ChunkMap = ChunkMap::new();
+        for x in 0..40 {
+            chunks.set(Vector3::new(x, 0, 0), Some(x as u8));
+        }
+        let mut object: Object = Object::new("Rod");
+        object.transform.rotate(Vector3::unit_y(), 90.0);
+        object.transform.position = Vector3::new(1.0, 2.0, 3.0);
+
+        let mut baked: ChunkMap = ChunkMap::new();
+        bake(&chunks, object.transform.matrix(), &mut baked);
+        assert_eq!(baked.len(), 40);
+        assert_eq!((baked.get(Vector3::new(1, 2, 3)), baked.get(Vector3::new(1, 2, -36))), (Some(0), Some(39)));
+
+        // other angles resample, a rod along a diagonal keeps about its length
+        object.transform.rotate(Vector3::unit_y(), 45.0);
+        let mut baked: ChunkMap = ChunkMap::new();
+        bake(&chunks, object.transform.matrix(), &mut baked);
+        assert!(baked.len() >= 28 && baked.len() <= 60, "{}", baked.len());
+
+        // scaling up fills the cells in between
+        let mut scaled: ChunkMap = ChunkMap::new();
+        bake(&chunks, Matrix4::from_scale(2.0), &mut scaled);
+        assert!(scaled.len() > 60, "{}", scaled.len());
+        assert_eq!(scaled.get(Vector3::new(40, 0, 0)), Some(20));
+
+        let mut moved: ChunkMap = ChunkMap::new();
+        bake(&chunks, Matrix4::from_translation(Vector3::new(-5.0, 0.0, 0.0)), &mut moved);
+        assert_eq!(moved.get(Vector3::new(-5, 0, 0)), Some(0));
+    }
+}
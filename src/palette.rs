@@ -0,0 +1,271 @@
+use anyhow::{anyhow, bail, Context, Result};
+use cgmath::Vector4;
+use bytemuck::{Pod, Zeroable};
+use image::RgbaImage;
+use crate::voxel::NO_TILE;
+
+// Voxels address the palette with 8 bits
+pub const MAX_ENTRIES: usize = 256;
+
+// How the surface of a palette entry responds to light, shared by the rasteriser and the path tracer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub emission: f32, // emitted radiance as a multiple of the colour
+    pub roughness: f32, // 0 is a mirror, 1 fully rough
+    pub metalness: f32, // metals tint their reflections with the colour and have no diffuse part
+    pub transparency: f32, // fraction of light let through, on top of the colour's alpha
+    pub ior: f32, // index of refraction of transparent entries
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {emission: 0.0, roughness: 1.0, metalness: 0.0, transparency: 0.0, ior: 1.5}
+    }
+}
+
+// A single colour in the palette, optionally textured with a tile from the texture atlas
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaletteEntry {
+    pub color: Vector4<f32>,
+    pub tile: Option<u32>,
+    pub material: Material,
+}
+
+// Palette entry as laid out in the shader's uniform array. The alpha of the colour is the entry's opacity
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+pub struct PaletteEntryRaw {
+    pub color: [f32; 4],
+    pub tile: u32,
+    pub emission: f32,
+    pub roughness: f32,
+    pub metalness: f32,
+}
+
+// Colours shared by every voxel in a model, addressed by index
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub entries: Vec<PaletteEntry>,
+}
+
+impl PaletteEntry {
+    pub fn new(color: Vector4<f32>) -> Self {
+        Self {color, tile: None, material: Material::default()}
+    }
+
+    pub fn textured(color: Vector4<f32>, tile: u32) -> Self {
+        Self {color, tile: Some(tile), material: Material::default()}
+    }
+
+    pub fn with_material(self, material: Material) -> Self {
+        Self {material, ..self}
+    }
+
+    // Fraction of the light the entry blocks, from the colour's alpha and the material's transparency
+    pub fn opacity(&self) -> f32 {
+        self.color.w * (1.0 - self.material.transparency)
+    }
+
+    // Voxels behind a see-through entry stay visible, so it doesn't hide the faces of its neighbours
+    pub fn is_see_through(&self) -> bool {
+        self.opacity() < 1.0
+    }
+
+    // A line of a text palette without its comment, `rrggbb[aa] [tile] [property=value ...]`
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut fields = line.split_whitespace();
+        let color: Vector4<f32> = fields.next().map(parse_hex_color).unwrap_or_else(|| Err(anyhow!("missing colour")))?;
+        let mut tile: Option<u32> = None;
+        let mut material: Material = Material::default();
+        for field in fields {
+            match field.split_once('=') {
+                Some((name, value)) => parse_property(&mut material, name, value)?,
+                None if tile.is_none() => tile = Some(field.parse().with_context(|| format!("invalid tile index {:?}", field))?),
+                None => bail!("unexpected {:?} after the tile index", field)
+            }
+        }
+        Ok(Self {color, tile, material})
+    }
+
+    fn to_raw(self) -> PaletteEntryRaw {
+        let mut color: [f32; 4] = self.color.into();
+        color[3] = self.opacity();
+        PaletteEntryRaw {color, tile: self.tile.unwrap_or(NO_TILE), emission: self.material.emission, roughness: self.material.roughness, metalness: self.material.metalness}
+    }
+}
+
+impl Palette {
+    pub fn new(entries: Vec<PaletteEntry>) -> Self {
+        Self {entries}
+    }
+
+    pub fn get(&self, index: usize) -> Option<&PaletteEntry> {
+        self.entries.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Exactly MAX_ENTRIES entries for the gpu, missing entries are transparent black
+    pub fn to_raw(&self) -> Vec<PaletteEntryRaw> {
+        if self.len() > MAX_ENTRIES {
+            log::warn!("palette has {} entries, only the first {} are used", self.len(), MAX_ENTRIES);
+        }
+
+        let mut raw: Vec<PaletteEntryRaw> = self.entries.iter().take(MAX_ENTRIES).map(|entry| entry.to_raw()).collect();
+        raw.resize(MAX_ENTRIES, PaletteEntryRaw {tile: NO_TILE, ..Default::default()});
+        raw
+    }
+
+    // Entries can only use tiles the texture atlas has, the shader would sample outside it otherwise
+    pub fn check_tiles(&self, tile_count: u32) -> Result<()> {
+        for (index, entry) in self.entries.iter().enumerate() {
+            if let Some(tile) = entry.tile.filter(|tile| *tile >= tile_count) {
+                bail!("palette entry {} uses tile {}, the texture atlas has {} tiles", index, tile, tile_count);
+            }
+        }
+        Ok(())
+    }
+
+    // Per palette index whether voxels behind it stay visible, missing entries count as see-through like on the gpu
+    pub fn see_through(&self) -> Vec<bool> {
+        let mut see_through: Vec<bool> = self.entries.iter().take(MAX_ENTRIES).map(PaletteEntry::is_see_through).collect();
+        see_through.resize(MAX_ENTRIES, true);
+        see_through
+    }
+
+    // Every pixel of the image becomes an entry, row by row (MagicaVoxel palettes are 256x1 images)
+    pub fn from_image(image: &RgbaImage) -> Self {
+        Self::new(image.pixels().map(|p| PaletteEntry::new(Vector4::from(p.0.map(|c| c as f32 / 255.0)))).collect())
+    }
+
+    // Text palettes list one entry per line as `rrggbb[aa] [tile] [property=value ...]`, `;` starts a comment.
+    // The properties are the material's: emission, roughness, metalness, transparency and ior
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut entries: Vec<PaletteEntry> = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line: &str = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            entries.push(PaletteEntry::parse(line).with_context(|| format!("line {}", number + 1))?);
+        }
+
+        if entries.is_empty() {
+            bail!("palette has no entries");
+        }
+        Ok(Self::new(entries))
+    }
+
+    // The text format read by `parse`, properties are only written where they differ from the default material
+    pub fn to_text(&self) -> String {
+        let default: Material = Material::default();
+        let mut text: String = String::new();
+        for entry in &self.entries {
+            let channels: [u8; 4] = Into::<[f32; 4]>::into(entry.color).map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+            text += &format!("{:02x}{:02x}{:02x}", channels[0], channels[1], channels[2]);
+            if channels[3] != 255 {
+                text += &format!("{:02x}", channels[3]);
+            }
+            if let Some(tile) = entry.tile {
+                text += &format!(" {}", tile);
+            }
+            let material: &Material = &entry.material;
+            for (name, value, default) in [("emission", material.emission, default.emission), ("roughness", material.roughness, default.roughness), ("metalness", material.metalness, default.metalness), ("transparency", material.transparency, default.transparency), ("ior", material.ior, default.ior)] {
+                if value != default {
+                    text += &format!(" {}={}", name, value);
+                }
+            }
+            text += "\n";
+        }
+        text
+    }
+}
+
+fn parse_property(material: &mut Material, name: &str, value: &str) -> Result<()> {
+    let parsed: f32 = value.parse().with_context(|| format!("invalid value {:?} for {}", value, name))?;
+    let (field, range): (&mut f32, std::ops::RangeInclusive<f32>) = match name {
+        "emission" => (&mut material.emission, 0.0..=f32::MAX),
+        "roughness" => (&mut material.roughness, 0.0..=1.0),
+        "metalness" => (&mut material.metalness, 0.0..=1.0),
+        "transparency" => (&mut material.transparency, 0.0..=1.0),
+        "ior" => (&mut material.ior, 1.0..=f32::MAX),
+        _ => bail!("unknown material property {:?}", name)
+    };
+    if !range.contains(&parsed) {
+        bail!("{} must be between {} and {}, got {}", name, range.start(), range.end(), parsed);
+    }
+    *field = parsed;
+    Ok(())
+}
+
+fn parse_hex_color(hex: &str) -> Result<Vector4<f32>> {
+    let hex: &str = hex.trim_start_matches('#');
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        bail!("invalid colour {:?}, expected rrggbb or rrggbbaa", hex);
+    }
+
+    let mut channels: [f32; 4] = [1.0; 4];
+    for (i, channel) in channels.iter_mut().take(hex.len() / 2).enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).with_context(|| format!("invalid colour {:?}", hex))? as f32 / 255.0;
+    }
+    Ok(channels.into())
+}
+
+impl Default for Palette {
+    // a 6x6x6 colour cube, 39 greys and white textured with the first atlas tile, 256 entries in total
+    fn default() -> Self {
+        let cube = (0..216).map(|i| PaletteEntry::new(((i % 6) as f32 / 5.0, (i / 6 % 6) as f32 / 5.0, (i / 36) as f32 / 5.0, 1.0).into()));
+        let greys = (0..39).map(|i| PaletteEntry::new((i as f32 / 38.0, i as f32 / 38.0, i as f32 / 38.0, 1.0).into()));
+        Self::new(cube.chain(greys).chain([PaletteEntry::textured((1.0, 1.0, 1.0, 1.0).into(), 0)]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::palette::{Material, Palette, PaletteEntry};
+
+    #[test]
+    fn test_parse_palette() {
+        let palette: Palette = Palette::parse("; comment\nff0000\n#00ff0080 3 ; textured\n\n").unwrap();
+
+        assert_eq!(palette.entries, vec![
+            PaletteEntry::new((1.0, 0.0, 0.0, 1.0).into()),
+            PaletteEntry::textured((0.0, 1.0, 0.0, 128.0 / 255.0).into(), 3),
+        ]);
+        assert!(Palette::parse("ff00").is_err());
+        assert!(Palette::parse("ff0000 tile").is_err());
+        assert!(Palette::parse("").is_err());
+
+        assert!(palette.check_tiles(4).is_ok());
+        assert!(palette.check_tiles(3).is_err());
+        assert!(Palette::default().check_tiles(1).is_ok());
+    }
+
+    #[test]
+    fn test_material_properties() {
+        let palette: Palette = Palette::parse("ffffff emission=2.5\n8080ff 1 transparency=0.5 ior=1.33 ; water\ncc8844 metalness=1 roughness=0.2").unwrap();
+
+        assert_eq!(palette.entries[0].material, Material {emission: 2.5, ..Material::default()});
+        assert_eq!((palette.entries[1].tile, palette.entries[1].material), (Some(1), Material {transparency: 0.5, ior: 1.33, ..Material::default()}));
+        assert_eq!(palette.entries[2].material, Material {metalness: 1.0, roughness: 0.2, ..Material::default()});
+        assert_eq!(palette.see_through()[..3], [false, true, false]);
+        assert_eq!(palette.to_raw()[1].color[3], 0.5);
+
+        // written back exactly as read
+        assert_eq!(Palette::parse(&palette.to_text()).unwrap(), palette);
+        assert_eq!(palette.to_text().lines().next(), Some("ffffff emission=2.5"));
+
+        assert!(Palette::parse("ffffff shininess=1").is_err());
+        assert!(Palette::parse("ffffff roughness=2").is_err());
+        assert!(Palette::parse("ffffff ior=glass").is_err());
+        assert!(Palette::parse("ffffff 1 2").is_err());
+    }
+}
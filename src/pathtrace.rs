@@ -0,0 +1,562 @@
+use std::path::Path;
+use std::sync::Mutex;
+use anyhow::Context;
+use cgmath::{ElementWise, InnerSpace, Point3, Vector3, VectorSpace, Zero};
+use image::{Rgba, RgbaImage};
+use crate::camera::Camera;
+use crate::chunk::{ChunkMap, Voxel};
+use crate::palette::{Material, Palette};
+
+// Offset along the normal when a ray leaves a surface, so it doesn't hit the face it started on
+const EPSILON: f32 = 1e-3;
+
+// Gradient sky with a sun, the only light source besides emissive voxels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sky {
+    pub zenith: Vector3<f32>,
+    pub horizon: Vector3<f32>,
+    pub ground: Vector3<f32>,
+    pub sun_direction: Vector3<f32>, // from the sun towards the scene, like DirectionalLight
+    pub sun_color: Vector3<f32>, // irradiance on a surface facing the sun
+    pub sun_size: f32, // angular radius in degrees
+}
+
+impl Sky {
+    // Radiance arriving along `-direction`. The sun disc only shows up where it isn't sampled directly
+    fn radiance(&self, direction: Vector3<f32>, with_sun: bool) -> Vector3<f32> {
+        let up: f32 = direction.y;
+        let mut color: Vector3<f32> = if up >= 0.0 {
+            self.horizon.lerp(self.zenith, up.sqrt())
+        } else {
+            self.horizon.lerp(self.ground, (-up).sqrt().min(1.0))
+        };
+
+        let cos_size: f32 = self.sun_size.to_radians().cos();
+        if with_sun && direction.dot(-self.sun_direction.normalize()) >= cos_size {
+            let solid_angle: f32 = 2.0 * std::f32::consts::PI * (1.0 - cos_size);
+            color += self.sun_color / solid_angle;
+        }
+        color
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self {
+            zenith: Vector3::new(0.35, 0.55, 0.9),
+            horizon: Vector3::new(0.8, 0.85, 0.9),
+            ground: Vector3::new(0.3, 0.28, 0.25),
+            sun_direction: Vector3::new(-0.4, -1.0, -0.3).normalize(),
+            sun_color: Vector3::new(3.0, 2.9, 2.7),
+            sun_size: 1.5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32, // per pixel
+    pub max_bounces: u32,
+    pub threads: usize, // 0 uses every available core
+    pub exposure: f32,
+}
+
+impl Default for TraceSettings {
+    fn default() -> Self {
+        Self {width: 1920, height: 1080, samples: 128, max_bounces: 5, threads: 0, exposure: 1.0}
+    }
+}
+
+// Pinhole camera the primary rays start from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceCamera {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub fov: f32, // vertical, in degrees
+}
+
+impl From<&Camera> for TraceCamera {
+    fn from(camera: &Camera) -> Self {
+        Self {eye: camera.eye, target: camera.target, up: camera.up, fov: camera.fov}
+    }
+}
+
+// Where a ray crossed into a cell with different contents than the one it travelled through
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub t: f32,
+    pub normal: Vector3<f32>, // of the crossed face, pointing back along the ray
+    pub voxel: Voxel, // contents of the entered cell
+}
+
+// The voxels and their bounding box. The traversal looks cells up in the chunk map as it goes, so a few voxels
+// far apart don't need a dense copy of all the space between them
+pub struct VoxelGrid {
+    min: Vector3<i32>,
+    size: Vector3<i32>,
+    map: ChunkMap,
+}
+
+impl VoxelGrid {
+    pub fn new(map: &ChunkMap) -> Self {
+        let (min, max) = map.bounds().unwrap_or((Vector3::zero(), Vector3::new(-1, -1, -1)));
+        Self {min, size: max - min + Vector3::new(1, 1, 1), map: map.clone()}
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size.x <= 0
+    }
+
+    fn cell(&self, p: Vector3<i32>) -> Voxel {
+        let inside: bool = (0..self.size.x).contains(&p.x) && (0..self.size.y).contains(&p.y) && (0..self.size.z).contains(&p.z);
+        if inside {self.map.get(self.min + p)} else {None}
+    }
+
+    // Walk the cells along the ray (Amanatides & Woo) from the one containing `origin`, which is assumed to hold
+    // `medium`, until the contents change. Voxel p covers p - 0.5 to p + 0.5 in world space, like the rasteriser's cubes
+    pub fn intersect(&self, origin: Point3<f32>, direction: Vector3<f32>, medium: Voxel) -> Option<Hit> {
+        let o: Vector3<f32> = origin.to_homogeneous().truncate() - self.min.cast::<f32>().unwrap() + Vector3::new(0.5, 0.5, 0.5);
+        let size: Vector3<f32> = self.size.cast::<f32>().unwrap();
+        let inverse: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0).div_element_wise(direction);
+
+        // slab test against the grid, remembering the axis the ray enters through
+        let (mut t_enter, mut t_exit, mut axis): (f32, f32, usize) = (f32::MIN, f32::MAX, 0);
+        for i in 0..3 {
+            if direction[i] == 0.0 {
+                if o[i] < 0.0 || o[i] > size[i] {
+                    return self.leave(medium, 0.0, Vector3::zero());
+                }
+                continue;
+            }
+            let (a, b): (f32, f32) = ((0.0 - o[i]) * inverse[i], (size[i] - o[i]) * inverse[i]);
+            let (near, far): (f32, f32) = (a.min(b), a.max(b));
+            if near > t_enter {
+                t_enter = near;
+                axis = i;
+            }
+            t_exit = t_exit.min(far);
+        }
+        if self.is_empty() || t_exit < t_enter.max(0.0) {
+            return self.leave(medium, 0.0, Vector3::zero());
+        }
+
+        let step: Vector3<i32> = direction.map(|d| if d > 0.0 {1} else if d < 0.0 {-1} else {0});
+        let face_normal = |axis: usize| {
+            let mut normal: Vector3<f32> = Vector3::zero();
+            normal[axis] = -step[axis] as f32;
+            normal
+        };
+
+        let outside: bool = t_enter > 0.0;
+        let t_start: f32 = if outside {t_enter} else {0.0};
+        let start: Vector3<f32> = o + direction * t_start;
+        let mut cell: Vector3<i32> = Vector3::<usize>::new(0, 1, 2).map(|i| (start[i].floor() as i32).clamp(0, self.size[i] - 1));
+        if outside && self.cell(cell) != medium {
+            return Some(Hit {t: t_start, normal: face_normal(axis), voxel: self.cell(cell)});
+        }
+
+        let mut t_max: Vector3<f32> = Vector3::<usize>::new(0, 1, 2).map(|i| match step[i] {
+            0 => f32::INFINITY,
+            s => ((cell[i] + (s > 0) as i32) as f32 - o[i]) * inverse[i]
+        });
+        let t_delta: Vector3<f32> = inverse.map(|i| i.abs());
+
+        loop {
+            axis = if t_max.x < t_max.y {if t_max.x < t_max.z {0} else {2}} else if t_max.y < t_max.z {1} else {2};
+            let t: f32 = t_max[axis];
+            cell[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+
+            if cell[axis] < 0 || cell[axis] >= self.size[axis] {
+                return self.leave(medium, t, face_normal(axis));
+            }
+            let voxel: Voxel = self.cell(cell);
+            if voxel != medium {
+                return Some(Hit {t, normal: face_normal(axis), voxel});
+            }
+        }
+    }
+
+    // Leaving the grid only counts as a hit when the ray was travelling through a voxel
+    fn leave(&self, medium: Voxel, t: f32, normal: Vector3<f32>) -> Option<Hit> {
+        medium.map(|_| Hit {t, normal, voxel: None})
+    }
+}
+
+// Everything the tracer needs to know about the model, copied so renders can run next to editing
+pub struct TraceScene {
+    pub grid: VoxelGrid,
+    pub colors: Vec<Vector3<f32>>, // per palette entry
+    pub materials: Vec<Material>, // per palette entry, the transparency includes the colour's alpha
+    pub sky: Sky,
+}
+
+impl TraceScene {
+    pub fn new(map: &ChunkMap, palette: &Palette) -> Self {
+        let colors: Vec<Vector3<f32>> = palette.entries.iter().map(|entry| entry.color.truncate()).collect();
+        let materials: Vec<Material> = palette.entries.iter().map(|entry| Material {transparency: 1.0 - entry.opacity(), ..entry.material}).collect();
+        Self {grid: VoxelGrid::new(map), colors, materials, sky: Sky::default()}
+    }
+
+    fn surface(&self, index: u8) -> (Vector3<f32>, Material) {
+        let index: usize = index as usize;
+        (self.colors.get(index).copied().unwrap_or(Vector3::new(1.0, 1.0, 1.0)), self.materials.get(index).copied().unwrap_or_default())
+    }
+
+    // Whether the way from `origin` along `direction` is blocked, transparent voxels let a ray through by chance
+    fn occluded(&self, mut origin: Point3<f32>, direction: Vector3<f32>, mut medium: Voxel, rng: &mut Rng) -> bool {
+        for _ in 0..64 {
+            match self.grid.intersect(origin, direction, medium) {
+                None => return false,
+                Some(Hit {voxel: Some(index), ..}) if medium != Some(index) && rng.next_f32() >= self.surface(index).1.transparency => return true,
+                Some(hit) => {
+                    origin += direction * (hit.t + EPSILON);
+                    medium = hit.voxel;
+                }
+            }
+        }
+        true
+    }
+
+    // Radiance arriving at `origin` from `direction`. Every hit picks one lobe of the material at random:
+    // transmission by its transparency, a metallic reflection by its metalness, and diffuse otherwise
+    fn radiance(&self, mut origin: Point3<f32>, mut direction: Vector3<f32>, max_bounces: u32, rng: &mut Rng) -> Vector3<f32> {
+        let mut radiance: Vector3<f32> = Vector3::zero();
+        let mut throughput: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0);
+        let mut medium: Voxel = None;
+        let mut sees_sun: bool = true; // camera and specular rays, diffuse bounces sample the sun explicitly
+        let to_sun: Vector3<f32> = -self.sky.sun_direction.normalize();
+
+        for _ in 0..=max_bounces {
+            let hit: Hit = match self.grid.intersect(origin, direction, medium) {
+                Some(hit) => hit,
+                None => {
+                    radiance += throughput.mul_element_wise(self.sky.radiance(direction, sees_sun));
+                    break;
+                }
+            };
+            let position: Point3<f32> = origin + direction * hit.t;
+            let normal: Vector3<f32> = hit.normal;
+
+            let index: u8 = match hit.voxel {
+                Some(index) => index,
+                None => {
+                    // leaving a transparent voxel, the normal points back inside
+                    let ior: f32 = medium.map_or(1.0, |index| self.surface(index).1.ior);
+                    let (refracted, transmitted) = dielectric(direction, normal, ior, rng);
+                    direction = refracted;
+                    origin = position + normal * if transmitted {-EPSILON} else {EPSILON};
+                    if transmitted {
+                        medium = None;
+                    }
+                    continue;
+                }
+            };
+            let (albedo, material) = self.surface(index);
+            radiance += throughput.mul_element_wise(albedo * material.emission);
+
+            if rng.next_f32() < material.transparency {
+                let (refracted, transmitted) = dielectric(direction, normal, 1.0 / material.ior, rng);
+                direction = refracted;
+                if transmitted {
+                    throughput = throughput.mul_element_wise(albedo);
+                    origin = position - normal * EPSILON;
+                    medium = Some(index);
+                } else {
+                    origin = position + normal * EPSILON;
+                }
+                sees_sun = true;
+            } else if rng.next_f32() < material.metalness {
+                direction = (reflect(direction, normal) + rng.unit_vector() * material.roughness).normalize();
+                if direction.dot(normal) <= 0.0 {
+                    break;
+                }
+                throughput = throughput.mul_element_wise(albedo);
+                origin = position + normal * EPSILON;
+                sees_sun = true;
+            } else {
+                let lit: f32 = normal.dot(to_sun);
+                if lit > 0.0 && !self.occluded(position + normal * EPSILON, to_sun, medium, rng) {
+                    radiance += throughput.mul_element_wise(albedo).mul_element_wise(self.sky.sun_color) * (lit / std::f32::consts::PI);
+                }
+                throughput = throughput.mul_element_wise(albedo);
+                direction = rng.cosine_hemisphere(normal);
+                origin = position + normal * EPSILON;
+                sees_sun = false;
+            }
+
+            // russian roulette once the path carries little energy
+            let survival: f32 = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+            if survival < 0.1 {
+                if rng.next_f32() > survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+        radiance
+    }
+}
+
+fn reflect(direction: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+    direction - normal * 2.0 * direction.dot(normal)
+}
+
+// Reflect or refract at a surface with relative index `eta`, picked by the Schlick approximation of the
+// Fresnel term. Returns the new direction and whether it went through
+fn dielectric(direction: Vector3<f32>, normal: Vector3<f32>, eta: f32, rng: &mut Rng) -> (Vector3<f32>, bool) {
+    let cos_i: f32 = (-direction.dot(normal)).min(1.0);
+    let sin2_t: f32 = eta * eta * (1.0 - cos_i * cos_i);
+    let r0: f32 = ((1.0 - eta) / (1.0 + eta)).powi(2);
+    let reflectance: f32 = r0 + (1.0 - r0) * (1.0 - cos_i).powi(5);
+
+    if sin2_t > 1.0 || rng.next_f32() < reflectance {
+        return (reflect(direction, normal), false);
+    }
+    let cos_t: f32 = (1.0 - sin2_t).sqrt();
+    ((direction * eta + normal * (eta * cos_i - cos_t)).normalize(), true)
+}
+
+// Small pcg32 generator, seeded per pixel and sample so the image doesn't depend on the thread count
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        let mut rng: Rng = Rng(0);
+        rng.next_u32();
+        rng.0 = rng.0.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let state: u64 = self.0;
+        self.0 = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let xorshifted: u32 = (((state >> 18) ^ state) >> 27) as u32;
+        xorshifted.rotate_right((state >> 59) as u32)
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    fn unit_vector(&mut self) -> Vector3<f32> {
+        let z: f32 = self.next_f32() * 2.0 - 1.0;
+        let angle: f32 = self.next_f32() * 2.0 * std::f32::consts::PI;
+        let r: f32 = (1.0 - z * z).sqrt();
+        Vector3::new(r * angle.cos(), r * angle.sin(), z)
+    }
+
+    fn cosine_hemisphere(&mut self, normal: Vector3<f32>) -> Vector3<f32> {
+        let direction: Vector3<f32> = normal + self.unit_vector();
+        if direction.magnitude2() < 1e-8 {normal} else {direction.normalize()}
+    }
+}
+
+// Narkowicz's fit of the ACES filmic curve, the same as the post-processing chain
+fn aces(x: f32) -> f32 {
+    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+fn srgb(linear: f32) -> u8 {
+    let encoded: f32 = if linear <= 0.0031308 {linear * 12.92} else {1.055 * linear.powf(1.0 / 2.4) - 0.055};
+    (encoded * 255.0).round() as u8
+}
+
+// Progressive cpu path tracer: every call to `render_sample` adds one sample per pixel to the accumulation,
+// `image` can be taken at any point in between
+pub struct PathTracer {
+    pub scene: TraceScene,
+    pub camera: TraceCamera,
+    pub settings: TraceSettings,
+    accumulation: Vec<Vector3<f32>>,
+    samples: u32,
+}
+
+impl PathTracer {
+    pub fn new(scene: TraceScene, camera: TraceCamera, settings: TraceSettings) -> Self {
+        let pixels: usize = (settings.width * settings.height) as usize;
+        Self {scene, camera, settings, accumulation: vec![Vector3::zero(); pixels], samples: 0}
+    }
+
+    // Samples per pixel accumulated so far
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.samples >= self.settings.samples
+    }
+
+    // Trace one more sample for every pixel, rows are handed out to the worker threads as they finish
+    pub fn render_sample(&mut self) {
+        let (width, height): (u32, u32) = (self.settings.width, self.settings.height);
+        let threads: usize = match self.settings.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n
+        };
+
+        let forward: Vector3<f32> = (self.camera.target - self.camera.eye).normalize();
+        let right: Vector3<f32> = forward.cross(self.camera.up).normalize();
+        let up: Vector3<f32> = right.cross(forward);
+        let half_height: f32 = (self.camera.fov.to_radians() / 2.0).tan();
+        let half_width: f32 = half_height * width as f32 / height as f32;
+
+        let sample: u32 = self.samples;
+        self.samples += 1;
+        if width == 0 || height == 0 {
+            return;
+        }
+        let (scene, eye, max_bounces) = (&self.scene, self.camera.eye, self.settings.max_bounces);
+        let rows = Mutex::new(self.accumulation.chunks_mut(width as usize).enumerate());
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let Some((y, row)) = rows.lock().unwrap().next() else {
+                        break;
+                    };
+                    for (x, pixel) in row.iter_mut().enumerate() {
+                        let seed: u64 = ((y as u64 * width as u64 + x as u64) << 32) | sample as u64;
+                        let mut rng: Rng = Rng::new(seed);
+                        let u: f32 = ((x as f32 + rng.next_f32()) / width as f32) * 2.0 - 1.0;
+                        let v: f32 = 1.0 - ((y as f32 + rng.next_f32()) / height as f32) * 2.0;
+                        let direction: Vector3<f32> = (forward + right * (u * half_width) + up * (v * half_height)).normalize();
+
+                        let radiance: Vector3<f32> = scene.radiance(eye, direction, max_bounces, &mut rng);
+                        // fireflies and the odd nan would otherwise stay visible for the rest of the render
+                        if radiance.x.is_finite() && radiance.y.is_finite() && radiance.z.is_finite() {
+                            *pixel += radiance.map(|c| c.min(64.0));
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    // Render the remaining samples, `progress` is called after each one with the samples done so far
+    pub fn render(&mut self, mut progress: impl FnMut(&Self)) {
+        while !self.is_done() {
+            self.render_sample();
+            progress(self);
+        }
+    }
+
+    // Average of the samples so far, tone mapped like the rasteriser's output
+    pub fn image(&self) -> RgbaImage {
+        let scale: f32 = self.settings.exposure / self.samples.max(1) as f32;
+        RgbaImage::from_fn(self.settings.width, self.settings.height, |x, y| {
+            let color: Vector3<f32> = self.accumulation[(y * self.settings.width + x) as usize] * scale;
+            Rgba([srgb(aces(color.x)), srgb(aces(color.y)), srgb(aces(color.z)), 255])
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        self.image().save(path).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, Vector3};
+    use image::RgbaImage;
+    use crate::chunk::ChunkMap;
+    use crate::palette::{Material, Palette};
+    use crate::pathtrace::{Hit, PathTracer, TraceCamera, TraceScene, TraceSettings, VoxelGrid};
+
+    #[test]
+    fn test_grid_traversal() {
+        let mut map: ChunkMap = ChunkMap::new();
+        map.set(Vector3::new(-3, 0, 0), Some(1));
+        map.set(Vector3::new(5, 0, 0), Some(2));
+        map.set(Vector3::new(5, 4, 2), Some(3));
+        let grid: VoxelGrid = VoxelGrid::new(&map);
+
+        // from outside the grid, the first face crossed is the -x face of the voxel at x = -3
+        let hit: Hit = grid.intersect(Point3::new(-10.0, 0.0, 0.0), Vector3::unit_x(), None).unwrap();
+        assert!((hit.t - 6.5).abs() < 1e-5);
+        assert_eq!((hit.normal, hit.voxel), (-Vector3::unit_x(), Some(1)));
+
+        // from inside, skipping empty cells until the next voxel
+        let hit: Hit = grid.intersect(Point3::new(0.0, 0.0, 0.0), Vector3::unit_x(), None).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-5);
+        assert_eq!(hit.voxel, Some(2));
+
+        // travelling through a voxel ends where it is left
+        let hit: Hit = grid.intersect(Point3::new(5.0, 0.0, 0.0), Vector3::unit_y(), Some(2)).unwrap();
+        assert!((hit.t - 0.5).abs() < 1e-5);
+        assert_eq!((hit.normal, hit.voxel), (-Vector3::unit_y(), None));
+
+        // diagonal ray into the voxel at (5, 4, 2), and misses
+        let direction: Vector3<f32> = Vector3::new(0.0, 4.0, 2.0) / 20f32.sqrt();
+        assert_eq!(grid.intersect(Point3::new(5.0, 0.0, 0.0) + direction * 0.6, direction, None).map(|hit| hit.voxel), Some(Some(3)));
+        assert_eq!(grid.intersect(Point3::new(0.0, 2.0, 0.0), Vector3::unit_x(), None), None);
+        assert_eq!(grid.intersect(Point3::new(0.0, 20.0, 0.0), Vector3::unit_y(), None), None);
+    }
+
+    #[test]
+    fn test_grid_with_far_apart_voxels() {
+        // the bounding box holds more cells than fit in an i32, only the two voxels are stored
+        let mut map: ChunkMap = ChunkMap::new();
+        map.set(Vector3::new(-1_000_000, -1_000_000, -1_000_000), Some(1));
+        map.set(Vector3::new(1_000_000, 1_000_000, 1_000_000), Some(2));
+        let grid: VoxelGrid = VoxelGrid::new(&map);
+
+        let hit: Hit = grid.intersect(Point3::new(1_000_000.0, 1_000_000.0, 999_990.0), Vector3::unit_z(), None).unwrap();
+        assert!((hit.t - 9.5).abs() < 1e-2);
+        assert_eq!(hit.voxel, Some(2));
+    }
+
+    fn render(scene: TraceScene, threads: usize) -> RgbaImage {
+        let camera: TraceCamera = TraceCamera {eye: Point3::new(0.0, 0.0, 6.0), target: Point3::new(0.0, 0.0, 0.0), up: Vector3::unit_y(), fov: 40.0};
+        let mut tracer: PathTracer = PathTracer::new(scene, camera, TraceSettings {width: 24, height: 16, samples: 4, max_bounces: 3, threads, exposure: 1.0});
+        tracer.render(|_| {});
+        assert_eq!(tracer.samples(), 4);
+        tracer.image()
+    }
+
+    #[test]
+    fn test_render_is_independent_of_threads() {
+        let mut map: ChunkMap = ChunkMap::new();
+        map.set(Vector3::new(0, 0, 0), Some(0));
+        map.set(Vector3::new(1, 0, 0), Some(1));
+        map.set(Vector3::new(-1, 0, 0), Some(2));
+        let scene = || {
+            let mut scene: TraceScene = TraceScene::new(&map, &Palette::default());
+            scene.colors[0] = Vector3::new(1.0, 0.5, 0.2);
+            scene.materials[0] = Material {emission: 4.0, ..Material::default()};
+            scene.materials[1] = Material {metalness: 1.0, roughness: 0.2, ..Material::default()};
+            scene.materials[2] = Material {transparency: 1.0, ior: 1.5, ..Material::default()};
+            scene
+        };
+
+        let image: RgbaImage = render(scene(), 1);
+        assert_eq!(image, render(scene(), 3));
+
+        // the emissive voxel in the middle of the image glows in its own colour
+        let center = image.get_pixel(12, 8).0;
+        assert!(center[0] > 200 && center[0] > center[1] && center[1] > center[2], "{:?}", center);
+    }
+
+    #[test]
+    fn test_zero_sized_render() {
+        let camera: TraceCamera = TraceCamera {eye: Point3::new(0.0, 0.0, 6.0), target: Point3::new(0.0, 0.0, 0.0), up: Vector3::unit_y(), fov: 40.0};
+        let settings: TraceSettings = TraceSettings {width: 0, height: 16, samples: 2, ..Default::default()};
+        let mut tracer: PathTracer = PathTracer::new(TraceScene::new(&ChunkMap::new(), &Palette::default()), camera, settings);
+        tracer.render(|_| {});
+        assert!(tracer.is_done());
+        assert_eq!(tracer.image().dimensions(), (0, 16));
+    }
+
+    #[test]
+    fn test_empty_scene_shows_the_sky() {
+        let scene: TraceScene = TraceScene::new(&ChunkMap::new(), &Palette::default());
+        let image: RgbaImage = render(scene, 0);
+        // looking at the horizon, brighter and bluer towards the top
+        let (top, middle) = (image.get_pixel(12, 0).0, image.get_pixel(12, 8).0);
+        assert!(top[2] > top[0] && middle[0] > top[0], "{:?} {:?}", top, middle);
+    }
+}
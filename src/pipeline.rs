@@ -0,0 +1,102 @@
+use wgpu::{BlendComponent, BlendFactor, BlendOperation, BlendState, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device, Face, Features, FragmentState, FrontFace, MultisampleState, PipelineLayout, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModule, StencilState, VertexState};
+use crate::{Vertex, texture};
+use crate::post::{ACCUM_FORMAT, HDR_FORMAT, NORMAL_DEPTH_FORMAT, REVEALAGE_FORMAT};
+use crate::voxel::Instance;
+
+// How the voxels are drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    #[default]
+    Solid,
+    Wireframe,
+    Overlay, // wireframe drawn over the solid faces
+}
+
+impl RenderMode {
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::Solid => RenderMode::Wireframe,
+            RenderMode::Wireframe => RenderMode::Overlay,
+            RenderMode::Overlay => RenderMode::Solid,
+        }
+    }
+}
+
+// All pipelines sharing the voxel shader, rebuilt together when the shader changes
+pub struct Pipelines {
+    pub fill: RenderPipeline,
+    pub wireframe: RenderPipeline,
+    pub overlay: RenderPipeline,
+    pub transparent: RenderPipeline, // see-through palette entries, after the opaque passes
+    pub line_mode: bool, // whether wireframes are rasterised as lines, or as triangles with edges picked in the shader
+    pub sample_count: u32,
+}
+
+impl Pipelines {
+    pub fn new(device: &Device, layout: &PipelineLayout, shader: &ShaderModule, sample_count: u32) -> Self {
+        let line_mode: bool = device.features().contains(Features::POLYGON_MODE_LINE);
+        if !line_mode {
+            log::info!("POLYGON_MODE_LINE is not supported, drawing wireframes in the fragment shader");
+        }
+
+        // wireframes test against the depth of the solid faces without writing, pulled slightly towards the camera
+        let polygon_mode: PolygonMode = if line_mode {PolygonMode::Line} else {PolygonMode::Fill};
+        let wire_depth: DepthStencilState = DepthStencilState {format: texture::Texture::DEPTH_FORMAT, depth_write_enabled: false, depth_compare: CompareFunction::LessEqual, stencil: StencilState::default(), bias: DepthBiasState {constant: -2, slope_scale: -1.0, clamp: 0.0}};
+
+        let scene_targets = |blend: Option<BlendState>, normal_writes: ColorWrites| [Some(ColorTargetState {format: HDR_FORMAT, blend, write_mask: ColorWrites::ALL}), Some(ColorTargetState {format: NORMAL_DEPTH_FORMAT, blend: None, write_mask: normal_writes})];
+        // colours add up, the revealage multiplies by 1 - alpha of every layer
+        let add: BlendComponent = BlendComponent {src_factor: BlendFactor::One, dst_factor: BlendFactor::One, operation: BlendOperation::Add};
+        let reveal: BlendComponent = BlendComponent {src_factor: BlendFactor::Zero, dst_factor: BlendFactor::OneMinusSrc, operation: BlendOperation::Add};
+        let transparent_targets = [Some(ColorTargetState {format: ACCUM_FORMAT, blend: Some(BlendState {color: add, alpha: add}), write_mask: ColorWrites::ALL}), Some(ColorTargetState {format: REVEALAGE_FORMAT, blend: Some(BlendState {color: reveal, alpha: reveal}), write_mask: ColorWrites::ALL})];
+
+        Self {
+            fill: create_pipeline(device, layout, shader, sample_count, "Fill Pipeline", "vs_main", "fs_main", &scene_targets(None, ColorWrites::ALL), PolygonMode::Fill, DepthStencilState {format: texture::Texture::DEPTH_FORMAT, depth_write_enabled: true, depth_compare: CompareFunction::Less, stencil: StencilState::default(), bias: DepthBiasState::default()}),
+            wireframe: create_pipeline(device, layout, shader, sample_count, "Wireframe Pipeline", "vs_all", "fs_wireframe", &scene_targets(Some(BlendState::ALPHA_BLENDING), ColorWrites::ALL), polygon_mode, wire_depth.clone()),
+            // lines over solid faces keep the faces' normals, otherwise every line becomes an outline
+            overlay: create_pipeline(device, layout, shader, sample_count, "Overlay Pipeline", "vs_all", "fs_overlay", &scene_targets(Some(BlendState::ALPHA_BLENDING), ColorWrites::empty()), polygon_mode, wire_depth),
+            transparent: create_pipeline(device, layout, shader, sample_count, "Transparent Pipeline", "vs_transparent", "fs_transparent", &transparent_targets, PolygonMode::Fill, DepthStencilState {format: texture::Texture::DEPTH_FORMAT, depth_write_enabled: false, depth_compare: CompareFunction::Less, stencil: StencilState::default(), bias: DepthBiasState::default()}),
+            line_mode,
+            sample_count
+        }
+    }
+
+    // Pipelines to draw, in order, for a render mode
+    pub fn passes(&self, mode: RenderMode) -> Vec<&RenderPipeline> {
+        match mode {
+            RenderMode::Solid => vec![&self.fill],
+            RenderMode::Wireframe => vec![&self.wireframe],
+            RenderMode::Overlay => vec![&self.fill, &self.overlay],
+        }
+    }
+
+    // Pipeline of the transparent pass, wireframes draw every voxel in the first pass already. The pass still
+    // has to run to clear its targets
+    pub fn transparent_pass(&self, mode: RenderMode) -> Option<&RenderPipeline> {
+        (mode != RenderMode::Wireframe).then_some(&self.transparent)
+    }
+}
+
+// Highest sample count in `supported` that doesn't exceed `requested`, 1 is always supported
+pub fn closest_sample_count(supported: &[u32], requested: u32) -> u32 {
+    supported.iter().copied().filter(|count| *count <= requested).max().unwrap_or(1)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_pipeline(device: &Device, layout: &PipelineLayout, shader: &ShaderModule, sample_count: u32, label: &str, vertex: &str, fragment: &str, targets: &[Option<ColorTargetState>], polygon_mode: PolygonMode, depth_stencil: DepthStencilState) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {label: Some(label), layout: Some(layout), vertex: VertexState { module: shader, entry_point: vertex, buffers: &[Vertex::desc(), Instance::desc()]}, fragment: Some(FragmentState {module: shader, entry_point: fragment, targets}), primitive: PrimitiveState {topology: PrimitiveTopology::TriangleList, strip_index_format: None, front_face: FrontFace::Ccw, cull_mode: Some(Face::Back), polygon_mode, unclipped_depth: false, conservative: false}, multisample: MultisampleState {count: sample_count, mask: !0, alpha_to_coverage_enabled: false}, multiview: None, depth_stencil: Some(depth_stencil)})
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::closest_sample_count;
+
+    #[test]
+    fn test_closest_sample_count() {
+        let supported: [u32; 3] = [1, 2, 4];
+        assert_eq!(closest_sample_count(&supported, 4), 4);
+        assert_eq!(closest_sample_count(&supported, 8), 4);
+        assert_eq!(closest_sample_count(&supported, 3), 2);
+        assert_eq!(closest_sample_count(&supported, 0), 1);
+        assert_eq!(closest_sample_count(&[], 8), 1);
+    }
+}
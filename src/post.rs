@@ -0,0 +1,281 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::Matrix4;
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites, CommandEncoder, Device, FragmentState, LoadOp, MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureSampleType, TextureView, TextureViewDimension, VertexState};
+use crate::assets::Assets;
+use crate::texture::Texture;
+use crate::utils::create_wgpu_buffer;
+
+// Formats of the scene pass targets, the post-processing chain turns them into the output format
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const NORMAL_DEPTH_FORMAT: TextureFormat = TextureFormat::Rgba16Float; // view space normal, linear depth in w
+// weighted blended order independent transparency (McGuire & Bavoil): the sum of weighted premultiplied colours, and
+// the product of the transparent layers' transmittance
+pub const ACCUM_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const REVEALAGE_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+const AO_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+const CLEAR_COLOR: Color = Color {r: 0.1, g: 0.2, b: 0.3, a: 1.0};
+
+// Which effects run, and how strong they are
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostSettings {
+    pub ssao: bool,
+    pub outline: bool,
+    pub tonemap: bool,
+    pub ssao_radius: f32, // world units
+    pub exposure: f32,
+    pub outline_color: [f32; 4], // alpha is the strength of the lines
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {ssao: true, outline: true, tonemap: true, ssao_radius: 1.5, exposure: 1.0, outline_color: [0.02, 0.02, 0.02, 0.8]}
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+struct PostUniform {
+    proj: [[f32; 4]; 4],
+    size: [f32; 2],
+    ssao_radius: f32,
+    exposure: f32,
+    outline_color: [f32; 4],
+    flags: u32,
+    _pad: [u32; 3],
+}
+
+// Everything the scene pass renders into, multisampled targets are resolved into the single sampled ones
+pub struct SceneTargets {
+    pub width: u32,
+    pub height: u32,
+    pub sample_count: u32,
+    pub color: Texture,
+    pub normal_depth: Texture,
+    pub accum: Texture,
+    pub revealage: Texture,
+    color_msaa: Option<Texture>,
+    normal_depth_msaa: Option<Texture>,
+    accum_msaa: Option<Texture>,
+    revealage_msaa: Option<Texture>,
+    pub depth: Texture,
+    ao: Texture,
+}
+
+impl SceneTargets {
+    pub fn new(device: &Device, width: u32, height: u32, sample_count: u32) -> Self {
+        let (width, height): (u32, u32) = (width.max(1), height.max(1));
+        let msaa = |format: TextureFormat, label: &str| (sample_count > 1).then(|| Texture::create_render_target(device, width, height, format, sample_count, label));
+        Self {
+            width,
+            height,
+            sample_count,
+            color: Texture::create_render_target(device, width, height, HDR_FORMAT, 1, "scene color"),
+            normal_depth: Texture::create_render_target(device, width, height, NORMAL_DEPTH_FORMAT, 1, "scene normal depth"),
+            accum: Texture::create_render_target(device, width, height, ACCUM_FORMAT, 1, "transparency accumulation"),
+            revealage: Texture::create_render_target(device, width, height, REVEALAGE_FORMAT, 1, "transparency revealage"),
+            color_msaa: msaa(HDR_FORMAT, "scene color msaa"),
+            normal_depth_msaa: msaa(NORMAL_DEPTH_FORMAT, "scene normal depth msaa"),
+            accum_msaa: msaa(ACCUM_FORMAT, "transparency accumulation msaa"),
+            revealage_msaa: msaa(REVEALAGE_FORMAT, "transparency revealage msaa"),
+            depth: Texture::create_sized_depth_texture(device, width, height, sample_count, "depth texture"),
+            ao: Texture::create_render_target(device, width, height, AO_FORMAT, 1, "ambient occlusion"),
+        }
+    }
+
+    fn attachment<'a>(target: &'a Texture, msaa: &'a Option<Texture>, clear: Color) -> Option<RenderPassColorAttachment<'a>> {
+        Some(RenderPassColorAttachment {
+            view: msaa.as_ref().map_or(&target.view, |msaa| &msaa.view),
+            resolve_target: msaa.as_ref().map(|_| &target.view),
+            ops: Operations {load: LoadOp::Clear(clear), store: true}
+        })
+    }
+
+    // Clears all targets, the opaque voxel pipelines draw into it
+    pub fn begin<'a>(&'a self, encoder: &'a mut CommandEncoder) -> RenderPass<'a> {
+        encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[
+                Self::attachment(&self.color, &self.color_msaa, CLEAR_COLOR),
+                Self::attachment(&self.normal_depth, &self.normal_depth_msaa, Color::TRANSPARENT),
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(Operations {load: LoadOp::Clear(1.0), store: true}),
+                stencil_ops: None
+            }),
+        })
+    }
+
+    // Pass for the transparent voxels after the opaque ones, testing against their depth in any order
+    pub fn begin_transparent<'a>(&'a self, encoder: &'a mut CommandEncoder) -> RenderPass<'a> {
+        encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Transparent Pass"),
+            color_attachments: &[
+                Self::attachment(&self.accum, &self.accum_msaa, Color::TRANSPARENT),
+                Self::attachment(&self.revealage, &self.revealage_msaa, Color::WHITE),
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(Operations {load: LoadOp::Load, store: false}),
+                stencil_ops: None
+            }),
+        })
+    }
+}
+
+// Post-processing chain between the scene pass and the output texture. Doesn't know about windows,
+// so headless rendering goes through the same path
+pub struct PostProcess {
+    pub settings: PostSettings,
+    pub targets: SceneTargets,
+    uniform_buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    placeholder: Texture, // bound instead of the ao target while the ssao pass writes to it
+    ssao_bind_group: BindGroup,
+    bind_group: BindGroup,
+    ssao_pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
+}
+
+impl PostProcess {
+    pub fn new(device: &Device, assets: &Assets, output_format: TextureFormat, width: u32, height: u32, sample_count: u32) -> anyhow::Result<Self> {
+        let shader: ShaderModule = device.create_shader_module(ShaderModuleDescriptor {label: Some("Post Shader Module"), source: ShaderSource::Wgsl(assets.load_shader("post.wgsl")?.into())});
+
+        let texture = |binding: u32| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {multisampled: false, view_dimension: TextureViewDimension::D2, sample_type: TextureSampleType::Float {filterable: false}},
+            count: None
+        };
+        let bind_group_layout: BindGroupLayout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {ty: BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None},
+                    count: None
+                },
+                texture(1),
+                texture(2),
+                texture(3),
+                texture(4),
+                texture(5),
+            ],
+            label: Some("Post Bind Group Layout")
+        });
+
+        let layout: PipelineLayout = device.create_pipeline_layout(&PipelineLayoutDescriptor {label: Some("Post Pipeline Layout"), bind_group_layouts: &[&bind_group_layout], push_constant_ranges: &[]});
+        let pipeline = |label: &str, entry_point: &str, format: TextureFormat| device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+            vertex: VertexState {module: &shader, entry_point: "vs_fullscreen", buffers: &[]},
+            fragment: Some(FragmentState {module: &shader, entry_point, targets: &[Some(ColorTargetState {format, blend: None, write_mask: ColorWrites::ALL})]}),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None
+        });
+        let ssao_pipeline: RenderPipeline = pipeline("Ssao Pipeline", "fs_ssao", AO_FORMAT);
+        let composite_pipeline: RenderPipeline = pipeline("Composite Pipeline", "fs_composite", output_format);
+
+        let uniform_buffer: Buffer = create_wgpu_buffer(device, Some("Post Buffer"), cast_slice(&[PostUniform::default()]), BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let targets: SceneTargets = SceneTargets::new(device, width, height, sample_count);
+        let placeholder: Texture = Texture::create_render_target(device, 1, 1, AO_FORMAT, 1, "ao placeholder");
+        let ssao_bind_group: BindGroup = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, &targets, &placeholder);
+        let bind_group: BindGroup = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, &targets, &targets.ao);
+
+        Ok(Self {settings: PostSettings::default(), targets, uniform_buffer, bind_group_layout, placeholder, ssao_bind_group, bind_group, ssao_pipeline, composite_pipeline})
+    }
+
+    fn create_bind_group(device: &Device, layout: &BindGroupLayout, uniform: &Buffer, targets: &SceneTargets, ao: &Texture) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &[
+                BindGroupEntry {binding: 0, resource: uniform.as_entire_binding()},
+                BindGroupEntry {binding: 1, resource: BindingResource::TextureView(&targets.color.view)},
+                BindGroupEntry {binding: 2, resource: BindingResource::TextureView(&targets.normal_depth.view)},
+                BindGroupEntry {binding: 3, resource: BindingResource::TextureView(&ao.view)},
+                BindGroupEntry {binding: 4, resource: BindingResource::TextureView(&targets.accum.view)},
+                BindGroupEntry {binding: 5, resource: BindingResource::TextureView(&targets.revealage.view)},
+            ],
+            label: Some("Post Bind Group")
+        })
+    }
+
+    // Recreate the scene targets for a new size or sample count
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32, sample_count: u32) {
+        self.targets = SceneTargets::new(device, width, height, sample_count);
+        self.ssao_bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, &self.targets, &self.placeholder);
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, &self.targets, &self.targets.ao);
+    }
+
+    fn fullscreen_pass(encoder: &mut CommandEncoder, label: &str, pipeline: &RenderPipeline, bind_group: &BindGroup, view: &TextureView) {
+        let mut pass: RenderPass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(RenderPassColorAttachment {view, resolve_target: None, ops: Operations {load: LoadOp::Clear(Color::WHITE), store: true}})],
+            depth_stencil_attachment: None
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    // Record the chain after the scene pass, writing the final image to `output`
+    pub fn run(&self, queue: &Queue, encoder: &mut CommandEncoder, proj: Matrix4<f32>, output: &TextureView) {
+        let settings: &PostSettings = &self.settings;
+        let uniform: PostUniform = PostUniform {
+            proj: proj.into(),
+            size: [self.targets.width as f32, self.targets.height as f32],
+            ssao_radius: settings.ssao_radius,
+            exposure: settings.exposure,
+            outline_color: settings.outline_color,
+            flags: settings.ssao as u32 | (settings.outline as u32) << 1 | (settings.tonemap as u32) << 2,
+            _pad: [0; 3]
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniform]));
+
+        if settings.ssao {
+            Self::fullscreen_pass(encoder, "Ssao Pass", &self.ssao_pipeline, &self.ssao_bind_group, &self.targets.ao.view);
+        }
+        Self::fullscreen_pass(encoder, "Composite Pass", &self.composite_pipeline, &self.bind_group, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+    use pollster::FutureExt;
+    use crate::assets::Assets;
+    use crate::post::CLEAR_COLOR;
+    use crate::state::State;
+
+    fn srgb(linear: f64) -> u8 {
+        let encoded: f64 = if linear <= 0.0031308 {linear * 12.92} else {1.055 * linear.powf(1.0 / 2.4) - 0.055};
+        (encoded * 255.0).round() as u8
+    }
+
+    fn aces(x: f64) -> f64 {
+        ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+    }
+
+    // The background goes through the whole chain untouched apart from tone mapping
+    #[test]
+    fn test_background_through_the_chain() {
+        let mut state: State = State::new(None, Assets::default()).block_on().unwrap();
+        let clear: [f64; 3] = [CLEAR_COLOR.r, CLEAR_COLOR.g, CLEAR_COLOR.b];
+
+        for tonemap in [false, true] {
+            state.post.settings.tonemap = tonemap;
+            let image: RgbaImage = state.render_image(64, 48).unwrap();
+            assert_eq!(image.dimensions(), (64, 48));
+
+            let pixel = image.get_pixel(0, 0).0;
+            for (channel, linear) in clear.iter().enumerate() {
+                let expected: u8 = srgb(if tonemap {aces(*linear)} else {*linear});
+                assert!(pixel[channel].abs_diff(expected) <= 1, "tonemap {}: {:?}", tonemap, pixel);
+            }
+            assert_eq!(pixel[3], 255);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::{anyhow, bail, Context, Result};
+use cgmath::{Quaternion, Vector3};
+use crate::chunk::ChunkMap;
+use crate::layers::Layer;
+use crate::model::{Frame, Model, ModelObject};
+use crate::objects::{Object, Objects, Transform};
+use crate::palette::{Palette, PaletteEntry};
+
+// The editor's project files are text, `;` starts a comment. A `palette` line is followed by the entries of a
+// text palette. Every `object visible name` or `object hidden name` line starts an object, with its placement,
+// `layer visible name` or `layer hidden name` lines listing its layers bottom up, and `frame seconds` lines that
+// each start a frame with one `layer x y z index` line per voxel:
+//
+//   palette
+//   ff0000
+//   object visible Arm
+//   parent 0              ; index of the object it moves with
+//   position 4 10 0       ; where the pivot is in the parent
+//   rotation 0 0 0 1      ; quaternion x y z w, turning the object around its pivot
+//   scale 2               ; size of its voxels in the parent
+//   pivot 0 3 0           ; in the object's voxels
+//   layer visible Base
+//   frame 0.1
+//   0 1 2 3 0
+//
+// The placement lines can be left out for an object that isn't moved. Layers and frames before the first object
+// line belong to a single object, like in projects from before objects
+
+enum Section {
+    None,
+    Palette,
+    Frame,
+}
+
+// An object as far as it has been read
+struct ObjectText {
+    object: Object,
+    layers: Vec<Layer>,
+    frames: Vec<(f32, Vec<ChunkMap>)>,
+}
+
+// Whitespace separated numbers, exactly N of them
+fn floats<const N: usize>(text: &str) -> Result<[f32; N]> {
+    let values: Vec<f32> = text.split_whitespace().map(str::parse).collect::<Result<_, _>>().map_err(|_| anyhow!("invalid numbers {:?}", text))?;
+    values.try_into().map_err(|_| anyhow!("expected {} numbers", N))
+}
+
+fn visibility(text: &str) -> Result<(bool, &str)> {
+    let (visibility, name): (&str, &str) = text.split_once(char::is_whitespace).map_or((text, ""), |(visibility, name)| (visibility, name.trim()));
+    match visibility {
+        "visible" => Ok((true, name)),
+        "hidden" => Ok((false, name)),
+        _ => bail!("expected `visible name` or `hidden name`")
+    }
+}
+
+pub fn parse(text: &str) -> Result<Model> {
+    let mut entries: Vec<PaletteEntry> = Vec::new();
+    let mut objects: Vec<ObjectText> = Vec::new();
+    let mut section: Section = Section::None;
+
+    for (number, line) in text.lines().enumerate() {
+        let line: &str = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut parse_line = || -> Result<()> {
+            let (keyword, rest): (&str, &str) = line.split_once(char::is_whitespace).map_or((line, ""), |(keyword, rest)| (keyword, rest.trim()));
+            if keyword == "object" {
+                let (visible, name): (bool, &str) = visibility(rest)?;
+                objects.push(ObjectText {object: Object {visible, ..Object::new(name)}, layers: Vec::new(), frames: Vec::new()});
+                section = Section::None;
+                return Ok(());
+            }
+            if matches!(keyword, "parent" | "position" | "rotation" | "scale" | "pivot" | "layer" | "frame") && objects.is_empty() {
+                objects.push(ObjectText {object: Object::new("Model"), layers: Vec::new(), frames: Vec::new()});
+            }
+            match keyword {
+                "palette" => section = Section::Palette,
+                "parent" => objects.last_mut().unwrap().object.parent = Some(rest.parse().map_err(|_| anyhow!("invalid parent {:?}", rest))?),
+                "position" => objects.last_mut().unwrap().object.transform.position = floats::<3>(rest)?.into(),
+                "rotation" => {
+                    let [x, y, z, w] = floats::<4>(rest)?;
+                    objects.last_mut().unwrap().object.transform.rotation = Quaternion::new(w, x, y, z);
+                }
+                "scale" => {
+                    let scale: f32 = rest.parse().ok().filter(|scale: &f32| *scale > 0.0).ok_or_else(|| anyhow!("invalid scale {:?}", rest))?;
+                    objects.last_mut().unwrap().object.transform.scale = scale;
+                }
+                "pivot" => objects.last_mut().unwrap().object.transform.pivot = floats::<3>(rest)?.into(),
+                "layer" => {
+                    let object: &mut ObjectText = objects.last_mut().unwrap();
+                    if !object.frames.is_empty() {
+                        bail!("layers have to come before the frames");
+                    }
+                    let (visible, name): (bool, &str) = visibility(rest).map_err(|_| anyhow!("expected `layer visible name` or `layer hidden name`"))?;
+                    object.layers.push(Layer {visible, ..Layer::new(name, ChunkMap::new())});
+                    section = Section::None;
+                }
+                "frame" => {
+                    let duration: f32 = rest.parse().ok().filter(|duration: &f32| *duration > 0.0).ok_or_else(|| anyhow!("invalid frame duration {:?}", rest))?;
+                    let object: &mut ObjectText = objects.last_mut().unwrap();
+                    object.frames.push((duration, vec![ChunkMap::new(); object.layers.len()]));
+                    section = Section::Frame;
+                }
+                _ => match section {
+                    Section::Palette => entries.push(PaletteEntry::parse(line)?),
+                    Section::Frame => {
+                        let values: Vec<i32> = line.split_whitespace().map(str::parse).collect::<Result<_, _>>().map_err(|_| anyhow!("invalid voxel {:?}", line))?;
+                        let [layer, x, y, z, index] = values[..] else {bail!("expected a voxel as `layer x y z index`")};
+                        let (_, chunks) = objects.last_mut().unwrap().frames.last_mut().unwrap();
+                        let chunks: &mut ChunkMap = usize::try_from(layer).ok().and_then(|layer| chunks.get_mut(layer)).ok_or_else(|| anyhow!("there is no layer {}", layer))?;
+                        let index: u8 = u8::try_from(index).map_err(|_| anyhow!("invalid palette index {}", index))?;
+                        chunks.set(Vector3::new(x, y, z), Some(index));
+                    }
+                    Section::None => bail!("unexpected {:?} outside of the palette and the frames", line)
+                }
+            }
+            Ok(())
+        };
+        parse_line().with_context(|| format!("line {}", number + 1))?;
+    }
+
+    if entries.is_empty() {
+        bail!("the project has no palette");
+    }
+    if objects.is_empty() {
+        bail!("the project has no frames");
+    }
+    if let Some(object) = objects.iter().find(|object| object.frames.is_empty()) {
+        bail!("object {:?} has no frames", object.object.name);
+    }
+    Objects::new(objects.iter().map(|object| object.object.clone()).collect()).validate()?;
+
+    let objects: Vec<ModelObject> = objects.into_iter().map(|ObjectText {object, layers, frames}| ModelObject {
+        object,
+        frames: frames.into_iter().map(|(duration, chunks)| Frame {
+            layers: layers.iter().zip(chunks).map(|(layer, chunks)| Layer {name: layer.name.clone(), visible: layer.visible, chunks}).collect(),
+            duration
+        }).collect()
+    }).collect();
+    Ok(Model {objects, palette: Palette::new(entries)})
+}
+
+// The text read by `parse`. Voxels are sorted by position, so saving an unchanged model writes the same file
+pub fn to_text(model: &Model) -> String {
+    let mut text: String = String::from("; voxelart project\npalette\n");
+    text += &model.palette.to_text();
+    for ModelObject {object, frames} in &model.objects {
+        text += &format!("object {} {}\n", if object.visible {"visible"} else {"hidden"}, object.name);
+        let (transform, identity): (&Transform, Transform) = (&object.transform, Transform::default());
+        if let Some(parent) = object.parent {
+            text += &format!("parent {}\n", parent);
+        }
+        if transform.position != identity.position {
+            text += &format!("position {} {} {}\n", transform.position.x, transform.position.y, transform.position.z);
+        }
+        if transform.rotation != identity.rotation {
+            text += &format!("rotation {} {} {} {}\n", transform.rotation.v.x, transform.rotation.v.y, transform.rotation.v.z, transform.rotation.s);
+        }
+        if transform.scale != identity.scale {
+            text += &format!("scale {}\n", transform.scale);
+        }
+        if transform.pivot != identity.pivot {
+            text += &format!("pivot {} {} {}\n", transform.pivot.x, transform.pivot.y, transform.pivot.z);
+        }
+
+        for layer in frames.first().map_or(&[][..], |frame| &frame.layers) {
+            text += &format!("layer {} {}\n", if layer.visible {"visible"} else {"hidden"}, layer.name);
+        }
+        for frame in frames {
+            text += &format!("frame {}\n", frame.duration);
+            for (number, layer) in frame.layers.iter().enumerate() {
+                let mut voxels: Vec<(Vector3<i32>, u8)> = layer.chunks.iter().collect();
+                voxels.sort_by_key(|(position, _)| (position.x, position.y, position.z));
+                for (position, index) in voxels {
+                    text += &format!("{} {} {} {} {}\n", number, position.x, position.y, position.z, index);
+                }
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Vector4};
+    use crate::chunk::ChunkMap;
+    use crate::layers::Layer;
+    use crate::model::{Frame, Model, ModelObject};
+    use crate::objects::Object;
+    use crate::palette::{Palette, PaletteEntry};
+    use crate::project::{parse, to_text};
+
+    #[test]
+    fn test_project_round_trip() {
+        let layer = |name: &str, visible: bool, voxels: &[(i32, i32, i32, u8)]| {
+            let mut layer: Layer = Layer::new(name, ChunkMap::new());
+            layer.visible = visible;
+            for (x, y, z, index) in voxels {
+                layer.chunks.set(Vector3::new(*x, *y, *z), Some(*index));
+            }
+            layer
+        };
+        let frames: Vec<Frame> = vec![
+            Frame {layers: vec![layer("Body", true, &[(0, 0, 0, 1), (-40, 3, 2, 0)]), layer("Spare arm", false, &[(1, 1, 1, 1)])], duration: 0.25},
+            Frame {layers: vec![layer("Body", true, &[(0, 1, 0, 1)]), layer("Spare arm", false, &[])], duration: 0.5},
+        ];
+        let palette: Palette = Palette::new(vec![PaletteEntry::new(Vector4::new(1.0, 0.0, 0.0, 1.0)), PaletteEntry::textured(Vector4::new(0.0, 1.0, 0.0, 1.0), 2)]);
+        let mut model: Model = Model::new(frames, palette.clone());
+        let mut head: Object = Object {parent: Some(0), visible: false, ..Object::new("Head")};
+        head.transform.position = Vector3::new(0.5, 12.0, -3.0);
+        head.transform.pivot = Vector3::new(2.0, 0.0, 2.0);
+        head.transform.scale = 0.5;
+        head.transform.rotate(Vector3::new(1.0, 1.0, 0.0), 33.0);
+        model.objects.push(ModelObject {object: head.clone(), frames: vec![Frame::new(vec![layer("Skull", true, &[(2, 0, 2, 1)])])]});
+        let text: String = to_text(&model);
+        assert!(text.contains("layer hidden Spare arm\n"));
+
+        let model: Model = parse(&text).unwrap();
+        assert_eq!(model.palette, palette);
+        let frames: &[Frame] = &model.objects[0].frames;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(model.layers().iter().map(|layer| (layer.name.as_str(), layer.visible)).collect::<Vec<_>>(), vec![("Body", true), ("Spare arm", false)]);
+        assert_eq!((frames[0].duration, frames[1].duration), (0.25, 0.5));
+        assert_eq!(frames[0].layers[0].chunks.get(Vector3::new(-40, 3, 2)), Some(0));
+        assert_eq!(frames[0].layers[1].chunks.len(), 1);
+        assert_eq!((frames[1].layers[0].chunks.len(), frames[1].layers[1].chunks.len()), (1, 0));
+        assert_eq!(model.objects[1].object, head);
+        assert_eq!(model.objects[1].frames[0].layers[0].chunks.len(), 1);
+        assert_eq!(to_text(&model), text);
+
+        // projects from before objects hold a single one
+        let model: Model = parse("palette\nff0000\nlayer visible A\nframe 0.1\n0 1 2 3 0\n").unwrap();
+        assert_eq!((model.objects.len(), model.objects[0].object.name.as_str()), (1, "Model"));
+        assert_eq!(model.scene().get(Vector3::new(1, 2, 3)), Some(0));
+
+        assert!(parse("palette\nff0000\n").is_err());
+        assert!(parse("layer visible A\nframe 0.1\n0 0 0 0 0\n").is_err());
+        assert!(parse("palette\nff0000\nlayer visible A\nframe 0.1\n1 0 0 0 0\n").is_err());
+        assert!(parse("palette\nff0000\nlayer shown A\nframe 0.1\n").is_err());
+        assert!(parse("palette\nff0000\nlayer visible A\nframe 0\n").is_err());
+        assert!(parse("palette\nff0000\nobject visible A\nframe 0.1\nobject visible B\n").is_err());
+        assert!(parse("palette\nff0000\nobject visible A\nparent 0\nframe 0.1\n").is_err());
+        assert!(parse("palette\nff0000\nobject visible A\nposition 1 2\nframe 0.1\n").is_err());
+        assert!(parse("palette\nff0000\nobject visible A\nscale 0\nframe 0.1\n").is_err());
+    }
+}
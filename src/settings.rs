@@ -0,0 +1,230 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
+use winit::dpi::{LogicalPosition, LogicalSize, PhysicalPosition, PhysicalSize, Position, Size};
+use winit::event_loop::EventLoop;
+use winit::monitor::{MonitorHandle, VideoMode};
+use winit::window::{Fullscreen, WindowBuilder};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WindowMode {
+    #[default]
+    Windowed,
+    Borderless, // fullscreen window on the current monitor
+    Fullscreen, // exclusive, at the monitor's largest video mode
+}
+
+impl WindowMode {
+    fn name(self) -> &'static str {
+        match self {
+            WindowMode::Windowed => "windowed",
+            WindowMode::Borderless => "borderless",
+            WindowMode::Fullscreen => "fullscreen"
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        [WindowMode::Windowed, WindowMode::Borderless, WindowMode::Fullscreen].into_iter().find(|mode| mode.name() == text)
+            .ok_or_else(|| anyhow!("unknown window mode {:?}, expected windowed, borderless or fullscreen", text))
+    }
+}
+
+// How the window is opened, read from window.txt in the user's config directory and the command line.
+// Size and position are in logical pixels unless `physical` is set, so the window keeps its size on high dpi screens
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowSettings {
+    pub width: u32,
+    pub height: u32,
+    pub position: Option<(i32, i32)>, // None lets the system place the window
+    pub maximized: bool,
+    pub mode: WindowMode,
+    pub vsync: bool,
+    pub physical: bool, // size and position are physical pixels
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {width: 1280, height: 720, position: None, maximized: false, mode: WindowMode::default(), vsync: true, physical: false}
+    }
+}
+
+impl WindowSettings {
+    // One setting per line as `name = value`, `;` starts a comment. Settings that aren't listed keep their defaults
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut settings: WindowSettings = WindowSettings::default();
+        for (number, line) in text.lines().enumerate() {
+            let line: &str = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or_else(|| anyhow!("line {}: expected `name = value`", number + 1))?;
+            settings.set(name.trim(), value.trim()).with_context(|| format!("line {}", number + 1))?;
+        }
+        Ok(settings)
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let number = |value: &str| value.parse::<i32>().with_context(|| format!("invalid {} {:?}", name, value));
+        let flag = |value: &str| match value {
+            "true" | "on" | "yes" => Ok(true),
+            "false" | "off" | "no" => Ok(false),
+            _ => Err(anyhow!("invalid {} {:?}, expected true or false", name, value))
+        };
+        let size = |value: &str| match value.parse::<u32>() {
+            Ok(size) if size > 0 => Ok(size),
+            _ => Err(anyhow!("invalid {} {:?}, expected a positive number of pixels", name, value))
+        };
+
+        match name {
+            "width" => self.width = size(value)?,
+            "height" => self.height = size(value)?,
+            "x" => self.position = Some((number(value)?, self.position.map_or(0, |(_, y)| y))),
+            "y" => self.position = Some((self.position.map_or(0, |(x, _)| x), number(value)?)),
+            "maximized" => self.maximized = flag(value)?,
+            "mode" => self.mode = WindowMode::parse(value)?,
+            "vsync" => self.vsync = flag(value)?,
+            "physical" => self.physical = flag(value)?,
+            _ => bail!("unknown setting {:?}", name)
+        }
+        Ok(())
+    }
+
+    // The text format read by `parse`
+    pub fn to_text(&self) -> String {
+        let mut text: String = format!("width = {}\nheight = {}\n", self.width, self.height);
+        if let Some((x, y)) = self.position {
+            text += &format!("x = {}\ny = {}\n", x, y);
+        }
+        text += &format!("maximized = {}\nmode = {}\nvsync = {}\nphysical = {}\n", self.maximized, self.mode.name(), self.vsync, self.physical);
+        text
+    }
+
+    // Override settings with command line flags, returns the arguments that aren't window flags
+    pub fn apply_args(&mut self, args: impl IntoIterator<Item = String>) -> Result<Vec<String>> {
+        let mut rest: Vec<String> = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or_else(|| anyhow!("{} needs a value", flag));
+            match arg.as_str() {
+                "--width" => self.set("width", &value("--width")?)?,
+                "--height" => self.set("height", &value("--height")?)?,
+                "--position" => {
+                    let position: String = value("--position")?;
+                    let (x, y) = position.split_once(',').ok_or_else(|| anyhow!("invalid position {:?}, expected x,y", position))?;
+                    self.set("x", x)?;
+                    self.set("y", y)?;
+                }
+                "--maximized" => self.maximized = true,
+                "--windowed" => self.mode = WindowMode::Windowed,
+                "--borderless" => self.mode = WindowMode::Borderless,
+                "--fullscreen" => self.mode = WindowMode::Fullscreen,
+                "--vsync" => self.vsync = true,
+                "--no-vsync" => self.vsync = false,
+                "--physical" => self.physical = true,
+                _ => rest.push(arg)
+            }
+        }
+        Ok(rest)
+    }
+
+    // voxelart/window.txt in the user's config directory
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("voxelart").join("window.txt"))
+    }
+
+    // The user's settings, the defaults when there is no settings file or it can't be read
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {return Self::default()};
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).unwrap_or_else(|e| {
+                log::error!("ignoring {}: {:#}", path.display(), e);
+                Self::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                log::error!("failed to read {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        fs::write(path, self.to_text()).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    // Remember where a windowed window ended up, in the settings' units
+    pub fn set_geometry(&mut self, size: PhysicalSize<u32>, position: Option<PhysicalPosition<i32>>, maximized: bool, scale_factor: f64) {
+        self.maximized = maximized;
+        if maximized || size.width == 0 || size.height == 0 {
+            return;
+        }
+        let scale: f64 = if self.physical {1.0} else {scale_factor};
+        self.width = (size.width as f64 / scale).round() as u32;
+        self.height = (size.height as f64 / scale).round() as u32;
+        if let Some(position) = position {
+            self.position = Some(((position.x as f64 / scale).round() as i32, (position.y as f64 / scale).round() as i32));
+        }
+    }
+
+    pub fn window_builder<T>(&self, event_loop: &EventLoop<T>) -> WindowBuilder {
+        let size: Size = match self.physical {
+            true => PhysicalSize::new(self.width, self.height).into(),
+            false => LogicalSize::new(self.width, self.height).into()
+        };
+        let mut builder: WindowBuilder = WindowBuilder::new().with_title("voxelart").with_inner_size(size).with_maximized(self.maximized);
+        if let Some((x, y)) = self.position {
+            let position: Position = match self.physical {
+                true => PhysicalPosition::new(x, y).into(),
+                false => LogicalPosition::new(x, y).into()
+            };
+            builder = builder.with_position(position);
+        }
+
+        let monitor: Option<MonitorHandle> = event_loop.primary_monitor().or_else(|| event_loop.available_monitors().next());
+        let fullscreen: Option<Fullscreen> = match self.mode {
+            WindowMode::Windowed => None,
+            WindowMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+            WindowMode::Fullscreen => {
+                let video_mode: Option<VideoMode> = monitor.as_ref().and_then(|monitor| monitor.video_modes().max_by_key(|mode| (mode.size().width * mode.size().height, mode.refresh_rate_millihertz())));
+                match video_mode {
+                    Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                    None => {
+                        log::warn!("no video mode for exclusive fullscreen, using a borderless window");
+                        Some(Fullscreen::Borderless(monitor))
+                    }
+                }
+            }
+        };
+        builder.with_fullscreen(fullscreen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::dpi::{PhysicalPosition, PhysicalSize};
+    use crate::settings::{WindowMode, WindowSettings};
+
+    #[test]
+    fn test_parse_settings_and_flags() {
+        let mut settings: WindowSettings = WindowSettings::parse("width = 800 ; comment\nx = 10\ny = -20\nmode = borderless\nvsync = off\n").unwrap();
+        assert_eq!(settings, WindowSettings {width: 800, position: Some((10, -20)), mode: WindowMode::Borderless, vsync: false, ..Default::default()});
+        assert_eq!(WindowSettings::parse(&settings.to_text()).unwrap(), settings);
+        assert!(WindowSettings::parse("width = 0").is_err());
+        assert!(WindowSettings::parse("mode = tiny").is_err());
+        assert!(WindowSettings::parse("colour = red").is_err());
+
+        let rest: Vec<String> = settings.apply_args(["--height", "600", "model.vox", "--position", "5,6", "--fullscreen", "--vsync"].map(String::from)).unwrap();
+        assert_eq!(rest, vec!["model.vox".to_string()]);
+        assert_eq!((settings.height, settings.position, settings.mode, settings.vsync), (600, Some((5, 6)), WindowMode::Fullscreen, true));
+        assert!(settings.apply_args(["--width".to_string()]).is_err());
+
+        // geometry is stored in logical pixels, and kept from before the window was maximized
+        settings.set_geometry(PhysicalSize::new(1000, 500), Some(PhysicalPosition::new(200, 100)), false, 2.0);
+        assert_eq!((settings.width, settings.height, settings.position), (500, 250, Some((100, 50))));
+        settings.set_geometry(PhysicalSize::new(4000, 2000), None, true, 2.0);
+        assert_eq!((settings.width, settings.maximized), (500, true));
+    }
+}
@@ -1,41 +0,0 @@
-struct Instance {
-    @location(1) color: vec4<f32>,
-    @location(2) position: vec3<f32>
-};
-
-
-struct Camera {
-    view_proj: mat4x4<f32>
-}
-
-@group(1) @binding(0)
-var<uniform> camera: Camera;
-
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-}
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec4<f32>
-};
-
-@vertex
-fn vs_main(model: VertexInput, instance: Instance) -> VertexOutput {
-    var out: VertexOutput;
-
-    out.color = instance.color;
-    out.clip_position = camera.view_proj * vec4<f32>(model.position + instance.position, 1.0);
-    return out;
-}
-
-
-@group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0) @binding(1)
-var s_diffuse: sampler;
-
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return in.color;
-}
\ No newline at end of file
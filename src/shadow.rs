@@ -0,0 +1,171 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{ortho, EuclideanSpace, InnerSpace, Matrix4, Point3, Transform, Vector3};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferUsages, CommandEncoder, CompareFunction, DepthBiasState, DepthStencilState, Device, Face, FrontFace, LoadOp, MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, Queue, RenderPass, RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderModule, ShaderStages, StencilState, VertexState};
+use crate::culling::Aabb;
+use crate::texture::Texture;
+use crate::utils::create_wgpu_buffer;
+use crate::voxel::Instance;
+use crate::{Vertex, OPENGL_TO_WGPU_MATRIX};
+
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+// Sun-like light shining along `direction`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: Vector3<f32>, // from the light towards the scene
+    pub ambient: f32, // fraction of the colour left in full shadow
+    pub shadows: bool,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {direction: Vector3::new(-0.4, -1.0, -0.3).normalize(), ambient: 0.35, shadows: true}
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+pub struct LightUniform {
+    view_proj: [[f32; 4]; 4],
+    direction: [f32; 4], // towards the light
+    ambient: f32,
+    shadows: u32,
+    normal_offset: f32, // world units a position moves along its normal before the shadow lookup
+    _pad: u32,
+}
+
+// Orthographic light projection that tightly encloses `bounds`, so the whole shadow map covers the model
+pub fn fit_to_bounds(direction: Vector3<f32>, bounds: &Aabb) -> Matrix4<f32> {
+    let center: Point3<f32> = Point3::from_vec((bounds.min + bounds.max) / 2.0);
+    let radius: f32 = (bounds.max - bounds.min).magnitude() / 2.0;
+    let direction: Vector3<f32> = direction.normalize();
+    let up: Vector3<f32> = if direction.y.abs() > 0.99 {Vector3::unit_z()} else {Vector3::unit_y()};
+    let view: Matrix4<f32> = Matrix4::look_at_rh(center - direction * radius, center, up);
+
+    // bounds of the box in light space, the light looks down -z
+    let (mut min, mut max) = (Vector3::new(f32::MAX, f32::MAX, f32::MAX), Vector3::new(f32::MIN, f32::MIN, f32::MIN));
+    for corner in bounds.corners() {
+        let p: Point3<f32> = view.transform_point(Point3::from_vec(corner));
+        min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+        max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+    }
+    OPENGL_TO_WGPU_MATRIX * ortho(min.x, max.x, min.y, max.y, -max.z, -min.z) * view
+}
+
+// Depth of the scene as seen from the light, sampled with pcf by the main fragment shader
+pub struct ShadowMap {
+    pub texture: Texture,
+    pub light_buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl ShadowMap {
+    // `texture_layout` and `chunk_layout` are the voxel pipeline's groups 0 and 2, the light takes group 1
+    pub fn new(device: &Device, texture_layout: &BindGroupLayout, chunk_layout: &BindGroupLayout, shader: &ShaderModule) -> Self {
+        let texture: Texture = Texture::create_sized_depth_texture(device, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE, 1, "shadow map");
+        let light_buffer: Buffer = create_wgpu_buffer(device, Some("Light Buffer"), cast_slice(&[LightUniform::default()]), BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+
+        let bind_group_layout: BindGroupLayout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {ty: BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None},
+                    count: None
+                }
+            ],
+            label: Some("Shadow Bind Group Layout")
+        });
+        let bind_group: BindGroup = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {binding: 1, resource: light_buffer.as_entire_binding()}],
+            label: Some("Shadow Bind Group")
+        });
+
+        let layout: PipelineLayout = device.create_pipeline_layout(&PipelineLayoutDescriptor {label: Some("Shadow Pipeline Layout"), bind_group_layouts: &[texture_layout, &bind_group_layout, chunk_layout], push_constant_ranges: &[]});
+        let pipeline: RenderPipeline = Self::create_pipeline(device, &layout, shader);
+        Self {texture, light_buffer, bind_group_layout, bind_group, pipeline}
+    }
+
+    fn create_pipeline(device: &Device, layout: &PipelineLayout, shader: &ShaderModule) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(layout),
+            vertex: VertexState {module: shader, entry_point: "vs_shadow", buffers: &[Vertex::desc(), Instance::desc()]},
+            fragment: None,
+            primitive: PrimitiveState {topology: PrimitiveTopology::TriangleList, strip_index_format: None, front_face: FrontFace::Ccw, cull_mode: Some(Face::Back), polygon_mode: PolygonMode::Fill, unclipped_depth: false, conservative: false},
+            // slope scaled bias against acne on faces at a grazing angle to the light
+            depth_stencil: Some(DepthStencilState {format: Texture::DEPTH_FORMAT, depth_write_enabled: true, depth_compare: CompareFunction::LessEqual, stencil: StencilState::default(), bias: DepthBiasState {constant: 2, slope_scale: 2.0, clamp: 0.0}}),
+            multisample: MultisampleState::default(),
+            multiview: None
+        })
+    }
+
+    // Rebuild the pipeline after the voxel shader changed
+    pub fn set_shader(&mut self, device: &Device, texture_layout: &BindGroupLayout, chunk_layout: &BindGroupLayout, shader: &ShaderModule) {
+        let layout: PipelineLayout = device.create_pipeline_layout(&PipelineLayoutDescriptor {label: Some("Shadow Pipeline Layout"), bind_group_layouts: &[texture_layout, &self.bind_group_layout, chunk_layout], push_constant_ranges: &[]});
+        self.pipeline = Self::create_pipeline(device, &layout, shader);
+    }
+
+    // Point the light at the model, returns false when there is nothing to cast a shadow
+    pub fn update(&self, queue: &Queue, light: &DirectionalLight, bounds: Option<Aabb>) -> bool {
+        let (view_proj, normal_offset): (Matrix4<f32>, f32) = match bounds {
+            Some(bounds) => (fit_to_bounds(light.direction, &bounds), (bounds.max - bounds.min).magnitude() / SHADOW_MAP_SIZE as f32),
+            None => (Matrix4::from_scale(1.0), 0.0)
+        };
+        let shadows: bool = light.shadows && bounds.is_some();
+        let uniform: LightUniform = LightUniform {
+            view_proj: view_proj.into(),
+            direction: (-light.direction.normalize()).extend(0.0).into(),
+            ambient: light.ambient,
+            shadows: shadows as u32,
+            normal_offset,
+            _pad: 0
+        };
+        queue.write_buffer(&self.light_buffer, 0, cast_slice(&[uniform]));
+        shadows
+    }
+
+    // Start the depth pass from the light, the caller binds group 0 and draws the chunks with group 2
+    pub fn begin<'a>(&'a self, encoder: &'a mut CommandEncoder) -> RenderPass<'a> {
+        let mut pass: RenderPass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(Operations {load: LoadOp::Clear(1.0), store: true}),
+                stencil_ops: None
+            })
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix4, Vector3, Vector4};
+    use crate::culling::Aabb;
+    use crate::shadow::fit_to_bounds;
+
+    #[test]
+    fn test_light_projection_covers_the_bounds() {
+        let bounds: Aabb = Aabb {min: Vector3::new(-10.0, 0.0, -100.0), max: Vector3::new(90.0, 100.0, 0.0)};
+        for direction in [Vector3::new(-0.4, -1.0, -0.3), Vector3::new(0.0, -1.0, 0.0), Vector3::new(1.0, 0.0, 0.0)] {
+            let view_proj: Matrix4<f32> = fit_to_bounds(direction, &bounds);
+            let (mut min, mut max) = (Vector3::new(f32::MAX, f32::MAX, f32::MAX), Vector3::new(f32::MIN, f32::MIN, f32::MIN));
+            for corner in bounds.corners() {
+                let clip: Vector4<f32> = view_proj * corner.extend(1.0);
+                min = Vector3::new(min.x.min(clip.x), min.y.min(clip.y), min.z.min(clip.z));
+                max = Vector3::new(max.x.max(clip.x), max.y.max(clip.y), max.z.max(clip.z));
+            }
+            // inside the clip volume, and tight: the box touches every side
+            for (lo, hi, expected) in [(min.x, max.x, -1.0), (min.y, max.y, -1.0), (min.z, max.z, 0.0)] {
+                assert!((lo - expected).abs() < 1e-4 && (hi - 1.0).abs() < 1e-4, "{:?}: {} {}", direction, lo, hi);
+            }
+        }
+    }
+}
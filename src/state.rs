@@ -1,71 +1,276 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use anyhow::Context;
 use bytemuck::cast_slice;
-use cgmath::{Deg, InnerSpace, Quaternion, Rotation3, Vector3, Zero};
-use image::{DynamicImage, GenericImageView, load_from_memory, RgbaImage};
-use wgpu::{Adapter, AddressMode, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites, CommandEncoder, CommandEncoderDescriptor, CompareFunction, CompositeAlphaMode, DepthBiasState, DepthStencilState, Device, DeviceDescriptor, Extent3d, Face, Features, FilterMode, FragmentState, FrontFace, ImageCopyTexture, ImageDataLayout, IndexFormat, InstanceDescriptor, LoadOp, MultisampleState, Operations, Origin3d, PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PresentMode, PrimitiveState, PrimitiveTopology, QuerySet, QuerySetDescriptor, QueryType, Queue, RenderPass, RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, Surface, SurfaceCapabilities, SurfaceConfiguration, SurfaceError, SurfaceTexture, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState};
-use wgpu::LoadOp::Clear;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+use image::RgbaImage;
+use wgpu::{Adapter, BufferDescriptor, Extent3d, ImageCopyBuffer, ImageDataLayout, Maintain, MapMode, TextureFormatFeatures, COPY_BYTES_PER_ROW_ALIGNMENT, BufferAddress, DownlevelCapabilities, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages, CommandEncoder, CommandEncoderDescriptor, CompositeAlphaMode, Device, DeviceDescriptor, Features, FilterMode, IndexFormat, InstanceDescriptor, PipelineLayout, PipelineLayoutDescriptor, PresentMode, Queue, RenderPass, RequestAdapterOptions, SamplerBindingType, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, Surface, SurfaceCapabilities, SurfaceConfiguration, SurfaceError, SurfaceTexture, TextureFormat, TextureFormatFeatureFlags, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension};
 use wgpu::PowerPreference::HighPerformance;
-use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use winit::dpi::PhysicalSize;
-use winit::event::{DeviceEvent, Event, WindowEvent};
+use winit::event::Event;
 use winit::event_loop::ControlFlow;
 use winit::window::Window;
-use crate::{Vertex, texture};
+use crate::texture;
 
+use crate::assets::Assets;
+use crate::atlas::TextureAtlas;
+use crate::bindings::{Action, ActionEvent, Bindings, Dispatcher};
 use crate::camera::{Camera, CameraController, CameraUniform};
+#[cfg(feature = "hot-reload")]
+use crate::hot_reload::FileWatcher;
+use crate::palette::Palette;
+use crate::shadow::{DirectionalLight, ShadowMap};
+use crate::pathtrace::{PathTracer, TraceCamera, TraceScene, TraceSettings};
+use crate::post::{PostProcess, PostSettings, ACCUM_FORMAT, HDR_FORMAT, NORMAL_DEPTH_FORMAT, REVEALAGE_FORMAT};
+use crate::pipeline::{closest_sample_count, Pipelines, RenderMode};
 use crate::utils::create_wgpu_buffer;
-use crate::voxel::{VERTEX_INDICES, VV, Instance};
+use crate::chunk::{ChunkMap, Voxel};
+use crate::layers::{Edit, Layer};
+use crate::chunk_buffers::{ChunkBuffers, GpuLevel, OnionSkin};
+use crate::culling::{classify, Aabb, Frustum, LodSettings, Visibility};
+use crate::gpu_culling::GpuCuller;
+use crate::gizmo::{transform_voxels, Gizmo, GizmoDrag, GizmoMode, GIZMO_SIZE};
+use crate::gui::{Gui, Rect, Tool};
+use crate::hud::{Hud, HudBatch};
+use crate::input::InputState;
+use crate::layers::Layers;
+use crate::model::{Frame, Model, ModelObject, DEFAULT_FRAME_DURATION, PROJECT_EXTENSION};
+use crate::objects::{bake, world_bounds, Object, Objects, Transform};
+use crate::stats::{FrameStats, GpuTimer, StatsLogger};
+use crate::timeline::Timeline;
+use crate::timestep::FixedTimestep;
+use crate::voxel::{VERTEX_INDICES, VV};
 
 pub struct State {
     surface: Option<Surface>,
     pub(crate) device: Device,
-    queue: Queue,
+    pub(crate) queue: Queue,
+    pub downlevel: DownlevelCapabilities,
     config: SurfaceConfiguration,
+    present_modes: Vec<PresentMode>, // supported by the surface
     pub size: PhysicalSize<u32>,
     pub(crate) window: Option<Window>,
-    render_pipeline: RenderPipeline,
+    pipelines: Pipelines,
+    render_pipeline_layout: PipelineLayout,
+    shader: ShaderModule,
+    pub sample_counts: Vec<u32>, // msaa sample counts supported by the scene targets
+    pub render_mode: RenderMode,
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: FileWatcher,
     vertex_buffer: Buffer,
     index_buffer: Buffer,
     num_indices: u32,
 
     diffuse_bind_group: BindGroup,
-    diffuse_texture: texture::Texture,
-    depth_texture: texture::Texture,
+    pub diffuse_texture: texture::Texture,
+    pub atlas: TextureAtlas,
+    pub atlas_buffer: Buffer,
+    pub post: PostProcess,
 
-    camera: Camera,
+    pub camera: Camera,
     camera_buffer: Buffer,
     camera_bind_group: BindGroup,
+    pub light: DirectionalLight,
+    shadow_map: ShadowMap,
+    #[cfg(feature = "hot-reload")]
+    texture_bind_group_layout: BindGroupLayout,
 
-    instances: Vec<Instance>,
-    instance_buffer: Buffer
+    pub assets: Assets,
+    pub palette: Palette,
+    palette_buffer: Buffer,
+    // the selected object, the one being edited
+    pub chunks: ChunkMap, // what is drawn, the visible layers of the current frame stacked
+    pub layers: Layers,
+    pub timeline: Timeline,
+    history: Vec<(usize, Vec<Edit>)>, // steps with the frame they were made in, undone from the back
+    undone: Vec<(usize, Vec<Edit>)>, // redone from the back, cleared by new edits
+    chunk_buffers: ChunkBuffers,
+    pub objects: Objects,
+    stored: Vec<Option<StoredObject>>, // the objects that aren't selected, None for the selected one
+    pub project: PathBuf, // where saving writes the model to
+    onion_skins: [ChunkBuffers; 2], // the frames before and after the current one
+    onion_frames: [Option<usize>; 2], // uploaded to the onion skins
+    onion_stale: bool, // the onion skins have to be uploaded again, even for the same frames
+    pub lod: LodSettings,
+    gpu_culler: Option<GpuCuller>,
+    pub gpu_culling: bool, // cull on the gpu with indirect draws when supported, on the cpu otherwise
+
+    pub stats: FrameStats,
+    pub show_stats: bool, // draw the stats in the top left corner
+    stats_logger: StatsLogger,
+    gpu_timer: Option<GpuTimer>,
+    frame_start: Instant,
+    hud: Hud,
+    overlay: Hud, // the gizmo, between the scene and the gui
+    gizmo_edit: Option<GizmoEdit>, // the gizmo handle being dragged
+    pub gui: Gui,
+    pub input: InputState,
+    pub dispatcher: Dispatcher,
+
+    pub timestep: FixedTimestep,
+    was_animating: bool, // animations only advance by time measured while they were running
+    pub turntable: bool, // circle the camera around its target
+    pub turntable_speed: f32, // degrees per second
+    needs_redraw: bool, // something changed since the last frame
+}
+
+// An object while another one is being edited, with what the state holds of the selected object
+struct StoredObject {
+    layers: Layers,
+    timeline: Timeline,
+    chunks: ChunkMap,
+    buffers: ChunkBuffers,
+    history: Vec<(usize, Vec<Edit>)>,
+    undone: Vec<(usize, Vec<Edit>)>,
+}
+
+impl StoredObject {
+    fn new(device: &Device, frames: Vec<Frame>) -> Self {
+        let (timeline, layers): (Timeline, Layers) = Timeline::from_frames(frames);
+        let chunks: ChunkMap = timeline.scene(0, &layers);
+        Self {layers, timeline, chunks, buffers: ChunkBuffers::new(device), history: Vec::new(), undone: Vec::new()}
+    }
+}
+
+// What a gizmo drag started from, every update applies the whole drag to it again
+enum GizmoStart {
+    Object(Transform),
+    Voxels {
+        voxels: Vec<(Vector3<i32>, u8)>, // of the active layer when the handle was grabbed
+        center: Vector3<i32>,
+        reverts: Vec<Edit>, // undo what the drag did so far, from the back
+        applied: f32, // the snapped amount the voxels are at
+    },
+}
+
+struct GizmoEdit {
+    drag: GizmoDrag,
+    start: GizmoStart,
+}
+
+// How a chunk level ends up in the render pass, with the bind group of the chunk origins it is drawn with
+enum ChunkDraw<'a> {
+    Direct(&'a BindGroup, &'a GpuLevel, u32), // culled on the cpu, drawn with the chunk's origin offset
+    Indirect(&'a BindGroup, &'a GpuLevel, u32, &'a Buffer, BufferAddress), // arguments written by the cull pass, and their offset
+}
+
+// Everything that can go wrong while setting up the renderer
+#[derive(Debug)]
+pub enum StateError {
+    NoAdapter, // no adapter at all, not even a software fallback
+    RequestDevice(wgpu::RequestDeviceError),
+    SurfaceUnsupported(String), // the window surface can't be created or presented to by the adapter
+    AssetDecode(anyhow::Error),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::NoAdapter => write!(f, "no suitable graphics adapter found"),
+            StateError::RequestDevice(e) => write!(f, "failed to request graphics device: {}", e),
+            StateError::SurfaceUnsupported(e) => write!(f, "window surface is not supported: {}", e),
+            StateError::AssetDecode(e) => write!(f, "failed to load assets: {:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StateError::RequestDevice(e) => Some(e),
+            StateError::AssetDecode(e) => Some(e.as_ref()),
+            _ => None
+        }
+    }
 }
 
 impl State {
-    pub async fn new(window: Option<Window>) -> Self {
+    // Features we'd like to have, but can do without
+    const OPTIONAL_FEATURES: Features = Features::POLYGON_MODE_LINE.union(Features::TIMESTAMP_QUERY).union(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    const DEFAULT_SAMPLE_COUNT: u32 = 4;
+
+    // Prefer a hardware adapter, but settle for a software one rather than not running at all
+    async fn request_adapter(instance: &wgpu::Instance, surface: Option<&Surface>) -> Result<Adapter, StateError> {
+        for force_fallback_adapter in [false, true] {
+            let adapter: Option<Adapter> = instance.request_adapter(&RequestAdapterOptions {
+                power_preference: HighPerformance,
+                compatible_surface: surface,
+                force_fallback_adapter
+            }).await;
+
+            match adapter {
+                Some(adapter) => return Ok(adapter),
+                None => log::warn!("no adapter found (force_fallback_adapter: {})", force_fallback_adapter)
+            }
+        }
+
+        Err(StateError::NoAdapter)
+    }
+
+    // Ask for the optional features the adapter supports, then retry with none and downlevel limits
+    async fn request_device(adapter: &Adapter) -> Result<(Device, Queue), StateError> {
+        let descriptor: DeviceDescriptor = DeviceDescriptor { features: adapter.features() & Self::OPTIONAL_FEATURES, limits: wgpu::Limits::default(), label: None };
+        match adapter.request_device(&descriptor, None).await {
+            Ok(device) => Ok(device),
+            Err(e) => {
+                log::warn!("device request failed ({}), retrying without optional features", e);
+                let descriptor: DeviceDescriptor = DeviceDescriptor { features: Features::empty(), limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()), label: None };
+                adapter.request_device(&descriptor, None).await.map_err(StateError::RequestDevice)
+            }
+        }
+    }
+
+    // Without TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES only the sample counts guaranteed by WebGPU can be used
+    fn supported_sample_counts(adapter: &Adapter, device: &Device) -> Vec<u32> {
+        let features = |format: TextureFormat| match device.features().contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            true => adapter.get_texture_format_features(format),
+            false => format.guaranteed_format_features(device.features())
+        };
+        let colors: [TextureFormatFeatures; 4] = [features(HDR_FORMAT), features(NORMAL_DEPTH_FORMAT), features(ACCUM_FORMAT), features(REVEALAGE_FORMAT)];
+        let depth: TextureFormatFeatures = features(texture::Texture::DEPTH_FORMAT);
+        [1, 2, 4, 8].into_iter().filter(|count| *count == 1 || (depth.flags.sample_count_supported(*count) && colors.iter().all(|color| color.flags.sample_count_supported(*count) && color.flags.contains(TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)))).collect()
+    }
+
+    pub async fn new(window: Option<Window>, assets: Assets) -> Result<Self, StateError> {
         let size: PhysicalSize<u32> = match &window {Some(w) => w.inner_size(), _ => (0, 0).into()}; // retrieve size information from window object
 
         // Create a new instance and surface (if window is present)
-        let instance: wgpu::Instance = wgpu::Instance::new(InstanceDescriptor {backends: Backends::all(), dx12_shader_compiler: Default::default() });
-        let surface: Option<Surface> = match &window {Some(w) => Some(unsafe {instance.create_surface(&w)}.unwrap()), _ => None};
+        let instance: wgpu::Instance = wgpu::Instance::new(InstanceDescriptor {backends: wgpu::util::backend_bits_from_env().unwrap_or(Backends::all()), dx12_shader_compiler: Default::default() });
+        let surface: Option<Surface> = match &window {
+            Some(w) => Some(unsafe {instance.create_surface(w)}.map_err(|e| StateError::SurfaceUnsupported(e.to_string()))?),
+            None => None
+        };
 
         // Connect to the almighty gpu
-        let adapter: Adapter = instance.request_adapter(&RequestAdapterOptions {
-                power_preference: HighPerformance,
-                compatible_surface: Option::from(&surface),
-                force_fallback_adapter: false
-            }).await.unwrap();
-        let (device, queue): (Device, Queue) = adapter.request_device(&DeviceDescriptor { features: Features::POLYGON_MODE_LINE, limits: wgpu::Limits::default(), label: None }, None).await.unwrap();
+        let adapter: Adapter = Self::request_adapter(&instance, surface.as_ref()).await?;
+        log::info!("using adapter {:?}", adapter.get_info());
+        let (device, queue): (Device, Queue) = Self::request_device(&adapter).await?;
+        let downlevel: DownlevelCapabilities = adapter.get_downlevel_capabilities();
 
         // configure surface if there is a window
         let caps: SurfaceCapabilities = match &surface {Some(s) => s.get_capabilities(&adapter), _ => SurfaceCapabilities::default()};
+        if surface.is_some() && caps.formats.is_empty() {
+            return Err(StateError::SurfaceUnsupported(format!("adapter {} can't present to this window", adapter.get_info().name)));
+        }
         let format: TextureFormat = caps.formats.iter().copied().find(|f| f.is_srgb()).unwrap_or(TextureFormat::Rgba8UnormSrgb);
         let config: SurfaceConfiguration = SurfaceConfiguration {usage: TextureUsages::RENDER_ATTACHMENT, format, width: size.width, height: size.height, present_mode: PresentMode::Fifo, alpha_mode: CompositeAlphaMode::Auto, view_formats: vec![]};
-        match &surface {Some(s) => s.configure(&device, &config), _ => {}};
+        if let Some(s) = &surface {s.configure(&device, &config)};
+
+        // load texture and lay it out as an atlas, palette entries pick their tile from it
+        let (atlas, atlas_image): (TextureAtlas, RgbaImage) = assets.load_atlas("img.png", 4).map_err(StateError::AssetDecode)?;
+        let diffuse_texture: texture::Texture = texture::Texture::from_rgba(&device, &queue, &atlas_image, FilterMode::Nearest, Some("rick")).map_err(StateError::AssetDecode)?;
+        let palette: Palette = assets.load_palette("default.txt").map_err(StateError::AssetDecode)?;
+        palette.check_tiles(atlas.tile_count()).map_err(StateError::AssetDecode)?;
 
-        // load texture
-        let diffuse_bytes: &[u8] = include_bytes!("../textures/img.png");
-        let diffuse_texture: texture::Texture = texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "rick").unwrap();
+        let atlas_buffer: Buffer = create_wgpu_buffer(&device, Some("Atlas Buffer"), cast_slice(&[atlas.uniform()]), BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let palette_buffer: Buffer = create_wgpu_buffer(&device, Some("Palette Buffer"), cast_slice(&palette.to_raw()), BufferUsages::UNIFORM | BufferUsages::COPY_DST);
 
-        let depth_texture: texture::Texture = texture::Texture::create_depth_texture(&device, &config, "depth texture");
+        let sample_counts: Vec<u32> = Self::supported_sample_counts(&adapter, &device);
+        let sample_count: u32 = closest_sample_count(&sample_counts, Self::DEFAULT_SAMPLE_COUNT);
+        log::info!("msaa sample counts {:?}, using {}", sample_counts, sample_count);
+        let post: PostProcess = PostProcess::new(&device, &assets, config.format, config.width, config.height, sample_count).map_err(StateError::AssetDecode)?;
 
         let texture_bind_group_layout: BindGroupLayout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             entries: &[
@@ -84,7 +289,27 @@ impl State {
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
                     count: None
-               }
+               },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None
+                }
             ],
             label: Some("Texture Bind Group Layout")
         });
@@ -98,15 +323,29 @@ impl State {
                     BindGroupEntry {
                         binding: 1,
                         resource: BindingResource::Sampler(&diffuse_texture.sampler)
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: atlas_buffer.as_entire_binding()
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: palette_buffer.as_entire_binding()
                     }
                 ],
                 label: Some("Diffuse Bind Group")
         });
 
-        // camera presets
-        let mut camera: Camera = Camera {
-            eye: (50.0, 10.0, 2.0).into(),
-            target: (0.0, 0.0, 0.0).into(),
+        // define shader module
+        let shader: ShaderModule = device.create_shader_module(ShaderModuleDescriptor {label: Some("Shader Module"), source: ShaderSource::Wgsl(assets.load_shader("shader.wgsl").map_err(StateError::AssetDecode)?.into())});
+        let mut chunk_buffers: ChunkBuffers = ChunkBuffers::new(&device);
+        let onion_skins: [ChunkBuffers; 2] = [ChunkBuffers::onion_skin(&device, OnionSkin::Previous), ChunkBuffers::onion_skin(&device, OnionSkin::Next)];
+        let shadow_map: ShadowMap = ShadowMap::new(&device, &texture_bind_group_layout, &chunk_buffers.bind_group_layout, &shader);
+
+        // camera presets, looking at the test model from above one of its corners
+        let camera: Camera = Camera {
+            eye: (120.0, 100.0, -20.0).into(),
+            target: (50.0, 50.0, 50.0).into(),
             up: Vector3::unit_y(),
             aspect: config.width as f32 / config.height as f32,
             fov: 110.0, near: 0.1, far: 10000.0,
@@ -119,13 +358,39 @@ impl State {
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: ShaderStages::VERTEX,
+                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT, // the fragment shader reads the eye position from the view
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
                     count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Depth
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                    count: None
                 }
             ],
             label: Some("Camera Bind Group Layout Descriptor")
@@ -136,54 +401,146 @@ impl State {
                 BindGroupEntry {
                     binding: 0,
                     resource: camera_buffer.as_entire_binding()
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: shadow_map.light_buffer.as_entire_binding()
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&shadow_map.texture.view)
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Sampler(&shadow_map.texture.sampler)
                 }
             ],
             label: Some("Camera Bind Group")
         });
 
-        // define shader module
-        let shader: ShaderModule = device.create_shader_module(ShaderModuleDescriptor {label: Some("Shader Module"), source: ShaderSource::Wgsl(include_str!("shader.wgsl").into())});
-        let render_pipeline_layout: PipelineLayout = device.create_pipeline_layout(&PipelineLayoutDescriptor {label: Some("Render Pipeline Layout"), bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout], push_constant_ranges: &[]});
+        let render_pipeline_layout: PipelineLayout = device.create_pipeline_layout(&PipelineLayoutDescriptor {label: Some("Render Pipeline Layout"), bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout, &chunk_buffers.bind_group_layout], push_constant_ranges: &[]});
         // Grab a plate of spaghetti
 
-        let render_pipeline: RenderPipeline = device.create_render_pipeline(&RenderPipelineDescriptor {label: Some("Render Pipeline"), layout: Some(&render_pipeline_layout), vertex: VertexState { module: &shader, entry_point: "vs_main", buffers: &[Vertex::desc(), Instance::desc()]}, fragment: Some(FragmentState {module: &shader, entry_point: "fs_main", targets: &[Some(ColorTargetState {format: config.format, blend: Some(BlendState::ALPHA_BLENDING), write_mask: ColorWrites::ALL})]}), primitive: PrimitiveState {topology: PrimitiveTopology::TriangleList, strip_index_format: None, front_face: FrontFace::Ccw, cull_mode: Some(Face::Back), polygon_mode: PolygonMode::Line, unclipped_depth: false, conservative: false}, multisample: MultisampleState {count: 1, mask: !0, alpha_to_coverage_enabled: false}, multiview: None, depth_stencil: Some(DepthStencilState {format: texture::Texture::DEPTH_FORMAT, depth_write_enabled: true, depth_compare: CompareFunction::Less, stencil: StencilState::default(), bias: DepthBiasState::default()})});
-        let vertex_buffer: Buffer = create_wgpu_buffer(&device, Some("Vertex Buffer"), cast_slice(&VV), BufferUsages::VERTEX);
-        let index_buffer: Buffer = create_wgpu_buffer(&device, Some("Index buffer"), cast_slice(&VERTEX_INDICES), BufferUsages::INDEX);
+        let pipelines: Pipelines = Pipelines::new(&device, &render_pipeline_layout, &shader, sample_count);
+        let vertex_buffer: Buffer = create_wgpu_buffer(&device, Some("Vertex Buffer"), cast_slice(VV), BufferUsages::VERTEX);
+        let index_buffer: Buffer = create_wgpu_buffer(&device, Some("Index buffer"), cast_slice(VERTEX_INDICES), BufferUsages::INDEX);
+
+        // crappy test code, a colour gradient on top of a textured floor
+        let mut chunks: ChunkMap = ChunkMap::new();
+        for z in -100..0 {
+            for y in 0..100 {
+                for x in 0..100 {
+                    let index: u8 = if y == 0 {255} else {(x * 6 / 100 + y * 6 / 100 * 6 + (z + 100) * 6 / 100 * 36) as u8};
+                    chunks.set(Vector3::new(x, y, 100 + z), Some(index));
+                }
+            }
+        }
+
+        chunk_buffers.sync(&device, &queue, &mut chunks, &palette.see_through());
 
-        // crappy test code
-        let instances = (-100..0).flat_map(|z| {
-            (0..100).flat_map(|y| {
-                (0..100).map(move |x| {
-                    let position: Vector3<f32> = Vector3::new(x as f32, y as f32, 100.0 + z as f32);
-                    Instance::new(position, (x as f32 * 0.005, y as f32 * 0.005, (z + 200) as f32 * 0.005, 1.0).into())
-                })
-            }).collect::<Vec<_>>()
-        }).collect::<Vec<_>>();
+        let gpu_culler: Option<GpuCuller> = match GpuCuller::supported(&downlevel) {
+            true => Some(GpuCuller::new(&device, &assets, VERTEX_INDICES.len() as u32).map_err(StateError::AssetDecode)?),
+            false => {log::info!("indirect draws are not supported, culling on the cpu"); None}
+        };
 
-        let instance_data = instances.iter().map(|d| {d.raw}).collect::<Vec<_>>();
-        let instance_buffer: Buffer = create_wgpu_buffer(&device, Some("Instance buffer"), cast_slice(&instance_data), BufferUsages::VERTEX);
+        let gpu_timer: Option<GpuTimer> = GpuTimer::new(&device, &queue);
+        let hud: Hud = Hud::new(&device, &assets, config.format).map_err(StateError::AssetDecode)?;
+        let overlay: Hud = Hud::new(&device, &assets, config.format).map_err(StateError::AssetDecode)?;
 
-        Self {
+        Ok(Self {
             window,
             surface,
             device,
             queue,
+            downlevel,
             config,
+            present_modes: caps.present_modes.clone(),
             size,
-            render_pipeline,
+            pipelines,
+            render_pipeline_layout,
+            shader,
+            sample_counts,
+            render_mode: RenderMode::default(),
+            #[cfg(feature = "hot-reload")]
+            shader_watcher: FileWatcher::new(assets.path("shaders/shader.wgsl")),
             vertex_buffer,
             index_buffer,
             num_indices: VERTEX_INDICES.len() as u32,
             diffuse_bind_group,
             diffuse_texture,
-            depth_texture,
+            atlas,
+            atlas_buffer,
+            post,
 
             camera,
             camera_buffer,
             camera_bind_group,
+            light: DirectionalLight::default(),
+            shadow_map,
+            #[cfg(feature = "hot-reload")]
+            texture_bind_group_layout,
+
+            assets,
+            palette,
+            palette_buffer,
+            layers: Layers::new(&chunks),
+            timeline: Timeline::new(DEFAULT_FRAME_DURATION),
+            history: Vec::new(),
+            undone: Vec::new(),
+            chunks,
+            chunk_buffers,
+            objects: Objects::new(vec![Object::new("Model")]),
+            stored: vec![None],
+            project: PathBuf::from(format!("untitled.{}", PROJECT_EXTENSION)),
+            onion_skins,
+            onion_frames: [None, None],
+            onion_stale: false,
+            lod: LodSettings::default(),
+            gpu_culler,
+            gpu_culling: true,
+
+            stats: FrameStats::default(),
+            show_stats: false,
+            stats_logger: StatsLogger::new(Duration::from_secs(1)),
+            gpu_timer,
+            frame_start: Instant::now(),
+            hud,
+            overlay,
+            gizmo_edit: None,
+            gui: Gui::default(),
+            input: InputState::default(),
+            dispatcher: Dispatcher::new(Bindings::load()),
+
+            timestep: FixedTimestep::default(),
+            was_animating: false,
+            turntable: false,
+            turntable_speed: 30.0,
+            needs_redraw: true
+        })
+    }
+
+    // Recompile the shader and rebuild the pipelines, keeping the old pipelines if the new shader is invalid
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_shader(&mut self) {
+        use pollster::FutureExt;
+
+        let source: String = match self.assets.load_shader("shader.wgsl") {
+            Ok(source) => source,
+            Err(e) => {log::error!("failed to reload shader: {:#}", e); return}
+        };
 
-            instances,
-            instance_buffer
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader: ShaderModule = self.device.create_shader_module(ShaderModuleDescriptor {label: Some("Shader Module"), source: ShaderSource::Wgsl(source.into())});
+        let pipelines: Pipelines = Pipelines::new(&self.device, &self.render_pipeline_layout, &shader, self.pipelines.sample_count);
+
+        match self.device.pop_error_scope().block_on() {
+            Some(e) => log::error!("shader reload failed, keeping previous pipelines:\n{}", e),
+            None => {
+                log::info!("reloaded shader");
+                self.pipelines = pipelines;
+                self.shadow_map.set_shader(&self.device, &self.texture_bind_group_layout, &self.chunk_buffers.bind_group_layout, &shader);
+                self.shader = shader;
+            }
         }
     }
 
@@ -193,73 +550,936 @@ impl State {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-            match &self.surface {Some(surface) => surface.configure(&self.device, &self.config), _ => {}};
+            self.camera.aspect = new_size.width as f32 / new_size.height as f32;
+            self.post.resize(&self.device, new_size.width, new_size.height, self.sample_count());
+            if let Some(surface) = &self.surface {surface.configure(&self.device, &self.config)};
+            self.needs_redraw = true;
+        }
+    }
+
+    // Wait for the display's refresh, or present right away without it. Immediate tears, mailbox doesn't but isn't always there
+    pub fn set_vsync(&mut self, vsync: bool) {
+        let present_mode: PresentMode = match vsync {
+            true => PresentMode::Fifo,
+            false => [PresentMode::Immediate, PresentMode::Mailbox].into_iter().find(|mode| self.present_modes.contains(mode)).unwrap_or(PresentMode::Fifo)
+        };
+        if present_mode != self.config.present_mode {
+            self.config.present_mode = present_mode;
+            if let Some(surface) = &self.surface {surface.configure(&self.device, &self.config)};
+            log::info!("present mode: {:?}", present_mode);
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.pipelines.sample_count
+    }
+
+    // Switch msaa on the fly, falls back to the closest supported sample count and returns it
+    pub fn set_sample_count(&mut self, requested: u32) -> u32 {
+        let sample_count: u32 = closest_sample_count(&self.sample_counts, requested);
+        if sample_count != self.pipelines.sample_count {
+            self.pipelines = Pipelines::new(&self.device, &self.render_pipeline_layout, &self.shader, sample_count);
+            self.post.resize(&self.device, self.post.targets.width, self.post.targets.height, sample_count);
+            log::info!("msaa: {}x", sample_count);
         }
+        sample_count
     }
 
-    // handling input
-    pub fn input(&mut self, control_flow: &mut ControlFlow, event: &Event<()>) {
+    // Let the gui look at an event first, returns true when it was used and shouldn't reach the scene
+    fn gui_event(&mut self, event: &Event<()>) -> bool {
+        match event {
+            Event::WindowEvent {event, ..} => self.gui.handle_event(event),
+            _ => false
+        }
     }
 
-    // Update (called every frame)
-    pub fn update(&mut self) {
+    // handling input, the gui sees it first so clicks on its panels don't edit the model. What is held and
+    // moved is collected for the next update, the actions bound to presses run right away
+    pub fn input(&mut self, _control_flow: &mut ControlFlow, event: &Event<()>) {
+        // raw mouse motion keeps coming when the cursor is elsewhere, it only shows while dragging the camera
+        match event {
+            Event::WindowEvent {..} => self.needs_redraw = true,
+            Event::DeviceEvent {..} if self.dispatcher.is_active(Action::Orbit) || self.dispatcher.is_active(Action::Pan) => self.needs_redraw = true,
+            _ => {}
+        }
+        if self.gui_event(event) {
+            return;
+        }
+        self.input.handle_event(event);
+        for action_event in self.dispatcher.dispatch(event) {
+            match action_event {
+                ActionEvent::Pressed(action) => self.run_action(action),
+                ActionEvent::Released(Action::UseTool) => self.release_gizmo(),
+                ActionEvent::Released(_) => {}
+                ActionEvent::Rebound(action, binding) => {
+                    log::info!("{} is now bound to {}", action.name(), binding);
+                    self.save_bindings();
+                }
+            }
+        }
+    }
+
+    pub fn run_action(&mut self, action: Action) {
+        match action {
+            Action::Orbit | Action::Pan => {} // held while dragging, nothing happens on the press itself
+            Action::UseTool => if !self.grab_gizmo() {
+                self.apply_tool(self.gui.tool);
+            }
+            Action::Place => self.apply_tool(Tool::Place),
+            Action::Erase => self.apply_tool(Tool::Erase),
+            Action::Paint => self.apply_tool(Tool::Paint),
+            Action::Pick => self.apply_tool(Tool::Pick),
+            Action::Undo => {self.undo();}
+            Action::Redo => {self.redo();}
+            Action::ToggleWireframe => self.toggle_wireframe(),
+            Action::ToggleStats => self.show_stats = !self.show_stats,
+            Action::CycleMsaa => {
+                // cycle through the supported sample counts
+                let next: u32 = self.sample_counts.iter().copied().find(|count| *count > self.sample_count()).unwrap_or(1);
+                self.set_sample_count(next);
+            }
+            Action::ToggleSsao | Action::ToggleOutline | Action::ToggleTonemap => {
+                let settings: &mut PostSettings = &mut self.post.settings;
+                match action {
+                    Action::ToggleSsao => settings.ssao = !settings.ssao,
+                    Action::ToggleOutline => settings.outline = !settings.outline,
+                    _ => settings.tonemap = !settings.tonemap
+                }
+                log::info!("post processing: {:?}", settings);
+            }
+            Action::PathTrace => {self.path_trace_view(TraceSettings {width: self.size.width.max(1), height: self.size.height.max(1), ..Default::default()});}
+            Action::ToggleGui => self.gui.visible = !self.gui.visible,
+            Action::Turntable => self.turntable = !self.turntable,
+            Action::Play => self.timeline.set_playing(!self.timeline.playing),
+            Action::NextFrame => self.switch_frame((self.timeline.current() + 1) % self.timeline.len()),
+            Action::PreviousFrame => self.switch_frame((self.timeline.current() + self.timeline.len() - 1) % self.timeline.len()),
+            Action::ToggleOnionSkin => self.timeline.onion_skin = !self.timeline.onion_skin,
+            Action::Save => match self.save() {
+                Ok(()) => log::info!("saved {}", self.project.display()),
+                Err(e) => log::error!("{:#}", e)
+            }
+            Action::TranslateGizmo | Action::RotateGizmo | Action::ScaleGizmo => {
+                let mode: GizmoMode = match action {
+                    Action::TranslateGizmo => GizmoMode::Translate,
+                    Action::RotateGizmo => GizmoMode::Rotate,
+                    _ => GizmoMode::Scale
+                };
+                self.release_gizmo();
+                self.gui.gizmo = if self.gui.gizmo == Some(mode) {None} else {Some(mode)};
+            }
+            Action::ToggleGizmoVoxels => {
+                self.release_gizmo();
+                self.gui.gizmo_voxels = !self.gui.gizmo_voxels;
+            }
+        }
+    }
+
+    // Write the bindings to the user's bindings file, so a rebinding lasts
+    pub fn save_bindings(&self) {
+        if let Some(path) = Bindings::path() {
+            match self.dispatcher.bindings.save(&path) {
+                Ok(()) => log::info!("saved bindings to {}", path.display()),
+                Err(e) => log::error!("{:#}", e)
+            }
+        }
+    }
+
+    // Path trace the current view in the background to `render-<timestamp>.png` in the working directory
+    pub fn path_trace_view(&self, settings: TraceSettings) -> JoinHandle<()> {
+        let seconds: u64 = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let path: PathBuf = PathBuf::from(format!("render-{}.png", seconds));
+        log::info!("path tracing the current view to {}", path.display());
+        self.start_path_trace(settings, path)
+    }
+
+    // The ray through the mouse cursor, None over the gui
+    fn cursor_ray(&self) -> Option<(Point3<f32>, Vector3<f32>)> {
+        let [x, y] = self.input.cursor.filter(|_| !self.gui.is_over_panel())?;
+        Some(self.camera.ray(x, y, self.size.width as f32, self.size.height as f32))
+    }
+
+    // The first voxel of an object a ray hits, in the object's coordinates, with the normal of the face it is seen
+    // through and the distance to it
+    fn raycast_object(&self, index: usize, origin: Point3<f32>, direction: Vector3<f32>) -> Option<(Vector3<i32>, Vector3<i32>, f32)> {
+        let world: Matrix4<f32> = self.objects.world(index);
+        let inverse: Matrix4<f32> = world.invert()?;
+        let local_origin: Point3<f32> = Point3::from_homogeneous(inverse * origin.to_homogeneous());
+        let local_direction: Vector3<f32> = (inverse * direction.extend(0.0)).truncate();
+        let (position, normal): (Vector3<i32>, Vector3<i32>) = self.object_chunks(index).raycast(local_origin, local_direction, self.camera.far)?;
+        let hit: Vector3<f32> = (world * position.cast::<f32>().unwrap().extend(1.0)).truncate();
+        Some((position, normal, (hit - origin.to_vec()).magnitude()))
+    }
+
+    // The voxel of the selected object under the mouse cursor and the normal of the face it is seen through, None
+    // over the gui
+    pub fn pick(&self) -> Option<(Vector3<i32>, Vector3<i32>)> {
+        let (origin, direction): (Point3<f32>, Vector3<f32>) = self.cursor_ray()?;
+        if !self.objects.is_visible(self.objects.selected) {
+            return None;
+        }
+        self.raycast_object(self.objects.selected, origin, direction).map(|(position, normal, _)| (position, normal))
+    }
+
+    // The nearest visible object under the mouse cursor
+    pub fn pick_object(&self) -> Option<usize> {
+        let (origin, direction): (Point3<f32>, Vector3<f32>) = self.cursor_ray()?;
+        (0..self.objects.len()).filter(|index| self.objects.is_visible(*index))
+            .filter_map(|index| self.raycast_object(index, origin, direction).map(|(_, _, distance)| (index, distance)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(index, _)| index)
+    }
+
+    // Use a tool on the voxel under the cursor
+    pub fn apply_tool(&mut self, tool: Tool) {
+        if tool == Tool::Select {
+            if let Some(index) = self.pick_object() {
+                self.select_object(index);
+            }
+            return;
+        }
+        let Some((position, normal)) = self.pick() else {return};
+        match tool {
+            Tool::Place if normal != Vector3::new(0, 0, 0) => {self.set_voxel(position + normal, Some(self.gui.color));}
+            Tool::Place => {}
+            Tool::Erase => {self.set_voxel(position, None);}
+            Tool::Paint => {self.set_voxel(position, Some(self.gui.color));}
+            Tool::Pick => self.gui.color = self.chunks.get(position).unwrap_or(self.gui.color),
+            Tool::Select => {}
+        }
+    }
+
+    // Cycle solid -> wireframe -> wireframe over solid
+    pub fn toggle_wireframe(&mut self) {
+        self.render_mode = self.render_mode.next();
+        log::info!("render mode: {:?}", self.render_mode);
+    }
+
+    // Colours live on the gpu, so swapping the palette only touches the chunks when entries became see-through or opaque
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.queue.write_buffer(&self.palette_buffer, 0, cast_slice(&palette.to_raw()));
+        if palette.see_through() != self.palette.see_through() {
+            self.chunks.mark_all_dirty();
+            for stored in self.stored.iter_mut().flatten() {
+                stored.chunks.mark_all_dirty();
+            }
+            self.onion_stale = true;
+        }
+        self.palette = palette;
+    }
+
+    // Change a single voxel of the active layer, the chunk containing it is re-uploaded on the next update
+    pub fn set_voxel(&mut self, position: Vector3<i32>, voxel: Voxel) -> Voxel {
+        let previous: Voxel = self.layers.set(&mut self.chunks, position, voxel);
+        if previous != voxel {
+            self.history.push((self.timeline.current(), vec![Edit {layer: self.layers.active, position, voxel: previous}]));
+            self.undone.clear();
+        }
+        previous
+    }
+
+    // Revert the last step, going to the frame it was made in. Returns false when there is nothing left to undo
+    pub fn undo(&mut self) -> bool {
+        self.release_gizmo();
+        let Some((frame, edits)) = self.history.pop() else {return false};
+        self.switch_frame(frame);
+        let reverts: Vec<Edit> = self.apply_edits(edits);
+        self.undone.push((frame, reverts));
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.release_gizmo();
+        let Some((frame, edits)) = self.undone.pop() else {return false};
+        self.switch_frame(frame);
+        let reverts: Vec<Edit> = self.apply_edits(edits);
+        self.history.push((frame, reverts));
+        true
+    }
+
+    // Make the edits of a step from the back, returns the step that reverts them
+    fn apply_edits(&mut self, edits: Vec<Edit>) -> Vec<Edit> {
+        edits.into_iter().rev().map(|edit| self.layers.apply(&mut self.chunks, edit)).collect()
+    }
+
+    pub fn add_layer(&mut self, name: &str) {
+        self.layers.add(name);
+        self.timeline.add_layer();
+    }
+
+    pub fn set_layer_visible(&mut self, index: usize, visible: bool) {
+        self.layers.set_visible(&mut self.chunks, index, visible);
+        self.onion_stale = true;
+    }
+
+    // The edits can't be undone anymore, they point at layers by index
+    pub fn remove_layer(&mut self, index: usize) {
+        if self.layers.len() > 1 && index < self.layers.len() {
+            self.layers.remove(&mut self.chunks, index);
+            self.timeline.remove_layer(index);
+            self.history.clear();
+            self.undone.clear();
+            self.onion_stale = true;
+        }
+    }
+
+    // Edit another frame of the animation
+    pub fn switch_frame(&mut self, frame: usize) {
+        if frame != self.timeline.current() {
+            self.release_gizmo();
+        }
+        self.timeline.switch(&mut self.layers, &mut self.chunks, frame);
+    }
+
+    // Continue the animation with a copy of the current frame
+    pub fn add_frame(&mut self) {
+        let frame: usize = self.timeline.duplicate(&mut self.layers, &mut self.chunks);
+        for (edited, _) in self.history.iter_mut().chain(self.undone.iter_mut()) {
+            if *edited >= frame {
+                *edited += 1;
+            }
+        }
+        self.onion_stale = true;
+    }
+
+    // Remove the current frame together with the edits made in it
+    pub fn remove_frame(&mut self) {
+        let Some(removed) = self.timeline.remove(&mut self.layers, &mut self.chunks) else {return};
+        for edits in [&mut self.history, &mut self.undone] {
+            edits.retain(|(frame, _)| *frame != removed);
+            for (frame, _) in edits.iter_mut() {
+                if *frame > removed {
+                    *frame -= 1;
+                }
+            }
+        }
+        self.onion_stale = true;
+    }
+
+    // The voxels of an object as they are drawn
+    fn object_chunks(&self, index: usize) -> &ChunkMap {
+        match &self.stored[index] {
+            Some(stored) => &stored.chunks,
+            None => &self.chunks
+        }
+    }
+
+    fn object_buffers(&self, index: usize) -> &ChunkBuffers {
+        match &self.stored[index] {
+            Some(stored) => &stored.buffers,
+            None => &self.chunk_buffers
+        }
+    }
+
+    // Trade what the state holds of the selected object for another object
+    fn swap_object(&mut self, object: &mut StoredObject) {
+        std::mem::swap(&mut self.layers, &mut object.layers);
+        std::mem::swap(&mut self.timeline, &mut object.timeline);
+        std::mem::swap(&mut self.chunks, &mut object.chunks);
+        std::mem::swap(&mut self.chunk_buffers, &mut object.buffers);
+        std::mem::swap(&mut self.history, &mut object.history);
+        std::mem::swap(&mut self.undone, &mut object.undone);
+    }
+
+    // Edit another object. Its layers, frames and undo history come along, the animation of the previous one stops
+    pub fn select_object(&mut self, index: usize) {
+        if index == self.objects.selected || index >= self.objects.len() {
+            return;
+        }
+        self.release_gizmo();
+        let mut object: StoredObject = self.stored[index].take().unwrap();
+        self.swap_object(&mut object);
+        object.timeline.set_playing(false);
+        self.stored[self.objects.selected] = Some(object);
+        self.objects.selected = index;
+        self.onion_stale = true;
+    }
+
+    // Add an object holding a voxel of the selected colour at the camera's target, and select it
+    pub fn add_object(&mut self) {
+        let mut object: Object = Object::new(&format!("Object {}", self.objects.len() + 1));
+        object.transform.position = self.camera.target.to_vec().map(f32::round);
+        let mut chunks: ChunkMap = ChunkMap::new();
+        chunks.set(Vector3::new(0, 0, 0), Some(self.gui.color));
+        let index: usize = self.objects.add(object);
+        self.stored.push(Some(StoredObject::new(&self.device, vec![Frame::new(vec![Layer::new("Base", chunks)])])));
+        self.select_object(index);
+    }
+
+    // Remove an object with its voxels, its children stay where they are. The last remaining object is kept
+    pub fn remove_object(&mut self, index: usize) {
+        if self.objects.len() <= 1 || index >= self.objects.len() {
+            return;
+        }
+        if index == self.objects.selected {
+            self.select_object(if index + 1 < self.objects.len() {index + 1} else {index - 1});
+        }
+        self.objects.remove(index);
+        self.stored.remove(index);
+    }
+
+    // Every object with all its frames and the palette, as a model that can be saved
+    pub fn model(&self) -> Model {
+        let objects: Vec<ModelObject> = self.objects.objects.iter().zip(&self.stored).map(|(object, stored)| ModelObject {
+            object: object.clone(),
+            frames: match stored {
+                Some(stored) => stored.timeline.frames(&stored.layers),
+                None => self.timeline.frames(&self.layers)
+            }
+        }).collect();
+        Model {objects, palette: self.palette.clone()}
+    }
+
+    // Write the model to the project file
+    pub fn save(&self) -> anyhow::Result<()> {
+        self.model().save(&self.project)
+    }
+
+    // The gizmo for the selected object, placed in its parent at its pivot, or for the voxels of the active layer,
+    // placed in the object at their middle. None without a gizmo mode, or with nothing to move
+    pub fn gizmo(&self) -> Option<Gizmo> {
+        let mode: GizmoMode = self.gui.gizmo?;
+        let selected: usize = self.objects.selected;
+        if !self.objects.is_visible(selected) {
+            return None;
+        }
+        let size: f32 = GIZMO_SIZE * self.gui.scale;
+        match self.gui.gizmo_voxels {
+            true => {
+                let (min, max): (Vector3<i32>, Vector3<i32>) = self.layers.active().chunks.bounds()?;
+                let center: Vector3<i32> = (min + max).map(|c| c.div_euclid(2));
+                Some(Gizmo {mode, space: self.objects.world(selected), center: center.cast().unwrap(), size})
+            }
+            false => {
+                let object: &Object = self.objects.selected();
+                let space: Matrix4<f32> = object.parent.map_or_else(Matrix4::identity, |parent| self.objects.world(parent));
+                Some(Gizmo {mode, space, center: object.transform.position, size})
+            }
+        }
+    }
+
+    // The gizmo handle being dragged
+    pub fn gizmo_drag(&self) -> Option<&GizmoDrag> {
+        self.gizmo_edit.as_ref().map(|edit| &edit.drag)
+    }
+
+    fn screen(&self) -> [f32; 2] {
+        [self.size.width as f32, self.size.height as f32]
+    }
+
+    // Start dragging the gizmo handle under the cursor, returns false when there is none
+    fn grab_gizmo(&mut self) -> bool {
+        let (Some(gizmo), Some(cursor)) = (self.gizmo(), self.input.cursor) else {return false};
+        let Some(axis) = gizmo.pick(&self.camera, self.screen(), cursor) else {return false};
+        let Some(measure) = gizmo.measure(&self.camera, self.screen(), axis, cursor) else {return false};
+        let drag: GizmoDrag = GizmoDrag::new(gizmo, axis, measure);
+        let start: GizmoStart = match self.gui.gizmo_voxels {
+            true => GizmoStart::Voxels {
+                voxels: self.layers.active().chunks.iter().collect(),
+                center: gizmo.center.map(|c| c.round() as i32),
+                reverts: Vec::new(),
+                applied: drag.snapped(true)
+            },
+            false => GizmoStart::Object(self.objects.selected().transform)
+        };
+        self.gizmo_edit = Some(GizmoEdit {drag, start});
+        true
+    }
+
+    // Apply the drag so far to what it started from. Voxels are put back and moved again whenever the snapped
+    // amount changes
+    fn drag_gizmo(&mut self) {
+        let (Some(cursor), screen): (Option<[f32; 2]>, [f32; 2]) = (self.input.cursor, self.screen()) else {return};
+        let Some(edit) = &mut self.gizmo_edit else {return};
+        let Some(measure) = edit.drag.gizmo.measure(&self.camera, screen, edit.drag.axis, cursor) else {return};
+        edit.drag.update(measure);
+        let (mode, axis): (GizmoMode, usize) = (edit.drag.gizmo.mode, edit.drag.axis);
+        match &mut edit.start {
+            GizmoStart::Object(start) => {
+                let amount: f32 = edit.drag.snapped(false);
+                let mut transform: Transform = *start;
+                let mut direction: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0);
+                direction[axis] = 1.0;
+                match mode {
+                    GizmoMode::Translate => transform.position += direction * amount,
+                    GizmoMode::Rotate => transform.rotate(direction, amount),
+                    GizmoMode::Scale => transform.scale *= amount
+                }
+                self.objects.objects[self.objects.selected].transform = transform;
+            }
+            GizmoStart::Voxels {voxels, center, reverts, applied} => {
+                let amount: f32 = edit.drag.snapped(true);
+                if amount == *applied {
+                    return;
+                }
+                *applied = amount;
+                for revert in reverts.drain(..).rev() {
+                    self.layers.apply(&mut self.chunks, revert);
+                }
+                let layer: usize = self.layers.active;
+                let erased = voxels.iter().map(|(position, _)| Edit {layer, position: *position, voxel: None});
+                let placed = transform_voxels(voxels, mode, axis, amount, *center).into_iter().map(|(position, index)| Edit {layer, position, voxel: Some(index)});
+                for change in erased.chain(placed) {
+                    reverts.push(self.layers.apply(&mut self.chunks, change));
+                }
+            }
+        }
+    }
+
+    // Let go of the gizmo, what the drag did to the voxels is undone in a single step
+    fn release_gizmo(&mut self) {
+        let Some(edit) = self.gizmo_edit.take() else {return};
+        if let GizmoStart::Voxels {reverts, ..} = edit.start {
+            if !reverts.is_empty() {
+                self.history.push((self.timeline.current(), reverts));
+                self.undone.clear();
+            }
+        }
+    }
+
+    // The visible voxels of every object as they are drawn, put into the world's grid
+    pub fn world_scene(&self) -> ChunkMap {
+        let mut scene: ChunkMap = ChunkMap::new();
+        for index in (0..self.objects.len()).filter(|index| self.objects.is_visible(*index)) {
+            bake(self.object_chunks(index), self.objects.world(index), &mut scene);
+        }
+        scene
+    }
+
+    // World bounds of the voxels of every visible object, None when there are none
+    pub fn world_bounds(&self) -> Option<Aabb> {
+        (0..self.objects.len()).filter(|index| self.objects.is_visible(*index))
+            .filter_map(|index| world_bounds(self.object_chunks(index), self.objects.world(index)))
+            .reduce(|a, b| a.union(&b))
+    }
+
+    // Replace the objects, their layers and frames with a loaded model, and point the camera at it. Edits can't be
+    // undone past this. Fails without changing anything when the palette uses tiles the atlas doesn't have
+    pub fn set_model(&mut self, model: Model) -> anyhow::Result<()> {
+        model.palette.check_tiles(self.atlas.tile_count())?;
+        let mut objects: Objects = model.graph();
+        let mut stored: Vec<Option<StoredObject>> = model.objects.into_iter().map(|object| Some(StoredObject::new(&self.device, object.frames))).collect();
+        if stored.is_empty() {
+            objects = Objects::new(vec![Object::new("Model")]);
+            stored.push(Some(StoredObject::new(&self.device, Vec::new())));
+        }
+        let mut first: StoredObject = stored[0].take().unwrap();
+        self.swap_object(&mut first);
+        self.objects = objects;
+        self.stored = stored;
+        self.onion_stale = true;
+        self.set_palette(model.palette);
+
+        if let Some(bounds) = self.world_bounds() {
+            self.camera.frame(Point3::from_vec(bounds.min), Point3::from_vec(bounds.max));
+        }
+        Ok(())
+    }
+
+    // Move the camera and continue tool strokes with the input collected since the last update
+    fn apply_input(&mut self) {
+        let motion: [f32; 2] = self.input.motion;
+        if self.dispatcher.is_active(Action::Orbit) {
+            self.camera.orbit(motion);
+        }
+        if self.dispatcher.is_active(Action::Pan) {
+            self.camera.pan(motion, self.size.height as f32);
+        }
+        if self.input.scroll != 0.0 {
+            self.camera.zoom(self.input.scroll);
+        }
+
+        // painting and erasing keep going over every voxel the cursor is dragged across, unless a gizmo is dragged
+        if self.input.cursor_moved && self.gizmo_edit.is_some() {
+            self.drag_gizmo();
+        } else if self.dispatcher.is_active(Action::UseTool) && self.input.cursor_moved && matches!(self.gui.tool, Tool::Paint | Tool::Erase) {
+            self.apply_tool(self.gui.tool);
+        }
+        self.input.end_frame();
+    }
+
+    // Animations keep the window redrawing, everything else only redraws when something changed
+    pub fn is_animating(&self) -> bool {
+        self.turntable || self.timeline.playing
+    }
+
+    pub fn wants_redraw(&self) -> bool {
+        self.needs_redraw || self.is_animating()
+    }
+
+    // Rebuild the pipelines when the shader file changed, called whenever the event loop wakes up
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_changed_shader(&mut self) {
+        if self.shader_watcher.changed() {
+            self.reload_shader();
+            self.needs_redraw = true;
+        }
+    }
+
+    // Advance animations by one fixed step
+    fn fixed_update(&mut self, step: Duration) {
+        if self.turntable {
+            self.camera.turn(self.turntable_speed * step.as_secs_f32());
+        }
+        if self.timeline.playing {
+            if let Some(frame) = self.timeline.advance(step.as_secs_f32()) {
+                self.switch_frame(frame);
+            }
+        }
+    }
+
+    // Update (called every frame), `dt` is the time since the previous one
+    pub fn update(&mut self, dt: Duration) {
+        self.frame_start = Instant::now();
+        self.apply_input();
+
+        // a pause, like the time the window sat idle before an animation started, isn't caught up on
+        let animating: bool = self.is_animating();
+        match animating && self.was_animating {
+            true => for _ in 0..self.timestep.advance(dt) {
+                self.fixed_update(self.timestep.step);
+            },
+            false => self.timestep.reset()
+        }
+        self.was_animating = animating;
+
+        self.sync_chunks();
+
         self.camera.update_view_proj();
         self.queue.write_buffer(&self.camera_buffer, 0, cast_slice(&[self.camera.uniform]));
     }
 
-    pub fn render(&mut self) -> Result<(), SurfaceError> {
-        match &self.surface {
-            Some(surface) => {
-                let output: SurfaceTexture = surface.get_current_texture()?;
-                let view: TextureView = output.texture.create_view(&TextureViewDescriptor::default());
-                let mut encoder: CommandEncoder = self.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Render Encoder") });
-
-                let mut render_pass: RenderPass = encoder.begin_render_pass(&RenderPassDescriptor {
-                    label: Some("Render Pass"),
-                    color_attachments: &[Some(RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: Operations {
-                            load: Clear(Color {
-                                r: 0.1,
-                                g: 0.2,
-                                b: 0.3,
-                                a: 1.0
-                            }),
-                            store: true,
-                        }
-                    })],
-                    depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                        view: &self.depth_texture.view,
-                        depth_ops: Some(Operations {
-                            load: LoadOp::Clear(1.0),
-                            store: true
-                        }),
-                        stencil_ops: None
-                    }),
-                });
-
-
-                render_pass.set_pipeline(&self.render_pipeline);
-                render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-                render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-
-                render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint16);
-
-                render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
-                drop(render_pass);
-
-                self.queue.submit(std::iter::once(encoder.finish()));
-                output.present();
-
-                Ok(())
+    // Place every object and upload the chunks that changed since the last frame
+    fn sync_chunks(&mut self) {
+        let see_through: Vec<bool> = self.palette.see_through();
+        for index in 0..self.objects.len() {
+            let world: Matrix4<f32> = self.objects.world(index);
+            match &mut self.stored[index] {
+                Some(stored) => {
+                    stored.buffers.set_transform(&self.queue, world);
+                    stored.buffers.sync(&self.device, &self.queue, &mut stored.chunks, &see_through);
+                }
+                None => self.chunk_buffers.set_transform(&self.queue, world)
+            }
+        }
+        self.chunk_buffers.sync(&self.device, &self.queue, &mut self.chunks, &see_through);
+        if let Some(culler) = &mut self.gpu_culler {
+            culler.update_chunks(&self.device, &self.queue, &self.chunk_buffers);
+        }
+
+        // the onion skins are only uploaded again when they show other frames, the frames besides the current one don't change
+        let frames: [Option<usize>; 2] = self.timeline.onion_frames();
+        if frames != self.onion_frames || self.onion_stale {
+            for (buffers, frame) in self.onion_skins.iter_mut().zip(frames) {
+                buffers.set_transform(&self.queue, self.chunk_buffers.transform());
+                let mut scene: ChunkMap = frame.map_or_else(ChunkMap::new, |frame| self.timeline.scene(frame, &self.layers));
+                buffers.retain(&self.device, &self.queue, &scene);
+                buffers.sync(&self.device, &self.queue, &mut scene, &see_through);
+            }
+            self.onion_frames = frames;
+            self.onion_stale = false;
+        }
+    }
+
+    // Record culling, the shadow and scene passes and post-processing into `output`, `timer` measures the opaque scene
+    // pass. Returns the number of direct draw calls, and how often the gpu culled chunks were drawn indirectly
+    fn encode_frame(&self, encoder: &mut CommandEncoder, output: &TextureView, mut timer: Option<&mut GpuTimer>) -> (u32, u32) {
+        // pick the chunks in view, and their level of detail. The gpu culls the selected object, which can have many
+        // chunks, in its own space. The other objects are culled on the cpu
+        let selected: usize = self.objects.selected;
+        let visible: Vec<usize> = (0..self.objects.len()).filter(|index| self.objects.is_visible(*index)).collect();
+        let culler: Option<&GpuCuller> = self.gpu_culler.as_ref().filter(|_| self.gpu_culling && visible.contains(&selected));
+        let mut draws: Vec<ChunkDraw> = match culler {
+            Some(culler) => {
+                let world: Matrix4<f32> = self.chunk_buffers.transform();
+                culler.dispatch(encoder, &self.queue, Matrix4::from(self.camera.uniform.view_proj) * world, self.local_eye(world), &self.lod);
+                // a chunk missing from the buffers is skipped, its slot in the indirect buffer just goes unused. So are
+                // levels without instances, the gpu can't pick anything to draw from them
+                culler.order.iter().enumerate().filter_map(|(slot, pos)| self.chunk_buffers.chunks.get(pos).map(|chunk| (slot, chunk))).flat_map(|(slot, chunk)| {
+                    let origin: u32 = self.chunk_buffers.origin_offset(chunk);
+                    chunk.levels.iter().enumerate().filter(|(_, level)| level.count > 0).map(move |(lod, level)| ChunkDraw::Indirect(&self.chunk_buffers.bind_group, level, origin, &culler.indirect_buffer, GpuCuller::indirect_offset(slot, lod)))
+                }).collect()
+            }
+            None => Vec::new()
+        };
+        for index in visible.iter().copied().filter(|index| culler.is_none() || *index != selected) {
+            draws.extend(self.cull(self.object_buffers(index)));
+        }
+
+        let (mut draw_calls, mut indirect_passes): (u32, u32) = (0, 0);
+
+        // every chunk casts a shadow at full detail, also those outside the view
+        let bounds: Option<Aabb> = visible.iter().filter_map(|index| self.object_buffers(*index).bounds()).reduce(|a, b| a.union(&b));
+        if self.shadow_map.update(&self.queue, &self.light, bounds) {
+            let mut shadow_pass: RenderPass = self.shadow_map.begin(encoder);
+            shadow_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+            shadow_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            shadow_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint16);
+            for buffers in visible.iter().map(|index| self.object_buffers(*index)) {
+                for chunk in buffers.chunks.values() {
+                    shadow_pass.set_bind_group(2, &buffers.bind_group, &[buffers.origin_offset(chunk)]);
+                    shadow_pass.set_vertex_buffer(1, chunk.levels[0].buffer.buffer.slice(..));
+                    shadow_pass.draw_indexed(0..self.num_indices, 0, 0..chunk.levels[0].count);
+                    draw_calls += 1;
+                }
+            }
+        }
+
+        if let Some(timer) = &mut timer {timer.begin(encoder)};
+        let mut render_pass: RenderPass = self.post.targets.begin(encoder);
+        render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint16);
+
+        for pipeline in self.pipelines.passes(self.render_mode) {
+            render_pass.set_pipeline(pipeline);
+            draw_calls += self.draw_chunks(&mut render_pass, &draws);
+            indirect_passes += culler.is_some() as u32;
+        }
+        drop(render_pass);
+        if let Some(timer) = &mut timer {timer.end(encoder)};
+
+        // every chunk is drawn again, the vertex shaders keep the opaque and the see-through voxels apart
+        let mut transparent_pass: RenderPass = self.post.targets.begin_transparent(encoder);
+        if let Some(pipeline) = self.pipelines.transparent_pass(self.render_mode) {
+            transparent_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+            transparent_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+            transparent_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            transparent_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint16);
+            transparent_pass.set_pipeline(pipeline);
+            draw_calls += self.draw_chunks(&mut transparent_pass, &draws);
+            indirect_passes += culler.is_some() as u32;
+            // the onion skins are see-through whatever their colours, they are never drawn opaque
+            for buffers in self.onion_skins.iter().filter(|_| visible.contains(&selected)) {
+                draw_calls += self.draw_chunks(&mut transparent_pass, &self.cull(buffers));
+            }
+        }
+        drop(transparent_pass);
+
+        self.post.run(&self.queue, encoder, self.camera.build_projection_matrix(), output);
+        (draw_calls, indirect_passes)
+    }
+
+    // The camera's position in the space of an object
+    fn local_eye(&self, world: Matrix4<f32>) -> Point3<f32> {
+        Point3::from_homogeneous(world.invert().unwrap_or_else(Matrix4::identity) * self.camera.eye.to_homogeneous())
+    }
+
+    // The chunks of `buffers` in view and their level of detail, culled on the cpu in the space of their object
+    fn cull<'a>(&self, buffers: &'a ChunkBuffers) -> Vec<ChunkDraw<'a>> {
+        let frustum: Frustum = Frustum::from_matrix(Matrix4::from(self.camera.uniform.view_proj) * buffers.transform());
+        let eye: Point3<f32> = self.local_eye(buffers.transform());
+        buffers.chunks.iter().filter_map(|(pos, chunk)| match classify(&frustum, eye, &self.lod, *pos) {
+            Visibility::Visible(lod) => chunk.levels.get(lod).map(|level| ChunkDraw::Direct(&buffers.bind_group, level, buffers.origin_offset(chunk))),
+            Visibility::Culled => None
+        }).collect()
+    }
+
+    // Draw the culled chunk levels with the pipeline already set, returns the number of direct draw calls. Most
+    // indirect ones draw nothing, they are counted on the gpu instead
+    fn draw_chunks<'a>(&'a self, render_pass: &mut RenderPass<'a>, draws: &[ChunkDraw<'a>]) -> u32 {
+        for draw in draws {
+            match draw {
+                ChunkDraw::Direct(bind_group, level, origin) => {
+                    render_pass.set_bind_group(2, bind_group, &[*origin]);
+                    render_pass.set_vertex_buffer(1, level.buffer.buffer.slice(..));
+                    render_pass.draw_indexed(0..self.num_indices, 0, 0..level.count);
+                }
+                ChunkDraw::Indirect(bind_group, level, origin, arguments, offset) => {
+                    render_pass.set_bind_group(2, bind_group, &[*origin]);
+                    render_pass.set_vertex_buffer(1, level.buffer.buffer.slice(..));
+                    render_pass.draw_indexed_indirect(arguments, *offset);
+                }
             }
+        }
+        draws.iter().filter(|draw| matches!(draw, ChunkDraw::Direct(..))).count() as u32
+    }
+
+    pub fn render(&mut self) -> Result<(), SurfaceError> {
+        // the gui is laid out before the frame is recorded, so its changes show right away
+        let batch: HudBatch = self.build_gui();
+        let overlay: HudBatch = self.build_overlay();
+
+        // waiting for a surface texture, which blocks on vsync, doesn't count towards the cpu time
+        let before_acquire: Duration = self.frame_start.elapsed();
+        let output: SurfaceTexture = match &self.surface {
+            Some(surface) => surface.get_current_texture()?,
+            None => return Err(SurfaceError::Lost)
+        };
+        let recording: Instant = Instant::now();
+        let view: TextureView = output.texture.create_view(&TextureViewDescriptor::default());
+        let mut encoder: CommandEncoder = self.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Render Encoder") });
+
+        let mut timer: Option<GpuTimer> = self.gpu_timer.take();
+        let (mut draw_calls, indirect_passes): (u32, u32) = self.encode_frame(&mut encoder, &view, timer.as_mut());
+        self.gpu_timer = timer;
+        if let Some(culler) = self.gpu_culler.as_mut().filter(|_| indirect_passes > 0) {culler.read_drawn(&mut encoder)};
+        self.overlay.draw(&self.device, &self.queue, &mut encoder, &view, self.size, &overlay);
+        self.hud.draw(&self.device, &self.queue, &mut encoder, &view, self.size, &batch);
+        let cpu_time: Duration = before_acquire + recording.elapsed();
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+
+        // the gpu time arrives a few frames late, keep showing the last one until then
+        if let Some(gpu_time) = self.gpu_timer.as_mut().and_then(|timer| timer.collect(&self.device)) {
+            self.stats.gpu_time = Some(gpu_time);
+        }
+        // the chunks the gpu drew arrive a few frames late too
+        if let Some(culler) = self.gpu_culler.as_mut().filter(|_| indirect_passes > 0) {
+            draw_calls += indirect_passes * culler.collect_drawn(&self.device);
+        }
+        self.stats.cpu_time = cpu_time;
+        self.stats.voxels = (0..self.objects.len()).map(|index| self.object_chunks(index).len()).sum();
+        self.stats.chunks = (0..self.objects.len()).map(|index| self.object_buffers(index).chunks.len()).sum();
+        self.stats.draw_calls = draw_calls;
+        (self.stats.buffer_memory, self.stats.pooled_memory) = (0..self.objects.len()).map(|index| self.object_buffers(index).buffer_bytes()).fold((0, 0), |(live, pooled), (a, b)| (live + a, pooled + b));
+        self.stats_logger.log(&self.stats);
+        self.needs_redraw = false;
+
+        Ok(())
+    }
 
-            _ => {Err(SurfaceError::Lost)}
+    // The editor panels and the stats, drawn over the finished frame
+    fn build_gui(&mut self) -> HudBatch {
+        let mut ui = self.gui.begin();
+        let viewport: Rect = match self.gui.visible {
+            true => crate::gui::editor(self, &mut ui),
+            false => Rect::new(0.0, 0.0, self.size.width as f32, self.size.height as f32)
+        };
+        if self.show_stats {
+            ui.batch.panel(viewport.x + 8.0, viewport.y + 8.0, 2.0, &self.stats.lines());
         }
+        self.gui.end(ui)
+    }
+
+    // The gizmo over the scene, with the handle being dragged or else the one under the cursor lit up
+    fn build_overlay(&self) -> HudBatch {
+        let mut batch: HudBatch = HudBatch::default();
+        if let Some(gizmo) = self.gizmo() {
+            let highlight: Option<usize> = match (&self.gizmo_edit, self.input.cursor) {
+                (Some(edit), _) => Some(edit.drag.axis),
+                (None, Some(cursor)) if !self.gui.is_over_panel() => gizmo.pick(&self.camera, self.screen(), cursor),
+                _ => None
+            };
+            gizmo.draw(&mut batch, &self.camera, self.screen(), highlight);
+        }
+        batch
+    }
+
+    // Render a frame without a window, through the same passes and post-processing as on screen. The image is drawn
+    // in the surface format the post-processing chain was built for, bgra surfaces get their channels swapped back
+    pub fn render_image(&mut self, width: u32, height: u32) -> anyhow::Result<RgbaImage> {
+        let format: TextureFormat = self.config.format;
+        let bgra: bool = match format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            _ => anyhow::bail!("can't read back images in the surface format {:?}", format)
+        };
+        // the window's view is put back once the frame is submitted
+        let (view_width, view_height, view_aspect): (u32, u32, f32) = (self.post.targets.width, self.post.targets.height, self.camera.aspect);
+        self.sync_chunks();
+        self.set_view_size(width, height, width as f32 / height as f32);
+
+        let target: texture::Texture = texture::Texture::create_render_target(&self.device, width, height, format, 1, "image target");
+        let mut encoder: CommandEncoder = self.device.create_command_encoder(&CommandEncoderDescriptor {label: Some("Image Encoder")});
+        self.encode_frame(&mut encoder, &target.view, None);
+
+        // rows of a texture copy have to be aligned
+        let row_bytes: u32 = width * 4;
+        let padded_row_bytes: u32 = row_bytes.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback: Buffer = self.device.create_buffer(&BufferDescriptor {label: Some("Image Readback Buffer"), size: (padded_row_bytes * height) as BufferAddress, usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST, mapped_at_creation: false});
+        encoder.copy_texture_to_buffer(
+            target.texture.as_image_copy(),
+            ImageCopyBuffer {buffer: &readback, layout: ImageDataLayout {offset: 0, bytes_per_row: Some(padded_row_bytes), rows_per_image: Some(height)}},
+            Extent3d {width, height, depth_or_array_layers: 1}
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.set_view_size(view_width, view_height, view_aspect);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        readback.slice(..).map_async(MapMode::Read, move |result| {sender.send(result).ok();});
+        self.device.poll(Maintain::Wait);
+        receiver.recv()?.context("failed to read back the rendered image")?;
+
+        let data = readback.slice(..).get_mapped_range();
+        let mut pixels: Vec<u8> = data.chunks(padded_row_bytes as usize).flat_map(|row| &row[..row_bytes as usize]).copied().collect();
+        if bgra {
+            pixels.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        }
+        RgbaImage::from_raw(width, height, pixels).context("rendered image has the wrong size")
+    }
+
+    // Size the post-processing targets and the camera's projection for a frame of `width` by `height`
+    fn set_view_size(&mut self, width: u32, height: u32, aspect: f32) {
+        if self.post.targets.width != width || self.post.targets.height != height {
+            self.post.resize(&self.device, width, height, self.sample_count());
+        }
+        self.camera.aspect = aspect;
+        self.camera.uniform.view_proj = self.camera.build_view_projection_matrix().into();
+        self.camera.uniform.view = self.camera.build_view_matrix().into();
+        self.queue.write_buffer(&self.camera_buffer, 0, cast_slice(&[self.camera.uniform]));
+    }
+
+    // Path trace the current view on a background thread. The image is written to `path` after every power of two
+    // samples, so it sharpens while the render runs. Progress and failures are logged
+    pub fn start_path_trace(&self, settings: TraceSettings, path: PathBuf) -> JoinHandle<()> {
+        let mut scene: TraceScene = TraceScene::new(&self.world_scene(), &self.palette);
+        scene.sky.sun_direction = self.light.direction;
+        let mut tracer: PathTracer = PathTracer::new(scene, TraceCamera::from(&self.camera), settings);
+
+        std::thread::spawn(move || {
+            let start: Instant = Instant::now();
+            let mut result: anyhow::Result<()> = Ok(());
+            tracer.render(|tracer| {
+                if result.is_ok() && (tracer.samples().is_power_of_two() || tracer.is_done()) {
+                    log::info!("path trace: {}/{} samples after {:.1?}", tracer.samples(), tracer.settings.samples, start.elapsed());
+                    result = tracer.save(&path);
+                }
+            });
+            if let Err(e) = result {
+                log::error!("path trace failed: {:#}", e);
+            }
+        })
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+    use pollster::FutureExt;
+    use wgpu::TextureFormat;
+    use crate::assets::Assets;
+    use crate::model::Model;
+    use crate::palette::{Palette, PaletteEntry};
+    use crate::post::PostProcess;
+    use crate::state::State;
+
+    #[test]
+    fn test_render_image_with_bgra_output() {
+        let mut state: State = State::new(None, Assets::default()).block_on().unwrap();
+        assert_eq!(state.config.format, TextureFormat::Rgba8UnormSrgb);
+        let rgba: RgbaImage = state.render_image(32, 16).unwrap();
+
+        // a window surface is usually bgra, the image comes out the same
+        state.config.format = TextureFormat::Bgra8UnormSrgb;
+        state.post = PostProcess::new(&state.device, &state.assets, state.config.format, state.config.width, state.config.height, state.sample_count()).unwrap();
+        let bgra: RgbaImage = state.render_image(32, 16).unwrap();
+        assert_eq!(bgra.dimensions(), (32, 16));
+        assert_eq!(bgra, rgba);
+        assert!(rgba.pixels().any(|pixel| pixel.0[0] != pixel.0[2]));
+    }
+
+    #[test]
+    fn test_models_only_use_atlas_tiles() {
+        let mut state: State = State::new(None, Assets::default()).block_on().unwrap();
+        let tile: u32 = state.atlas.tile_count();
+        let palette: Palette = Palette::new(vec![PaletteEntry::textured((1.0, 1.0, 1.0, 1.0).into(), tile)]);
+        assert!(state.set_model(Model {objects: Vec::new(), palette}).is_err());
+        assert_eq!(state.palette, Palette::default());
+
+        let palette: Palette = Palette::new(vec![PaletteEntry::textured((1.0, 1.0, 1.0, 1.0).into(), tile - 1)]);
+        state.set_model(Model {objects: Vec::new(), palette: palette.clone()}).unwrap();
+        assert_eq!(state.palette, palette);
+    }
+}
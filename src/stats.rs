@@ -0,0 +1,148 @@
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wgpu::{Buffer, BufferAddress, BufferDescriptor, BufferUsages, CommandEncoder, Device, Features, MapMode, QuerySet, QuerySetDescriptor, QueryType, Queue, QUERY_SIZE};
+
+// Numbers describing the last rendered frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub cpu_time: Duration, // update and command recording, without acquiring, submitting or presenting the frame
+    pub gpu_time: Option<Duration>, // opaque scene pass duration, without culling, shadows or post-processing. Only with Features::TIMESTAMP_QUERY. Lags a frame or two behind
+    pub voxels: usize,
+    pub chunks: usize,
+    pub draw_calls: u32,
+    pub buffer_memory: u64, // bytes of instance buffers holding chunks
+    pub pooled_memory: u64, // bytes of released instance buffers kept for reuse
+}
+
+impl FrameStats {
+    // One entry per line of the on-screen overlay
+    pub fn lines(&self) -> Vec<String> {
+        let gpu: String = match self.gpu_time {
+            Some(time) => format!("{:.2} MS", time.as_secs_f64() * 1000.0),
+            None => "N/A".to_string()
+        };
+        vec![
+            format!("CPU {:.2} MS", self.cpu_time.as_secs_f64() * 1000.0),
+            format!("GPU SCENE {}", gpu),
+            format!("VOXELS {}", self.voxels),
+            format!("CHUNKS {}", self.chunks),
+            format!("DRAWS {}", self.draw_calls),
+            format!("BUFFERS {:.1} MB", self.buffer_memory as f64 / (1024.0 * 1024.0)),
+            format!("POOLED {:.1} MB", self.pooled_memory as f64 / (1024.0 * 1024.0)),
+        ]
+    }
+}
+
+impl Display for FrameStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cpu {:.2?}, gpu scene ", self.cpu_time)?;
+        match self.gpu_time {
+            Some(time) => write!(f, "{:.2?}", time)?,
+            None => write!(f, "n/a")?
+        }
+        write!(f, ", {} voxels in {} chunks, {} draw calls, {} KiB instance buffers, {} KiB pooled", self.voxels, self.chunks, self.draw_calls, self.buffer_memory / 1024, self.pooled_memory / 1024)
+    }
+}
+
+// Measures the gpu time between two points in a command encoder with timestamp queries.
+// The result is read back asynchronously, frames recorded while the previous readback is
+// still mapped are not measured
+pub struct GpuTimer {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    readback_buffer: Buffer,
+    period: f32, // nanoseconds per timestamp tick
+    written: bool, // timestamps were recorded this frame
+    mapping: Option<Arc<AtomicBool>>, // set once the readback buffer is mapped
+}
+
+impl GpuTimer {
+    const SIZE: BufferAddress = 2 * QUERY_SIZE as BufferAddress;
+
+    // None when the device was created without timestamp queries
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            log::info!("TIMESTAMP_QUERY is not supported, gpu times are not measured");
+            return None;
+        }
+
+        let query_set: QuerySet = device.create_query_set(&QuerySetDescriptor {label: Some("Timestamp Query Set"), ty: QueryType::Timestamp, count: 2});
+        let resolve_buffer: Buffer = device.create_buffer(&BufferDescriptor {label: Some("Timestamp Resolve Buffer"), size: Self::SIZE, usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC, mapped_at_creation: false});
+        let readback_buffer: Buffer = device.create_buffer(&BufferDescriptor {label: Some("Timestamp Readback Buffer"), size: Self::SIZE, usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST, mapped_at_creation: false});
+        Some(Self {query_set, resolve_buffer, readback_buffer, period: queue.get_timestamp_period(), written: false, mapping: None})
+    }
+
+    pub fn begin(&mut self, encoder: &mut CommandEncoder) {
+        self.written = self.mapping.is_none();
+        if self.written {
+            encoder.write_timestamp(&self.query_set, 0);
+        }
+    }
+
+    pub fn end(&mut self, encoder: &mut CommandEncoder) {
+        if self.written {
+            encoder.write_timestamp(&self.query_set, 1);
+            encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+            encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, Self::SIZE);
+        }
+    }
+
+    // Call after submitting, returns the time of an earlier frame once its readback completed
+    pub fn collect(&mut self, device: &Device) -> Option<Duration> {
+        if self.written {
+            self.written = false;
+            let mapped: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+            let flag: Arc<AtomicBool> = mapped.clone();
+            self.readback_buffer.slice(..).map_async(MapMode::Read, move |result| flag.store(result.is_ok(), Ordering::Release));
+            self.mapping = Some(mapped);
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        if !self.mapping.as_ref()?.load(Ordering::Acquire) {
+            return None;
+        }
+        self.mapping = None;
+
+        let ticks: [u64; 2] = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+            [timestamps[0], timestamps[1]]
+        };
+        self.readback_buffer.unmap();
+        Some(Duration::from_nanos((ticks[1].saturating_sub(ticks[0]) as f64 * self.period as f64) as u64))
+    }
+}
+
+// Logs the frame stats at a fixed interval instead of every frame
+pub struct StatsLogger {
+    pub interval: Duration,
+    last: Instant,
+}
+
+impl StatsLogger {
+    pub fn new(interval: Duration) -> Self {
+        Self {interval, last: Instant::now()}
+    }
+
+    pub fn log(&mut self, stats: &FrameStats) {
+        if self.last.elapsed() >= self.interval {
+            self.last = Instant::now();
+            log::info!("{}", stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::stats::FrameStats;
+
+    #[test]
+    fn test_frame_stats_lines() {
+        let stats: FrameStats = FrameStats {cpu_time: Duration::from_micros(1500), gpu_time: None, voxels: 12, chunks: 1, draw_calls: 3, buffer_memory: 3 * 1024 * 1024, pooled_memory: 512 * 1024};
+        assert_eq!(stats.lines(), vec!["CPU 1.50 MS", "GPU SCENE N/A", "VOXELS 12", "CHUNKS 1", "DRAWS 3", "BUFFERS 3.0 MB", "POOLED 0.5 MB"]);
+        assert_eq!(stats.to_string(), "cpu 1.50ms, gpu scene n/a, 12 voxels in 1 chunks, 3 draw calls, 3072 KiB instance buffers, 512 KiB pooled");
+    }
+}
@@ -1,7 +1,7 @@
 use std::default::Default;
-use image::{DynamicImage, GenericImageView, RgbaImage};
+use image::{DynamicImage, RgbaImage};
 use anyhow::*;
-use wgpu::{Sampler, TextureView, Device, Queue, Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, ImageCopyTexture, Origin3d, TextureAspect, ImageDataLayout, TextureViewDescriptor, SamplerDescriptor, AddressMode, FilterMode, SurfaceConfiguration, CompareFunction};
+use wgpu::{Sampler, TextureView, Device, Queue, Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, ImageCopyTexture, Origin3d, TextureAspect, ImageDataLayout, TextureViewDescriptor, SamplerDescriptor, AddressMode, FilterMode, CompareFunction};
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -11,21 +11,19 @@ pub struct Texture {
 
 impl Texture {
     pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
-    pub fn create_depth_texture(device: &Device, config: &SurfaceConfiguration, label: &str) -> Self {
-        let size: Extent3d = Extent3d {
-            width: config.width,
-            height: config.height,
-            depth_or_array_layers: 1
-        };
+    // Depth texture with a comparison sampler, for depth buffers and shadow maps
+    pub fn create_sized_depth_texture(device: &Device, width: u32, height: u32, sample_count: u32, label: &str) -> Self {
+        let size: Extent3d = Extent3d {width, height, depth_or_array_layers: 1};
 
         let desc: TextureDescriptor = TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            // multisampled depth can't be sampled like a regular texture, and the GL backend fails to create it as one
+            usage: if sample_count > 1 {TextureUsages::RENDER_ATTACHMENT} else {TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING},
             view_formats: &[]
         };
 
@@ -47,13 +45,35 @@ impl Texture {
         Self {texture, view, sampler}
     }
 
+    // Colour target of a render pass, single sampled targets can also be read by later passes or copied out
+    pub fn create_render_target(device: &Device, width: u32, height: u32, format: TextureFormat, sample_count: u32, label: &str) -> Self {
+        let texture: wgpu::Texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {width, height, depth_or_array_layers: 1},
+            mip_level_count: 1,
+            sample_count,
+            dimension: TextureDimension::D2,
+            format,
+            usage: if sample_count > 1 {TextureUsages::RENDER_ATTACHMENT} else {TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC},
+            view_formats: &[]
+        });
+        let view: TextureView = texture.create_view(&TextureViewDescriptor::default());
+        let sampler: Sampler = device.create_sampler(&SamplerDescriptor::default());
+
+        Self {texture, view, sampler}
+    }
+
     pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8], label: &str) -> Result<Self> {
         let img: DynamicImage = image::load_from_memory(bytes)?;
         Self::from_image(device, queue, &img, Some(label))
     }
 
     pub fn from_image(device: &Device, queue: &Queue, img: &DynamicImage, label: Option<&str>) -> Result<Self> {
-        let img_rgba: RgbaImage = img.to_rgba8();
+        Self::from_rgba(device, queue, &img.to_rgba8(), FilterMode::Linear, label)
+    }
+
+    // Atlases are sampled with nearest filtering, so texels of neighbouring tiles never get mixed in
+    pub fn from_rgba(device: &Device, queue: &Queue, img_rgba: &RgbaImage, mag_filter: FilterMode, label: Option<&str>) -> Result<Self> {
 
         let dimensions: (u32, u32) = img_rgba.dimensions();
         let texture_size = Extent3d {
@@ -70,7 +90,7 @@ impl Texture {
             dimension: TextureDimension::D2,
             format: TextureFormat::Rgba8UnormSrgb,
             usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-            label: Some("texture"),
+            label,
             view_formats: &[]
         });
 
@@ -81,7 +101,7 @@ impl Texture {
                 origin: Origin3d::ZERO,
                 aspect: TextureAspect::All
             },
-            &img_rgba,
+            img_rgba,
             ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(4 * dimensions.0),
@@ -95,7 +115,7 @@ impl Texture {
             address_mode_u: AddressMode::ClampToEdge,
             address_mode_v: AddressMode::ClampToEdge,
             address_mode_w: AddressMode::ClampToEdge,
-            mag_filter: FilterMode::Linear,
+            mag_filter,
             min_filter: FilterMode::Nearest,
             mipmap_filter: FilterMode::Nearest,
             ..Default::default()
@@ -0,0 +1,229 @@
+use crate::chunk::ChunkMap;
+use crate::layers::{Layer, Layers};
+use crate::model::Frame;
+
+// Shortest time a frame can be shown for, so playback always moves on
+pub const MIN_FRAME_DURATION: f32 = 0.01;
+
+// The voxels of every layer in a frame, and how long it is shown
+struct StoredFrame {
+    chunks: Vec<ChunkMap>, // empty for the current frame, whose voxels are in the layers
+    duration: f32,
+}
+
+// The frames of an animation. The frame being edited lives in `Layers` like a model without frames,
+// switching to another frame swaps the voxels of every layer
+pub struct Timeline {
+    frames: Vec<StoredFrame>,
+    current: usize,
+    pub playing: bool,
+    pub speed: f32, // playback rate, 1 shows every frame for its duration
+    pub onion_skin: bool, // draw the frames before and after the current one see-through
+    elapsed: f32, // seconds the current frame has been shown while playing
+}
+
+impl Timeline {
+    // A single frame, held by the layers
+    pub fn new(duration: f32) -> Self {
+        Self {frames: vec![StoredFrame {chunks: Vec::new(), duration: duration.max(MIN_FRAME_DURATION)}], current: 0, playing: false, speed: 1.0, onion_skin: true, elapsed: 0.0}
+    }
+
+    // The frames of a model, the first one goes to the layers. Frames with more or fewer layers than the first are cut or padded
+    pub fn from_frames(frames: Vec<Frame>) -> (Self, Layers) {
+        let mut frames = frames.into_iter();
+        let first: Frame = frames.next().unwrap_or_else(|| Frame::new(Vec::new()));
+        let mut layers: Vec<Layer> = first.layers;
+        if layers.is_empty() {
+            layers.push(Layer::new("Base", ChunkMap::new()));
+        }
+        let mut timeline: Timeline = Self::new(first.duration);
+        for frame in frames {
+            let mut chunks: Vec<ChunkMap> = frame.layers.into_iter().map(|layer| layer.chunks).collect();
+            chunks.resize(layers.len(), ChunkMap::new());
+            timeline.frames.push(StoredFrame {chunks, duration: frame.duration.max(MIN_FRAME_DURATION)});
+        }
+        (timeline, Layers {layers, active: 0})
+    }
+
+    // Copies of every frame, with the layers' names and visibility
+    pub fn frames(&self, layers: &Layers) -> Vec<Frame> {
+        (0..self.len()).map(|frame| Frame {
+            layers: layers.layers.iter().zip(self.chunks(frame, layers)).map(|(layer, chunks)| Layer {name: layer.name.clone(), visible: layer.visible, chunks: chunks.clone()}).collect(),
+            duration: self.frames[frame].duration
+        }).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn duration(&self, frame: usize) -> f32 {
+        self.frames[frame].duration
+    }
+
+    pub fn set_duration(&mut self, frame: usize, seconds: f32) {
+        self.frames[frame].duration = seconds.max(MIN_FRAME_DURATION);
+    }
+
+    // The voxels of each layer in a frame
+    fn chunks<'a>(&'a self, frame: usize, layers: &'a Layers) -> Vec<&'a ChunkMap> {
+        match frame == self.current {
+            true => layers.layers.iter().map(|layer| &layer.chunks).collect(),
+            false => self.frames[frame].chunks.iter().collect()
+        }
+    }
+
+    // The visible layers of a frame stacked, like the scene of the current one
+    pub fn scene(&self, frame: usize, layers: &Layers) -> ChunkMap {
+        let mut scene: ChunkMap = ChunkMap::new();
+        for (layer, chunks) in layers.layers.iter().zip(self.chunks(frame, layers)) {
+            if layer.visible {
+                for (position, index) in chunks.iter() {
+                    scene.set(position, Some(index));
+                }
+            }
+        }
+        scene
+    }
+
+    // Make another frame the one being edited, bringing the scene up to date
+    pub fn switch(&mut self, layers: &mut Layers, scene: &mut ChunkMap, frame: usize) {
+        if frame == self.current || frame >= self.len() {
+            return;
+        }
+        let chunks: Vec<ChunkMap> = std::mem::take(&mut self.frames[frame].chunks);
+        self.frames[self.current].chunks = layers.replace_chunks(scene, chunks);
+        self.current = frame;
+    }
+
+    // Add a copy of the current frame after it and switch to the copy, returns its index
+    pub fn duplicate(&mut self, layers: &mut Layers, scene: &mut ChunkMap) -> usize {
+        let chunks: Vec<ChunkMap> = layers.layers.iter().map(|layer| layer.chunks.clone()).collect();
+        let frame: usize = self.current + 1;
+        self.frames.insert(frame, StoredFrame {chunks, duration: self.duration(self.current)});
+        self.switch(layers, scene, frame);
+        frame
+    }
+
+    // Remove the current frame and switch to the next one, or the previous one at the end. The last remaining
+    // frame is kept. Returns the index the removed frame had
+    pub fn remove(&mut self, layers: &mut Layers, scene: &mut ChunkMap) -> Option<usize> {
+        if self.len() <= 1 {
+            return None;
+        }
+        let removed: usize = self.current;
+        self.switch(layers, scene, if removed + 1 < self.len() {removed + 1} else {removed - 1});
+        self.frames.remove(removed);
+        if self.current > removed {
+            self.current -= 1;
+        }
+        Some(removed)
+    }
+
+    // Keep the frames in step with `Layers::add`
+    pub fn add_layer(&mut self) {
+        for (frame, stored) in self.frames.iter_mut().enumerate() {
+            if frame != self.current {
+                stored.chunks.push(ChunkMap::new());
+            }
+        }
+    }
+
+    // Keep the frames in step with `Layers::remove`
+    pub fn remove_layer(&mut self, index: usize) {
+        for (frame, stored) in self.frames.iter_mut().enumerate() {
+            if frame != self.current && index < stored.chunks.len() {
+                stored.chunks.remove(index);
+            }
+        }
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        self.playing = playing && self.len() > 1;
+        self.elapsed = 0.0;
+    }
+
+    // Move playback on by `seconds`, returns the frame to switch to once the current one has been shown long enough.
+    // The animation loops
+    pub fn advance(&mut self, seconds: f32) -> Option<usize> {
+        self.elapsed += seconds * self.speed;
+        let mut frame: usize = self.current;
+        while self.elapsed >= self.frames[frame].duration {
+            self.elapsed -= self.frames[frame].duration;
+            frame = (frame + 1) % self.len();
+        }
+        (frame != self.current).then_some(frame)
+    }
+
+    // The frames drawn as onion skins, the one before and the one after the current frame. None while playing
+    pub fn onion_frames(&self) -> [Option<usize>; 2] {
+        match self.onion_skin && !self.playing {
+            true => [self.current.checked_sub(1), Some(self.current + 1).filter(|frame| *frame < self.len())],
+            false => [None, None]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+    use crate::chunk::ChunkMap;
+    use crate::layers::{Layer, Layers};
+    use crate::model::Frame;
+    use crate::timeline::Timeline;
+
+    #[test]
+    fn test_frames_swap_through_the_layers() {
+        let (a, b): (Vector3<i32>, Vector3<i32>) = (Vector3::new(0, 0, 0), Vector3::new(1, 0, 0));
+        let mut first: ChunkMap = ChunkMap::new();
+        first.set(a, Some(1));
+        let mut second: ChunkMap = ChunkMap::new();
+        second.set(b, Some(2));
+        let frames: Vec<Frame> = vec![Frame::new(vec![Layer::new("Base", first)]), Frame {layers: vec![Layer::new("Base", second)], duration: 0.3}];
+        let (mut timeline, mut layers): (Timeline, Layers) = Timeline::from_frames(frames);
+        let mut scene: ChunkMap = ChunkMap::new();
+        scene.set(a, Some(1));
+
+        timeline.switch(&mut layers, &mut scene, 1);
+        assert_eq!((scene.get(a), scene.get(b)), (None, Some(2)));
+        assert_eq!(timeline.scene(0, &layers).get(a), Some(1));
+
+        // a copy of the second frame, edited apart from it
+        assert_eq!(timeline.duplicate(&mut layers, &mut scene), 2);
+        layers.set(&mut scene, a, Some(3));
+        assert_eq!(timeline.scene(1, &layers).get(a), None);
+        assert_eq!(timeline.onion_frames(), [Some(1), None]);
+
+        // new layers reach every frame
+        layers.add("Top");
+        timeline.add_layer();
+        timeline.switch(&mut layers, &mut scene, 0);
+        assert_eq!(layers.len(), 2);
+        assert_eq!(scene.get(a), Some(1));
+        assert_eq!(timeline.frames(&layers)[2].layers[0].chunks.get(a), Some(3));
+
+        // playback loops and holds every frame for its duration
+        timeline.set_playing(true);
+        assert_eq!(timeline.advance(0.05), None);
+        assert_eq!(timeline.advance(0.05), Some(1));
+        timeline.switch(&mut layers, &mut scene, 1);
+        timeline.speed = 2.0;
+        assert_eq!(timeline.advance(0.3), Some(0));
+        assert_eq!(timeline.onion_frames(), [None, None]);
+
+        assert_eq!(timeline.remove(&mut layers, &mut scene), Some(1));
+        assert_eq!((timeline.len(), timeline.current()), (2, 1));
+        assert_eq!(scene.get(a), Some(3));
+        timeline.remove(&mut layers, &mut scene);
+        assert_eq!(timeline.remove(&mut layers, &mut scene), None);
+        assert_eq!(scene.get(a), Some(1));
+    }
+}
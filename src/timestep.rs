@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+// Splits measured frame times into steps of a fixed length, so animations advance the same at any frame rate
+pub struct FixedTimestep {
+    pub step: Duration,
+    pub max_steps: u32, // per frame, time past that is dropped so a stall doesn't fast forward
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration, max_steps: u32) -> Self {
+        Self {step, max_steps, accumulator: Duration::ZERO}
+    }
+
+    // Add the time a frame took, returns how many steps to run
+    pub fn advance(&mut self, dt: Duration) -> u32 {
+        self.accumulator += dt;
+        let steps: u32 = (self.accumulator.as_nanos() / self.step.as_nanos().max(1)) as u32;
+        if steps > self.max_steps {
+            self.accumulator = Duration::ZERO;
+            return self.max_steps;
+        }
+        self.accumulator -= self.step * steps;
+        steps
+    }
+
+    // Forget the time collected towards the next step, after a pause
+    pub fn reset(&mut self) {
+        self.accumulator = Duration::ZERO;
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1) / 60, 8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::timestep::FixedTimestep;
+
+    #[test]
+    fn test_steps_add_up_at_any_frame_rate() {
+        let step: Duration = Duration::from_millis(10);
+        let mut fast: FixedTimestep = FixedTimestep::new(step, 8);
+        let mut slow: FixedTimestep = FixedTimestep::new(step, 8);
+        let fast_steps: u32 = (0..100).map(|_| fast.advance(Duration::from_millis(4))).sum();
+        let slow_steps: u32 = (0..10).map(|_| slow.advance(Duration::from_millis(40))).sum();
+        assert_eq!((fast_steps, slow_steps), (40, 40));
+        assert_eq!((fast.advance(Duration::from_millis(9)), slow.advance(Duration::from_millis(9))), (0, 0));
+
+        // the time left over from a step counts towards the next
+        assert_eq!(fast.advance(Duration::from_millis(6)), 1);
+        assert_eq!(fast.advance(Duration::from_millis(5)), 1);
+
+        // a long stall runs at most max_steps and drops the rest
+        assert_eq!(fast.advance(Duration::from_secs(10)), 8);
+        assert_eq!(fast.advance(Duration::from_millis(9)), 0);
+    }
+}
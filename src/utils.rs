@@ -1,7 +1,4 @@
-use bytemuck::{Pod, Zeroable};
-use cgmath::Zero;
-use wgpu::{Adapter, Backends, Buffer, BufferUsages, Device, DeviceDescriptor, Features, InstanceDescriptor, Queue, RequestAdapterOptions, Surface};
-use wgpu::PowerPreference::HighPerformance;
+use wgpu::{Buffer, BufferUsages, Device};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
 pub(crate) fn create_wgpu_buffer(device: &Device, label: Option<&str>, contents: &[u8], usage: BufferUsages) -> Buffer {
@@ -15,12 +12,12 @@ mod tests {
     use pollster::FutureExt;
     use wgpu::BufferUsages;
     use crate::utils::create_wgpu_buffer;
-    use wgpu::{Adapter, Backends, Device, DeviceDescriptor, Features, InstanceDescriptor, Queue, RequestAdapterOptions, Surface};
+    use crate::assets::Assets;
     use crate::state::State;
 
     #[test]
     fn test_create_wgpu_buffer() {
-        let state: State = State::new(None).block_on();
+        let state: State = State::new(None, Assets::default()).block_on().unwrap();
 
         create_wgpu_buffer(&state.device, Some("Buffer 1"), bytemuck::cast_slice(&[0; 1024]), BufferUsages::VERTEX);
         create_wgpu_buffer(&state.device, Some("Buffer 2"), bytemuck::cast_slice(&[0; 1024 * 1024]), BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST);
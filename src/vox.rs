@@ -0,0 +1,605 @@
+use std::collections::HashMap;
+use anyhow::{anyhow, bail, Context, Result};
+use cgmath::{Matrix3, SquareMatrix, Vector3, Vector4};
+use crate::chunk::ChunkMap;
+use crate::layers::Layer;
+use crate::model::{Frame, Model};
+use crate::palette::{Material, Palette, PaletteEntry, MAX_ENTRIES};
+
+// Largest model MagicaVoxel opens, along every axis
+const MAX_MODEL_SIZE: i32 = 256;
+
+// Frame numbers past this are taken for a broken file rather than an animation
+const MAX_FRAMES: usize = 10000;
+
+// MagicaVoxel's .vox format: a MAIN chunk holding models (SIZE and XYZI), the palette (RGBA), materials (MATL),
+// layers (LAYR) and a scene graph (nTRN, nGRP and nSHP nodes) placing the models.
+// MagicaVoxel is z up, voxel (x, y, z) of the file is (x, z, -y) here. Animated shapes list a model per key frame
+// with its frame number in `_f`, a shape shows the model of the last key at or before a frame
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < count {
+            bail!("unexpected end of file");
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn count(&mut self) -> Result<usize> {
+        let count: i32 = self.i32()?;
+        usize::try_from(count).map_err(|_| anyhow!("negative count {}", count))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length: usize = self.count()?;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>> {
+        (0..self.count()?).map(|_| Ok((self.string()?, self.string()?))).collect()
+    }
+
+    // Chunk id, content and children
+    fn chunk(&mut self) -> Result<([u8; 4], &'a [u8], &'a [u8])> {
+        let id: [u8; 4] = self.take(4)?.try_into().unwrap();
+        let content: usize = self.u32()? as usize;
+        let children: usize = self.u32()? as usize;
+        Ok((id, self.take(content)?, self.take(children)?))
+    }
+}
+
+enum Node {
+    Transform {child: i32, layer: i32, rotation: Matrix3<f32>, translation: Vector3<i32>},
+    Group {children: Vec<i32>},
+    Shape {models: Vec<(usize, usize)>}, // model and the frame it starts at
+}
+
+// A scene node still to be visited: its id, layer, the transform of its parents and its depth
+type Visit = (i32, i32, Matrix3<f32>, Vector3<i32>, usize);
+
+// A model's voxels, in the file's coordinates
+struct VoxModel {
+    size: Vector3<i32>,
+    voxels: Vec<(Vector3<i32>, u8)>,
+}
+
+// MagicaVoxel's palette when a file has no RGBA chunk: a 6x6x6 colour cube followed by red, green, blue and grey ramps.
+// Index 0 is the empty voxel, so entry i is colour index i + 1
+pub fn default_palette() -> Palette {
+    let levels: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    let ramp: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    let cube = (0..215).map(|i| [levels[i / 36], levels[i / 6 % 6], levels[i % 6]]);
+    let ramps = (0..4).flat_map(|channel| ramp.iter().map(move |&v| match channel {
+        3 => [v; 3],
+        _ => {
+            let mut color: [u8; 3] = [0; 3];
+            color[channel] = v;
+            color
+        }
+    }));
+    let mut entries: Vec<PaletteEntry> = cube.chain(ramps).map(|[r, g, b]| PaletteEntry::new(Vector4::new(r, g, b, 255).map(|c| c as f32 / 255.0))).collect();
+    entries.push(PaletteEntry::new(Vector4::new(0.0, 0.0, 0.0, 0.0)));
+    Palette::new(entries)
+}
+
+// Rotations are stored as a byte: bits 0-1 and 2-3 pick the column of the one in the first and second row,
+// bits 4-6 flip the sign of the rows
+fn parse_rotation(bits: u8) -> Result<Matrix3<f32>> {
+    let (first, second): (usize, usize) = ((bits & 3) as usize, (bits >> 2 & 3) as usize);
+    if first > 2 || second > 2 || first == second {
+        bail!("invalid rotation {}", bits);
+    }
+    let mut rows: [[f32; 3]; 3] = [[0.0; 3]; 3];
+    for (row, column) in [first, second, 3 - first - second].into_iter().enumerate() {
+        rows[row][column] = if bits >> (4 + row) & 1 == 1 {-1.0} else {1.0};
+    }
+    // Matrix3::new takes columns
+    Ok(Matrix3::new(rows[0][0], rows[1][0], rows[2][0], rows[0][1], rows[1][1], rows[2][1], rows[0][2], rows[1][2], rows[2][2]))
+}
+
+fn parse_node(id: &[u8; 4], content: &[u8]) -> Result<(i32, Node)> {
+    let mut reader: Reader = Reader {bytes: content};
+    let node_id: i32 = reader.i32()?;
+    reader.dict()?;
+    let node: Node = match id {
+        b"nTRN" => {
+            let child: i32 = reader.i32()?;
+            reader.i32()?;
+            let layer: i32 = reader.i32()?;
+            let frames: Vec<HashMap<String, String>> = (0..reader.count()?).map(|_| reader.dict()).collect::<Result<_>>()?;
+            // only the first frame of an animated transform is used
+            let frame: HashMap<String, String> = frames.into_iter().next().unwrap_or_default();
+            let rotation: Matrix3<f32> = match frame.get("_r") {
+                Some(r) => parse_rotation(r.parse().with_context(|| format!("invalid rotation {:?}", r))?)?,
+                None => Matrix3::identity()
+            };
+            let translation: Vector3<i32> = match frame.get("_t") {
+                Some(t) => {
+                    let values: Vec<i32> = t.split_whitespace().map(str::parse).collect::<Result<_, _>>().with_context(|| format!("invalid translation {:?}", t))?;
+                    match values[..] {
+                        [x, y, z] => Vector3::new(x, y, z),
+                        _ => bail!("invalid translation {:?}", t)
+                    }
+                }
+                None => Vector3::new(0, 0, 0)
+            };
+            Node::Transform {child, layer, rotation, translation}
+        }
+        b"nGRP" => Node::Group {children: (0..reader.count()?).map(|_| reader.i32()).collect::<Result<_>>()?},
+        _ => Node::Shape {models: (0..reader.count()?).map(|_| {
+            let model: usize = reader.count()?;
+            let frame: usize = match reader.dict()?.get("_f") {
+                Some(frame) => frame.parse().with_context(|| format!("invalid frame {:?}", frame))?,
+                None => 0
+            };
+            Ok((model, frame))
+        }).collect::<Result<_>>()?}
+    };
+    Ok((node_id, node))
+}
+
+fn parse_material(material: &mut Material, properties: &HashMap<String, String>) -> Result<()> {
+    let value = |name: &str| properties.get(name).map(|value| value.parse::<f32>().with_context(|| format!("invalid {} {:?}", name, value))).transpose();
+    let kind: &str = properties.get("_type").map_or("_diffuse", String::as_str);
+    if let Some(rough) = value("_rough")? {
+        material.roughness = rough;
+    }
+    match kind {
+        "_metal" => material.metalness = value("_metal")?.unwrap_or(1.0),
+        "_glass" => {
+            material.transparency = value("_trans")?.unwrap_or(1.0);
+            // stored without the 1 of the vacuum
+            material.ior = 1.0 + value("_ior")?.unwrap_or(0.5);
+        }
+        "_emit" => material.emission = value("_emit")?.unwrap_or(1.0) * (1.0 + value("_flux")?.unwrap_or(0.0)),
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Model> {
+    let mut reader: Reader = Reader {bytes};
+    if reader.take(4)? != b"VOX " {
+        bail!("not a MagicaVoxel file");
+    }
+    reader.u32()?;
+    let (id, _, children) = reader.chunk()?;
+    if &id != b"MAIN" {
+        bail!("missing MAIN chunk");
+    }
+
+    let mut models: Vec<VoxModel> = Vec::new();
+    let mut size: Option<Vector3<i32>> = None;
+    let mut palette: Palette = default_palette();
+    let mut materials: Vec<(usize, HashMap<String, String>)> = Vec::new();
+    let mut nodes: HashMap<i32, Node> = HashMap::new();
+    let mut layers: Vec<(i32, Layer)> = Vec::new();
+
+    let mut reader: Reader = Reader {bytes: children};
+    while !reader.bytes.is_empty() {
+        let (id, content, _) = reader.chunk()?;
+        let name: String = String::from_utf8_lossy(&id).into_owned();
+        let mut content: Reader = Reader {bytes: content};
+        match &id {
+            b"SIZE" => size = Some(Vector3::new(content.i32()?, content.i32()?, content.i32()?)),
+            b"XYZI" => {
+                let size: Vector3<i32> = size.take().context("XYZI chunk without a SIZE chunk")?;
+                let voxels: Vec<(Vector3<i32>, u8)> = (0..content.count()?).map(|_| {
+                    let [x, y, z, index]: [u8; 4] = content.take(4)?.try_into().unwrap();
+                    Ok((Vector3::new(x, y, z).cast().unwrap(), index))
+                }).collect::<Result<_>>()?;
+                models.push(VoxModel {size, voxels});
+            }
+            b"RGBA" => palette = Palette::new((0..MAX_ENTRIES).map(|_| Ok(PaletteEntry::new(Vector4::from(<[u8; 4]>::try_from(content.take(4)?).unwrap().map(|c| c as f32 / 255.0))))).collect::<Result<_>>()?),
+            b"MATL" => materials.push((content.count()?, content.dict()?)),
+            b"LAYR" => {
+                let layer: i32 = content.i32()?;
+                let properties: HashMap<String, String> = content.dict()?;
+                let mut entry: Layer = Layer::new(properties.get("_name").map_or(&format!("Layer {}", layer), |name| name), ChunkMap::new());
+                entry.visible = properties.get("_hidden").is_none_or(|hidden| hidden != "1");
+                layers.push((layer, entry));
+            }
+            b"nTRN" | b"nGRP" | b"nSHP" => {
+                let (node_id, node) = parse_node(&id, content.bytes).with_context(|| format!("invalid {} chunk", name))?;
+                nodes.insert(node_id, node);
+            }
+            _ => {}
+        }
+    }
+
+    // material i belongs to colour index i, which is palette entry i - 1
+    for (index, properties) in materials {
+        if let Some(entry) = index.checked_sub(1).and_then(|i| palette.entries.get_mut(i)) {
+            parse_material(&mut entry.material, &properties).with_context(|| format!("invalid material {}", index))?;
+        }
+    }
+
+    let mut placed: Vec<(i32, usize, Vector3<i32>, u8)> = Vec::new();
+    let mut place = |layer: i32, frame: usize, model: &VoxModel, rotation: Matrix3<f32>, translation: Vector3<i32>, centered: bool| {
+        let center: Vector3<i32> = if centered {model.size / 2} else {Vector3::new(0, 0, 0)};
+        for (position, index) in &model.voxels {
+            let p: Vector3<i32> = (rotation * (position - center).cast().unwrap()).map(|c| c.round() as i32) + translation;
+            placed.push((layer, frame, Vector3::new(p.x, p.z, -p.y), index.wrapping_sub(1)));
+        }
+    };
+    let frame_count: usize = nodes.values().filter_map(|node| match node {
+        Node::Shape {models} => models.iter().map(|(_, frame)| frame + 1).max(),
+        _ => None
+    }).max().unwrap_or(1);
+    if frame_count > MAX_FRAMES {
+        bail!("{} frames are more than the {} supported", frame_count, MAX_FRAMES);
+    }
+    match nodes.is_empty() {
+        // files from before the scene graph have their models at the origin
+        true => for model in &models {
+            place(0, 0, model, Matrix3::identity(), Vector3::new(0, 0, 0), false);
+        },
+        false => {
+            // walk the graph from the root, transforms apply to everything below them
+            let mut stack: Vec<Visit> = vec![(0, 0, Matrix3::identity(), Vector3::new(0, 0, 0), 0)];
+            while let Some((node, layer, rotation, translation, depth)) = stack.pop() {
+                if depth > nodes.len() {
+                    bail!("the scene graph has a cycle");
+                }
+                match nodes.get(&node).ok_or_else(|| anyhow!("missing scene node {}", node))? {
+                    Node::Transform {child, layer: own, rotation: r, translation: t} => {
+                        let layer: i32 = if *own >= 0 {*own} else {layer};
+                        stack.push((*child, layer, rotation * r, (rotation * t.cast().unwrap()).map(|c| c.round() as i32) + translation, depth + 1));
+                    }
+                    Node::Group {children} => stack.extend(children.iter().map(|child| (*child, layer, rotation, translation, depth + 1))),
+                    Node::Shape {models: shape} => for frame in 0..frame_count {
+                        // frames before the first key show the first model
+                        let key: Option<&(usize, usize)> = shape.iter().filter(|(_, key)| *key <= frame).max_by_key(|(_, key)| *key).or_else(|| shape.iter().min_by_key(|(_, key)| *key));
+                        let Some((index, _)) = key else {continue};
+                        let model: &VoxModel = models.get(*index).ok_or_else(|| anyhow!("missing model {}", index))?;
+                        place(layer, frame, model, rotation, translation, true);
+                    }
+                }
+            }
+        }
+    }
+
+    // every layer that has voxels in some frame, in the file's order
+    layers.sort_by_key(|(id, _)| *id);
+    let mut frames: Vec<Vec<ChunkMap>> = vec![Vec::new(); frame_count];
+    for (layer, frame, position, index) in placed {
+        let slot: usize = match layers.iter().position(|(id, _)| *id == layer) {
+            Some(slot) => slot,
+            None => {
+                layers.push((layer, Layer::new(&format!("Layer {}", layer), ChunkMap::new())));
+                layers.len() - 1
+            }
+        };
+        if frames[frame].len() <= slot {
+            frames[frame].resize(slot + 1, ChunkMap::new());
+        }
+        frames[frame][slot].set(position, Some(index));
+    }
+    let used: Vec<usize> = (0..layers.len()).filter(|slot| frames.iter().any(|chunks| chunks.get(*slot).is_some_and(|chunks| !chunks.is_empty()))).collect();
+    let frames: Vec<Frame> = frames.into_iter().map(|mut chunks| {
+        chunks.resize(layers.len(), ChunkMap::new());
+        let mut frame_layers: Vec<Layer> = used.iter().map(|slot| Layer {name: layers[*slot].1.name.clone(), visible: layers[*slot].1.visible, chunks: std::mem::take(&mut chunks[*slot])}).collect();
+        if frame_layers.is_empty() {
+            frame_layers.push(Layer::new("Base", ChunkMap::new()));
+        }
+        Frame::new(frame_layers)
+    }).collect();
+    Ok(Model::new(frames, palette))
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn i32(&mut self, value: i32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn string(&mut self, text: &str) {
+        self.i32(text.len() as i32);
+        self.bytes.extend(text.as_bytes());
+    }
+
+    fn dict(&mut self, entries: &[(&str, String)]) {
+        self.i32(entries.len() as i32);
+        for (key, value) in entries {
+            self.string(key);
+            self.string(value);
+        }
+    }
+
+    // A chunk without children
+    fn chunk(&mut self, id: &[u8; 4], content: Writer) {
+        self.bytes.extend(id);
+        self.i32(content.bytes.len() as i32);
+        self.i32(0);
+        self.bytes.extend(content.bytes);
+    }
+}
+
+// The properties `parse_material` reads back into the same material
+fn material_properties(material: &Material) -> Vec<(&'static str, String)> {
+    let mut properties: Vec<(&'static str, String)> = match material {
+        Material {emission, ..} if *emission > 0.0 => {
+            // _emit stops at 1, _flux raises it further
+            let emit: f32 = emission.min(1.0);
+            vec![("_type", "_emit".to_string()), ("_emit", emit.to_string()), ("_flux", (emission / emit - 1.0).to_string())]
+        }
+        Material {transparency, ior, ..} if *transparency > 0.0 => vec![("_type", "_glass".to_string()), ("_trans", transparency.to_string()), ("_ior", (ior - 1.0).to_string())],
+        Material {metalness, ..} if *metalness > 0.0 => vec![("_type", "_metal".to_string()), ("_metal", metalness.to_string())],
+        _ => vec![("_type", "_diffuse".to_string())]
+    };
+    properties.push(("_rough", material.roughness.to_string()));
+    properties
+}
+
+// Write a model as a .vox file, with its objects flattened into layers. Every layer becomes a shape with a model per
+// frame, split into blocks where it is larger than MagicaVoxel's models can be. Palette entry 255 has no colour index
+// in the file, its voxels are left out
+pub fn write(model: &Model) -> Vec<u8> {
+    let frames: Vec<Frame> = model.flatten();
+    let layers: &[Layer] = frames.first().map_or(&[], |frame| &frame.layers);
+    let mut children: Writer = Writer::default();
+    let mut model_count: i32 = 0;
+    let mut shapes: Vec<(i32, Vector3<i32>, Vec<i32>)> = Vec::new(); // layer, translation and the model of every frame
+    let mut skipped: usize = 0;
+
+    for layer in 0..layers.len() {
+        // in the file's coordinates
+        let voxels: Vec<Vec<(Vector3<i32>, u8)>> = frames.iter().map(|frame| match frame.layers.get(layer) {
+            Some(layer) => layer.chunks.iter().filter(|(_, index)| {
+                skipped += (*index == u8::MAX) as usize;
+                *index != u8::MAX
+            }).map(|(p, index)| (Vector3::new(p.x, -p.z, p.y), index + 1)).collect(),
+            None => Vec::new()
+        }).collect();
+        let Some((min, max)) = voxels.iter().flatten().map(|(p, _)| (*p, *p)).reduce(|(min, max), (p, _)| (min.zip(p, i32::min), max.zip(p, i32::max))) else {continue};
+
+        for x in (min.x..=max.x).step_by(MAX_MODEL_SIZE as usize) {
+            for y in (min.y..=max.y).step_by(MAX_MODEL_SIZE as usize) {
+                for z in (min.z..=max.z).step_by(MAX_MODEL_SIZE as usize) {
+                    let origin: Vector3<i32> = Vector3::new(x, y, z);
+                    let size: Vector3<i32> = (max - origin + Vector3::new(1, 1, 1)).map(|c| c.min(MAX_MODEL_SIZE));
+                    let inside = |p: &Vector3<i32>| (0..3).all(|axis| p[axis] >= origin[axis] && p[axis] < origin[axis] + size[axis]);
+                    let blocks: Vec<Vec<(Vector3<i32>, u8)>> = voxels.iter().map(|frame| frame.iter().filter(|(p, _)| inside(p)).copied().collect()).collect();
+                    if blocks.iter().all(Vec::is_empty) {
+                        continue;
+                    }
+                    let mut frames: Vec<i32> = Vec::new();
+                    for block in blocks {
+                        let mut content: Writer = Writer::default();
+                        content.i32(size.x);
+                        content.i32(size.y);
+                        content.i32(size.z);
+                        children.chunk(b"SIZE", content);
+                        let mut content: Writer = Writer::default();
+                        content.i32(block.len() as i32);
+                        for (p, index) in block {
+                            let local: Vector3<i32> = p - origin;
+                            content.bytes.extend([local.x as u8, local.y as u8, local.z as u8, index]);
+                        }
+                        children.chunk(b"XYZI", content);
+                        frames.push(model_count);
+                        model_count += 1;
+                    }
+                    // models are centred on their translation
+                    shapes.push((layer as i32, origin + size / 2, frames));
+                }
+            }
+        }
+    }
+    if skipped > 0 {
+        log::warn!("left out {} voxels of palette entry 255, which .vox files have no colour for", skipped);
+    }
+
+    // a root transform and a group holding a transform and a shape per block
+    let mut root: Writer = Writer::default();
+    root.i32(0);
+    root.dict(&[]);
+    root.i32(1);
+    root.i32(-1);
+    root.i32(-1);
+    root.i32(1);
+    root.dict(&[]);
+    children.chunk(b"nTRN", root);
+    let mut group: Writer = Writer::default();
+    group.i32(1);
+    group.dict(&[]);
+    group.i32(shapes.len() as i32);
+    for i in 0..shapes.len() as i32 {
+        group.i32(2 + 2 * i);
+    }
+    children.chunk(b"nGRP", group);
+    for (i, (layer, translation, frames)) in shapes.into_iter().enumerate() {
+        let id: i32 = 2 + 2 * i as i32;
+        let mut transform: Writer = Writer::default();
+        transform.i32(id);
+        transform.dict(&[]);
+        transform.i32(id + 1);
+        transform.i32(-1);
+        transform.i32(layer);
+        transform.i32(1);
+        transform.dict(&[("_t", format!("{} {} {}", translation.x, translation.y, translation.z))]);
+        children.chunk(b"nTRN", transform);
+        let mut shape: Writer = Writer::default();
+        shape.i32(id + 1);
+        shape.dict(&[]);
+        shape.i32(frames.len() as i32);
+        for (frame, model) in frames.into_iter().enumerate() {
+            shape.i32(model);
+            shape.dict(&[("_f", frame.to_string())]);
+        }
+        children.chunk(b"nSHP", shape);
+    }
+
+    for (id, layer) in layers.iter().enumerate() {
+        let mut content: Writer = Writer::default();
+        content.i32(id as i32);
+        let mut properties: Vec<(&str, String)> = vec![("_name", layer.name.clone())];
+        if !layer.visible {
+            properties.push(("_hidden", "1".to_string()));
+        }
+        content.dict(&properties);
+        content.i32(-1);
+        children.chunk(b"LAYR", content);
+    }
+
+    // colour index i is palette entry i - 1
+    let mut rgba: Writer = Writer::default();
+    for index in 0..MAX_ENTRIES {
+        let color: Vector4<f32> = model.palette.get(index).map_or(Vector4::new(0.0, 0.0, 0.0, 0.0), |entry| entry.color);
+        rgba.bytes.extend([color.x, color.y, color.z, color.w].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8));
+    }
+    children.chunk(b"RGBA", rgba);
+    for (index, entry) in model.palette.entries.iter().enumerate().take(MAX_ENTRIES - 1) {
+        if entry.material != Material::default() {
+            let mut content: Writer = Writer::default();
+            content.i32(index as i32 + 1);
+            content.dict(&material_properties(&entry.material));
+            children.chunk(b"MATL", content);
+        }
+    }
+
+    let mut file: Writer = Writer::default();
+    file.bytes.extend(b"VOX ");
+    file.i32(150);
+    file.bytes.extend(b"MAIN");
+    file.i32(0);
+    file.i32(children.bytes.len() as i32);
+    file.bytes.extend(children.bytes);
+    file.bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix3, SquareMatrix, Vector3, Vector4};
+    use crate::chunk::ChunkMap;
+    use crate::layers::Layer;
+    use crate::model::{Frame, Model};
+    use crate::palette::{Material, Palette, PaletteEntry};
+    use crate::vox::{default_palette, parse, parse_rotation, write};
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        [&id[..], &(content.len() as u32).to_le_bytes(), &(children.len() as u32).to_le_bytes(), content, children].concat()
+    }
+
+    fn ints(values: &[i32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn dict(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = ints(&[entries.len() as i32]);
+        for (key, value) in entries {
+            for text in [key, value] {
+                bytes.extend(ints(&[text.len() as i32]));
+                bytes.extend(text.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn file(chunks: &[Vec<u8>]) -> Vec<u8> {
+        [&b"VOX "[..], &ints(&[150]), &chunk(b"MAIN", &[], &chunks.concat())].concat()
+    }
+
+    #[test]
+    fn test_default_palette() {
+        let palette = default_palette();
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette.entries[0].color, Vector4::new(1.0, 1.0, 1.0, 1.0));
+        assert_eq!(palette.entries[1].color, Vector4::new(1.0, 1.0, 0.8, 1.0));
+        assert_eq!(palette.entries[215].color, Vector4::new(0xee as f32 / 255.0, 0.0, 0.0, 1.0));
+        assert_eq!(palette.entries[254].color, Vector4::new(0x11 as f32 / 255.0, 0x11 as f32 / 255.0, 0x11 as f32 / 255.0, 1.0));
+        assert_eq!(parse_rotation(4).unwrap(), Matrix3::identity());
+        assert!(parse_rotation(0).is_err());
+    }
+
+    #[test]
+    fn test_parse_scene_graph_and_layers() {
+        let size: Vec<u8> = chunk(b"SIZE", &ints(&[2, 2, 2]), &[]);
+        let xyzi: Vec<u8> = chunk(b"XYZI", &[ints(&[2]), vec![0, 0, 0, 1, 1, 0, 1, 3]].concat(), &[]);
+        let mut rgba: Vec<u8> = vec![255; 1024];
+        rgba[8..12].copy_from_slice(&[255, 0, 0, 128]);
+        let nodes: Vec<Vec<u8>> = vec![
+            chunk(b"nTRN", &[ints(&[0]), dict(&[]), ints(&[1, -1, -1, 1]), dict(&[])].concat(), &[]),
+            chunk(b"nGRP", &[ints(&[1]), dict(&[]), ints(&[2, 2, 4])].concat(), &[]),
+            chunk(b"nTRN", &[ints(&[2]), dict(&[]), ints(&[3, -1, 0, 1]), dict(&[("_t", "10 0 0")])].concat(), &[]),
+            chunk(b"nSHP", &[ints(&[3]), dict(&[]), ints(&[1, 0]), dict(&[])].concat(), &[]),
+            chunk(b"nTRN", &[ints(&[4]), dict(&[]), ints(&[5, -1, 1, 1]), dict(&[("_t", "0 0 5")])].concat(), &[]),
+            chunk(b"nSHP", &[ints(&[5]), dict(&[]), ints(&[1, 0]), dict(&[])].concat(), &[]),
+        ];
+        let layers: Vec<Vec<u8>> = vec![
+            chunk(b"LAYR", &[ints(&[0]), dict(&[("_name", "body")]), ints(&[-1])].concat(), &[]),
+            chunk(b"LAYR", &[ints(&[1]), dict(&[("_hidden", "1")]), ints(&[-1])].concat(), &[]),
+        ];
+        let matl: Vec<u8> = chunk(b"MATL", &[ints(&[3]), dict(&[("_type", "_metal"), ("_rough", "0.2"), ("_metal", "0.9")])].concat(), &[]);
+        let bytes: Vec<u8> = file(&[vec![size, xyzi, chunk(b"RGBA", &rgba, &[]), matl], nodes, layers].concat());
+
+        let model: Model = parse(&bytes).unwrap();
+        assert_eq!((model.objects[0].frames.len(), model.layers().len()), (1, 2));
+        assert_eq!((model.layers()[0].name.as_str(), model.layers()[0].visible), ("body", true));
+        assert_eq!((model.layers()[1].name.as_str(), model.layers()[1].visible), ("Layer 1", false));
+
+        // centred on the translation, z up in the file is y up here
+        let body = &model.layers()[0].chunks;
+        assert_eq!(body.len(), 2);
+        assert_eq!(body.get(Vector3::new(9, -1, 1)), Some(0));
+        assert_eq!(body.get(Vector3::new(10, 0, 1)), Some(2));
+        assert_eq!(model.layers()[1].chunks.get(Vector3::new(-1, 4, 1)), Some(0));
+
+        assert_eq!(model.palette.entries[2].color, Vector4::new(1.0, 0.0, 0.0, 128.0 / 255.0));
+        assert_eq!((model.palette.entries[2].material.roughness, model.palette.entries[2].material.metalness), (0.2, 0.9));
+        assert_eq!(model.scene().len(), 2);
+
+        assert!(parse(b"VOX ").is_err());
+        assert!(parse(&file(&[chunk(b"XYZI", &ints(&[0]), &[])])).is_err());
+    }
+
+    #[test]
+    fn test_write_animation() {
+        let layer = |name: &str, voxels: &[(i32, i32, i32, u8)]| {
+            let mut layer: Layer = Layer::new(name, ChunkMap::new());
+            for (x, y, z, index) in voxels {
+                layer.chunks.set(Vector3::new(*x, *y, *z), Some(*index));
+            }
+            layer
+        };
+        let mut hidden: Layer = layer("wings", &[(0, 5, 0, 2)]);
+        hidden.visible = false;
+        // the body is wider than a model can be, and moves in the second frame
+        let frames: Vec<Frame> = vec![
+            Frame::new(vec![layer("body", &[(0, 0, 0, 0), (300, 0, -2, 1), (1, 1, 1, 255)]), hidden]),
+            Frame::new(vec![layer("body", &[(0, 1, 0, 0)]), layer("wings", &[])]),
+        ];
+        let mut palette: Palette = Palette::new(vec![PaletteEntry::new(Vector4::new(1.0, 0.0, 0.0, 1.0)); 3]);
+        palette.entries[1].material = Material {metalness: 1.0, roughness: 0.25, ..Material::default()};
+        palette.entries[2].material = Material {emission: 3.0, ..Material::default()};
+
+        let model: Model = parse(&write(&Model::new(frames, palette))).unwrap();
+        assert_eq!(model.objects[0].frames.len(), 2);
+        assert_eq!(model.layers().iter().map(|layer| (layer.name.as_str(), layer.visible)).collect::<Vec<_>>(), vec![("body", true), ("wings", false)]);
+        let body = &model.objects[0].frames[0].layers[0].chunks;
+        assert_eq!((body.len(), body.get(Vector3::new(0, 0, 0)), body.get(Vector3::new(300, 0, -2))), (2, Some(0), Some(1)));
+        assert_eq!(model.objects[0].frames[0].layers[1].chunks.get(Vector3::new(0, 5, 0)), Some(2));
+        assert_eq!(model.objects[0].frames[1].layers[0].chunks.iter().collect::<Vec<_>>(), vec![(Vector3::new(0, 1, 0), 0)]);
+        assert!(model.objects[0].frames[1].layers[1].chunks.is_empty());
+        assert_eq!(model.palette.entries[1].material, Material {metalness: 1.0, roughness: 0.25, ..Material::default()});
+        assert_eq!(model.palette.entries[2].material.emission, 3.0);
+    }
+}
@@ -1,8 +1,9 @@
 use bytemuck::{Pod, Zeroable};
-use cgmath::{Vector3, Vector4};
+use cgmath::Vector3;
 use crate::Vertex;
 
 // THIS FILE CONTAINS THE BASE POINTS FOR EVERY VOXEL
+// every face has its own four vertices so it can carry its own uv coordinates
 pub(crate) const VERTEX_INDICES: &[u16] =
     &[ // front
         0, 1, 2,
@@ -12,49 +13,107 @@ pub(crate) const VERTEX_INDICES: &[u16] =
         4, 5, 6,
         6, 7, 4,
 
-        // bottom
-        5, 2, 1,
-        6, 5, 1,
+        // right
+        8, 9, 10,
+        10, 11, 8,
+
+        // left
+        12, 13, 14,
+        14, 15, 12,
 
         // top
-        0, 3, 4,
-        0, 4, 7,
+        16, 17, 18,
+        18, 19, 16,
+
+        // bottom
+        20, 21, 22,
+        22, 23, 20,
     ];
 
 pub(crate) const VV : &[Vertex] = &[
-    Vertex { position: [-0.5, 0.5, 0.5]}, // 0
-    Vertex { position: [-0.5, -0.5, 0.5]},
-    Vertex { position: [0.5, -0.5, 0.5]},
-    Vertex { position: [0.5, 0.5, 0.5]}, // 3
-    Vertex { position: [0.5, 0.5, -0.5]}, // 4
-    Vertex { position: [0.5, -0.5, -0.5]},
-    Vertex { position: [-0.5, -0.5, -0.5]},
-    Vertex { position: [-0.5, 0.5, -0.5]} // 7
+    // front (+z)
+    Vertex { position: [-0.5, -0.5, 0.5], uv: [0.0, 1.0]}, // 0
+    Vertex { position: [0.5, -0.5, 0.5], uv: [1.0, 1.0]},
+    Vertex { position: [0.5, 0.5, 0.5], uv: [1.0, 0.0]},
+    Vertex { position: [-0.5, 0.5, 0.5], uv: [0.0, 0.0]}, // 3
+
+    // back (-z)
+    Vertex { position: [0.5, -0.5, -0.5], uv: [0.0, 1.0]}, // 4
+    Vertex { position: [-0.5, -0.5, -0.5], uv: [1.0, 1.0]},
+    Vertex { position: [-0.5, 0.5, -0.5], uv: [1.0, 0.0]},
+    Vertex { position: [0.5, 0.5, -0.5], uv: [0.0, 0.0]}, // 7
+
+    // right (+x)
+    Vertex { position: [0.5, -0.5, 0.5], uv: [0.0, 1.0]}, // 8
+    Vertex { position: [0.5, -0.5, -0.5], uv: [1.0, 1.0]},
+    Vertex { position: [0.5, 0.5, -0.5], uv: [1.0, 0.0]},
+    Vertex { position: [0.5, 0.5, 0.5], uv: [0.0, 0.0]}, // 11
+
+    // left (-x)
+    Vertex { position: [-0.5, -0.5, -0.5], uv: [0.0, 1.0]}, // 12
+    Vertex { position: [-0.5, -0.5, 0.5], uv: [1.0, 1.0]},
+    Vertex { position: [-0.5, 0.5, 0.5], uv: [1.0, 0.0]},
+    Vertex { position: [-0.5, 0.5, -0.5], uv: [0.0, 0.0]}, // 15
+
+    // top (+y)
+    Vertex { position: [-0.5, 0.5, 0.5], uv: [0.0, 1.0]}, // 16
+    Vertex { position: [0.5, 0.5, 0.5], uv: [1.0, 1.0]},
+    Vertex { position: [0.5, 0.5, -0.5], uv: [1.0, 0.0]},
+    Vertex { position: [-0.5, 0.5, -0.5], uv: [0.0, 0.0]}, // 19
+
+    // bottom (-y)
+    Vertex { position: [-0.5, -0.5, -0.5], uv: [0.0, 1.0]}, // 20
+    Vertex { position: [0.5, -0.5, -0.5], uv: [1.0, 1.0]},
+    Vertex { position: [0.5, -0.5, 0.5], uv: [1.0, 0.0]},
+    Vertex { position: [-0.5, -0.5, 0.5], uv: [0.0, 0.0]} // 23
 ];
 
-pub struct Instance {
-    position: Vector3<f32>,
-    color: Vector4<f32>,
+// Marks a palette entry that does not sample the texture atlas
+pub const NO_TILE: u32 = u32::MAX;
 
-    pub raw: InstanceRaw
+// A voxel as drawn: a cell inside a chunk, its level of detail and its palette colour
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instance {
+    pub position: Vector3<u32>, // relative to the chunk origin, in voxels
+    pub lod: u32, // the cube is 2^lod voxels wide
+    pub palette_index: u8,
+    pub faces: u8, // one bit per visible face, in the order of the vertices above
 }
 
+// Packed instance, decoded in the vertex shader:
+// data[0] = x (10 bits) | y (10 bits) | z (10 bits) | lod (2 bits), data[1] = palette index (8 bits) | faces (6 bits)
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
 pub struct InstanceRaw {
-    pub(crate) color: [f32; 4],
-    pub(crate) position: [f32; 3],
+    pub(crate) data: [u32; 2],
 }
 
 impl Instance {
-    pub fn new(position: Vector3<f32>, color: Vector4<f32>) -> Self {
+    pub const MAX_COORDINATE: u32 = (1 << 10) - 1;
+    pub const MAX_LOD: u32 = 3;
+    pub const ALL_FACES: u8 = 0b11_1111;
+
+    pub fn new(position: Vector3<u32>, lod: u32, palette_index: u8) -> Self {
+        Self {position, lod, palette_index, faces: Self::ALL_FACES}
+    }
+
+    // Only draw the faces in `faces`, faces covered by a neighbour would otherwise poke through along shared edges
+    pub fn with_faces(self, faces: u8) -> Self {
+        Self {faces, ..self}
+    }
+
+    pub fn encode(&self) -> InstanceRaw {
+        debug_assert!(self.position.x <= Self::MAX_COORDINATE && self.position.y <= Self::MAX_COORDINATE && self.position.z <= Self::MAX_COORDINATE && self.lod <= Self::MAX_LOD);
+        InstanceRaw {data: [self.position.x | self.position.y << 10 | self.position.z << 20 | self.lod << 30, self.palette_index as u32 | (self.faces as u32) << 8]}
+    }
+
+    pub fn decode(raw: InstanceRaw) -> Self {
+        let [data, index] = raw.data;
         Self {
-            position,
-            color,
-            raw: InstanceRaw {
-                color: color.into(),
-                position: position.into()
-            }
+            position: Vector3::new(data & Self::MAX_COORDINATE, (data >> 10) & Self::MAX_COORDINATE, (data >> 20) & Self::MAX_COORDINATE),
+            lod: data >> 30,
+            palette_index: (index & 0xff) as u8,
+            faces: ((index >> 8) as u8) & Self::ALL_FACES
         }
     }
 
@@ -69,16 +128,51 @@ impl Instance {
             attributes: &[
                 wgpu::VertexAttribute {
                     offset: 0,
-                    shader_location: 1, // color
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x3,
+                    shader_location: 2, // packed position, lod and palette index
+                    format: wgpu::VertexFormat::Uint32x2,
                 }
             ],
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+    use crate::chunk::CHUNK_SIZE;
+    use crate::voxel::{Instance, InstanceRaw};
+
+    #[test]
+    fn test_instance_size() {
+        assert_eq!(std::mem::size_of::<InstanceRaw>(), 8);
+    }
+
+    #[test]
+    fn test_round_trip_every_chunk_coordinate() {
+        let size: u32 = CHUNK_SIZE as u32;
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    for lod in 0..=Instance::MAX_LOD {
+                        for palette_index in 0..=255 {
+                            let instance: Instance = Instance::new(Vector3::new(x, y, z), lod, palette_index).with_faces(palette_index & Instance::ALL_FACES);
+                            assert_eq!(Instance::decode(instance.encode()), instance);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_full_coordinate_range() {
+        // every 10 bit value on every axis, with the other fields set so neighbouring bits would show up
+        for value in 0..=Instance::MAX_COORDINATE {
+            let other: u32 = Instance::MAX_COORDINATE - value;
+            for position in [Vector3::new(value, other, other), Vector3::new(other, value, other), Vector3::new(other, other, value)] {
+                let instance: Instance = Instance::new(position, Instance::MAX_LOD, 255);
+                assert_eq!(Instance::decode(instance.encode()), instance);
+            }
+        }
+    }
+}